@@ -1,5 +1,5 @@
 use crate::config::connection::{ConnectionConfig, InterfaceConfig, RuntimeConfig};
-use crate::network::{RouteState, add_route};
+use crate::network::{PushedNetwork, RouteState, add_route};
 use crate::success_err;
 use clap::Args;
 use holynet_sdk::gateway::network::tun::TunNetwork;
@@ -12,10 +12,10 @@ use holynet_sdk::runtime::state::RuntimeState;
 use ipnetwork::IpNetwork;
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::process;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use std::{process, thread};
 use tokio::sync::watch;
 use tracing::{debug, error, info};
 
@@ -117,15 +117,7 @@ impl ConnectCmd {
             }
         };
 
-        let tun_name = match tun.name() {
-            Ok(n) => n,
-            Err(e) => {
-                success_err!("get tun name: {}", e);
-                process::exit(1);
-            }
-        };
-
-        let routes = match RouteState::new(server_addr.ip(), tun_name).build() {
+        let routes = match RouteState::new(server_addr.ip()).build() {
             Ok(r) => Arc::new(r),
             Err(e) => {
                 success_err!("setup routes: {}", e);
@@ -152,7 +144,10 @@ impl ConnectCmd {
         let client = match ClientBuilder::new(transport, tun)
             .alg(config.general.alg)
             .keepalive(runtime.keepalive.map(Duration::from_secs))
+            .dead_peer_after(runtime.dead_peer_after)
             .handshake_timeout(Duration::from_millis(runtime.handshake_timeout))
+            .backoff(runtime.reconnect.backoff())
+            .rekey_after_time(runtime.rekey_after.map(Duration::from_secs))
             .cred(cred)
             .encrypt_workers(crate::config::resolve_pool_workers(runtime.encrypt_workers))
             .decrypt_workers(crate::config::resolve_pool_workers(runtime.decrypt_workers))
@@ -165,69 +160,125 @@ impl ConnectCmd {
             }
         };
 
+        let pushed = Arc::new(Mutex::new(None));
         let state_rx = client.subscribe();
-        tokio::spawn(tun_service(state_rx, tun_arc));
+        tokio::spawn(tun_service(state_rx, tun_arc, pushed.clone()));
 
-        let routes_ctrlc = routes.clone();
+        let (ctrlc_tx, mut ctrlc_rx) = tokio::sync::mpsc::unbounded_channel();
         ctrlc::set_handler(move || {
-            println!("Ctrl-C received, stopping...");
-            routes_ctrlc.restore();
-            thread::sleep(Duration::from_secs(1));
-            process::exit(0);
+            let _ = ctrlc_tx.send(());
         })
         .expect("error setting Ctrl-C handler");
-
-        match client.run().await {
-            Ok(_) => unreachable!(),
-            Err(RuntimeError::StopSignal) => info!("runtime stopped"),
-            Err(e) => {
-                routes.restore();
-                success_err!("{}", e);
+        let handle = client.handle();
+        tokio::spawn(async move {
+            if ctrlc_rx.recv().await.is_some() {
+                println!("Ctrl-C received, stopping...");
+                handle.stop().await;
+            }
+            if ctrlc_rx.recv().await.is_some() {
+                process::exit(1);
             }
+        });
+
+        let Err(e) = client.run().await;
+        undo_pushed(&pushed);
+        routes.restore();
+        match e {
+            RuntimeError::StopSignal => info!("runtime stopped"),
+            ref e => success_err!("{}", e),
         }
+        process::exit(exit_code(&e));
     }
 }
 
-async fn tun_service(mut state_rx: watch::Receiver<RuntimeState>, tun: Arc<TunNetwork>) {
+/// Exit code for the error that ended the client, as listed in the README.
+fn exit_code(err: &RuntimeError) -> i32 {
+    match err {
+        RuntimeError::StopSignal => 0,
+        RuntimeError::Timeout(_) | RuntimeError::Transport(_) => 2,
+        RuntimeError::UnknownServerKey => 3,
+        RuntimeError::Rejected(_) | RuntimeError::Disconnected(_) => 4,
+        RuntimeError::Tun(_) => 5,
+        RuntimeError::Crypto(_) | RuntimeError::Protocol(_) | RuntimeError::Unexpected(_) => 1,
+    }
+}
+
+/// Settings pushed by the server for the current session, if any.
+type Pushed = Arc<Mutex<Option<PushedNetwork>>>;
+
+fn undo_pushed(pushed: &Pushed) {
+    if let Some(previous) = pushed.lock().expect("pushed lock").take() {
+        previous.undo();
+    }
+}
+
+async fn tun_service(
+    mut state_rx: watch::Receiver<RuntimeState>,
+    tun: Arc<TunNetwork>,
+    pushed: Pushed,
+) {
     while state_rx.changed().await.is_ok() {
         let state = state_rx.borrow().clone();
         match state {
             RuntimeState::Connected((payload, _)) => {
-                configure_tun(&tun, &payload).await;
+                undo_pushed(&pushed);
+                let applied = configure_tun(&tun, &payload).await;
+                *pushed.lock().expect("pushed lock") = applied;
+            }
+            RuntimeState::Connecting | RuntimeState::Disconnected(_) | RuntimeState::Paused => {
+                undo_pushed(&pushed)
             }
-            RuntimeState::Error(_) => break,
-            _ => {}
+            RuntimeState::Error(_) => {
+                undo_pushed(&pushed);
+                break;
+            }
+            RuntimeState::Listening => {}
         }
     }
 }
 
-async fn configure_tun(tun: &TunNetwork, payload: &HandshakeResponderPayload) {
-    let prefix = match payload.ipaddr {
-        IpAddr::V4(_) => 32,
-        IpAddr::V6(_) => 128,
+/// Assign the session addresses and apply the server-pushed settings. Without
+/// pushed routes everything is sent through the tunnel.
+async fn configure_tun(
+    tun: &TunNetwork,
+    payload: &HandshakeResponderPayload,
+) -> Option<PushedNetwork> {
+    let tun_name = match tun.name() {
+        Ok(n) => n,
+        Err(e) => {
+            error!("get tun name: {}", e);
+            return None;
+        }
     };
-    if let Err(e) = tun.configure_ip(payload.ipaddr, prefix) {
-        error!("configure tun ip {}: {}", payload.ipaddr, e);
-        return;
+    let network = &payload.network;
+    if let Some(mtu) = network.mtu
+        && let Err(e) = tun.set_mtu(mtu)
+    {
+        error!("set tun mtu {}: {}", mtu, e);
     }
-    if payload.ipaddr.is_ipv4() {
-        let tun_name = match tun.name() {
-            Ok(n) => n,
-            Err(e) => {
-                error!("get tun name: {}", e);
-                return;
-            }
+    let addrs = std::iter::once(payload.ipaddr).chain(payload.ipv6.map(IpAddr::V6));
+    for addr in addrs {
+        let (prefix, routes) = match addr {
+            IpAddr::V4(_) => (32, ["0.0.0.0/1", "128.0.0.0/1"]),
+            IpAddr::V6(_) => (128, ["::/1", "8000::/1"]),
         };
-        for prefix in ["0.0.0.0/1", "128.0.0.0/1"] {
-            if let Err(e) = add_route(
-                &IpNetwork::from_str(prefix).unwrap(),
-                None,
-                &tun_name,
-                Some(1),
-            ) {
-                error!("add route {}: {}", prefix, e);
+        if let Err(e) = tun.configure_ip(addr, prefix) {
+            error!("configure tun ip {}: {}", addr, e);
+            continue;
+        }
+        if network.routes.is_empty() {
+            for route in routes {
+                if let Err(e) = add_route(
+                    &IpNetwork::from_str(route).unwrap(),
+                    None,
+                    &tun_name,
+                    Some(1),
+                ) {
+                    error!("add route {}: {}", route, e);
+                }
             }
         }
+        debug!("tun configured with ip {}", addr);
     }
-    debug!("tun configured with ip {}", payload.ipaddr);
+    Some(PushedNetwork::apply(&tun_name, network))
 }
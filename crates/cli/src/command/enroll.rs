@@ -0,0 +1,85 @@
+use crate::config::connection::{ConnectionConfig, CredentialsConfig, EnrollToken, GeneralConfig};
+use crate::{success_err, success_ok};
+use clap::Args;
+use holynet_sdk::crypto::{PublicKey, SecretKey};
+use holynet_sdk::protocol::Alg;
+use holynet_sdk::runtime::enroll::enroll;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// Time allowed to reach the server and finish the exchange.
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Args)]
+pub struct EnrollCmd {
+    /// Enrollment token from `server users invite`
+    #[arg()]
+    token: String,
+    /// Where to write the connection config
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+impl EnrollCmd {
+    pub async fn exec(self) {
+        let token = match EnrollToken::from_base64(&self.token) {
+            Ok(t) => t,
+            Err(e) => {
+                success_err!("parse token: {}", e);
+                process::exit(1);
+            }
+        };
+
+        // The private key never leaves this machine.
+        let sk = SecretKey::generate_x25519();
+        let exchange = async {
+            let mut stream = TcpStream::connect((token.host.as_str(), token.port)).await?;
+            let enrolled = enroll(&mut stream, &sk, &token.server_public_key, &token.token).await?;
+            anyhow::Ok(enrolled)
+        };
+        let enrolled = match tokio::time::timeout(TIMEOUT, exchange).await {
+            Ok(Ok(enrolled)) => enrolled,
+            Ok(Err(e)) => {
+                success_err!("enroll at {}:{}: {}", token.host, token.port, e);
+                process::exit(1);
+            }
+            Err(_) => {
+                success_err!("enroll at {}:{}: timed out", token.host, token.port);
+                process::exit(1);
+            }
+        };
+
+        let pk = PublicKey::from_secret(&sk);
+        let connection_config = ConnectionConfig {
+            general: GeneralConfig {
+                host: token.host,
+                port: enrolled.port,
+                // The server takes either; pick the one this CPU runs fastest.
+                alg: Alg::default(),
+            },
+            credentials: CredentialsConfig {
+                private_key: sk,
+                pre_shared_key: enrolled.psk,
+                server_public_key: token.server_public_key,
+            },
+            interface: None,
+            runtime: None,
+        };
+
+        let config_path = self.output.unwrap_or_else(|| {
+            PathBuf::from(format!(
+                "connection-{}.toml",
+                chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S")
+            ))
+        });
+        if let Err(e) = connection_config.save(&config_path) {
+            success_err!("save connection config: {}", e);
+            process::exit(1);
+        }
+
+        success_ok!("PubKey", pk);
+        success_ok!("Saved", "config to {}", config_path.display());
+    }
+}
@@ -1,14 +1,18 @@
 pub mod connect;
+pub mod enroll;
 pub mod server;
 
 use clap::Subcommand;
 use connect::ConnectCmd;
+use enroll::EnrollCmd;
 use server::ServerCmd;
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Connect to a VPN server
     Connect(ConnectCmd),
+    /// Create a key pair and enroll it with an invite token
+    Enroll(EnrollCmd),
     /// Server management
     #[clap(subcommand_required = true)]
     Server(ServerCmd),
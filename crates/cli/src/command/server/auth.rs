@@ -0,0 +1,163 @@
+//! Authenticating handshakes against the user store.
+//!
+//! [`StoreAuthenticator`] reads the user and their traffic from the store on
+//! every handshake, so `users` commands apply to the next handshake without
+//! waiting for a users reload. Only one process or task can open the store at
+//! a time; while a `users` command holds it, the last answer read for the key
+//! stands in.
+
+use crate::config::QuotaPeriod;
+use crate::storage::{Client, Clients, Usage, Usages, database};
+use dashmap::DashMap;
+use holynet_sdk::crypto::PublicKey;
+use holynet_sdk::runtime::server::{ClientAuth, ClientAuthenticator};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+/// What the server lets `client` do, given their `usage` this period.
+pub fn user_auth(client: &Client, usage: Option<&Usage>) -> ClientAuth {
+    let mut auth = client.auth();
+    auth.policy.quota_exceeded = usage.is_some_and(Usage::over_quota);
+    auth
+}
+
+/// Resolves clients from the `clients` and `usage` keyspaces of the store.
+pub struct StoreAuthenticator {
+    storage: PathBuf,
+    period: QuotaPeriod,
+    /// Last answer read for each known key
+    last: DashMap<PublicKey, ClientAuth>,
+    /// Handshake workers take turns opening the store.
+    open: Mutex<()>,
+}
+
+impl StoreAuthenticator {
+    /// Read from the store at `storage`, starting from the `known` answers
+    /// loaded with the users at startup.
+    pub fn new(
+        storage: PathBuf,
+        period: QuotaPeriod,
+        known: impl IntoIterator<Item = (PublicKey, ClientAuth)>,
+    ) -> Self {
+        Self {
+            storage,
+            period,
+            last: known.into_iter().collect(),
+            open: Mutex::new(()),
+        }
+    }
+
+    async fn read(&self, pk: &PublicKey) -> anyhow::Result<Option<ClientAuth>> {
+        let _open = self.open.lock().await;
+        let db = database(&self.storage)?;
+        let Some(client) = Clients::new(db.clone())?.get(pk).await else {
+            return Ok(None);
+        };
+        let usage = Usages::new(db)?.get(pk, self.period.current()).await;
+        Ok(Some(user_auth(&client, Some(&usage))))
+    }
+}
+
+impl ClientAuthenticator for StoreAuthenticator {
+    fn authenticate<'a>(
+        &'a self,
+        pk: &'a PublicKey,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Option<ClientAuth>>> + Send + 'a>> {
+        Box::pin(async move {
+            match self.read(pk).await {
+                Ok(Some(auth)) => {
+                    self.last.insert(pk.clone(), auth.clone());
+                    Ok(Some(auth))
+                }
+                Ok(None) => {
+                    self.last.remove(pk);
+                    Ok(None)
+                }
+                Err(e) => match self.last.get(pk) {
+                    Some(auth) => {
+                        debug!("read user {}: {}, using the last answer", pk, e);
+                        Ok(Some(auth.clone()))
+                    }
+                    None => Err(e),
+                },
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use holynet_sdk::crypto::SecretKey;
+
+    fn storage(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("holynet-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_reads_changes_at_the_next_handshake() {
+        let path = storage("auth-changes");
+        let auth = StoreAuthenticator::new(path.clone(), QuotaPeriod::Monthly, []);
+        let pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        assert!(auth.authenticate(&pk).await.unwrap().is_none());
+
+        let clients = Clients::new(database(&path).unwrap()).unwrap();
+        let mut client = Client::new(pk.clone(), SecretKey::generate_x25519());
+        clients.save(client.clone()).await;
+        drop(clients);
+        let found = auth.authenticate(&pk).await.unwrap().unwrap();
+        assert_eq!(found.psk.as_bytes(), client.psk.as_bytes());
+        assert!(found.policy.allowed);
+
+        client.enabled = false;
+        let db = database(&path).unwrap();
+        Clients::new(db.clone()).unwrap().save(client).await;
+        let usages = Usages::new(db.clone()).unwrap();
+        let period = QuotaPeriod::Monthly.current();
+        let mut usage = usages.get(&pk, period).await;
+        usage.quota = Some(10);
+        usage.rx_bytes = 10;
+        usages.save(&pk, &usage).await.unwrap();
+        drop((usages, db));
+        let policy = auth.authenticate(&pk).await.unwrap().unwrap().policy;
+        assert!(!policy.allowed);
+        assert!(policy.quota_exceeded);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[tokio::test]
+    async fn test_busy_store_falls_back_to_the_last_answer() {
+        let path = storage("auth-busy");
+        let known = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let psk = SecretKey::generate_x25519();
+        let auth = StoreAuthenticator::new(
+            path.clone(),
+            QuotaPeriod::Monthly,
+            [(known.clone(), ClientAuth::new(psk.clone()))],
+        );
+
+        let lock = database(&path).unwrap();
+        let found = auth.authenticate(&known).await.unwrap().unwrap();
+        assert_eq!(found.psk.as_bytes(), psk.as_bytes());
+        let stranger = PublicKey::from_secret(&SecretKey::generate_x25519());
+        assert!(
+            auth.authenticate(&stranger).await.is_err(),
+            "client retries"
+        );
+        drop(lock);
+
+        assert!(
+            auth.authenticate(&known).await.unwrap().is_none(),
+            "not in the store"
+        );
+        assert!(!auth.last.contains_key(&known));
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
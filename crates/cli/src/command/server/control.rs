@@ -0,0 +1,185 @@
+//! Control socket of a running server.
+//!
+//! `server start` listens on the Unix socket `general.control`; the `server
+//! sessions` commands connect to it. Each connection carries one request and
+//! one response, every message a big-endian `u32` length followed by its
+//! bincode encoding.
+
+use holynet_sdk::crypto::PublicKey;
+use holynet_sdk::protocol::{Alg, DisconnectReason, SessionId};
+use holynet_sdk::runtime::server::ServerHandle;
+use holynet_sdk::runtime::server::session::Session;
+use holynet_sdk::time::sec_since_start;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, error, info};
+
+/// Largest message either side accepts.
+const MAX_MESSAGE: u32 = 16 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub enum Target {
+    Sid(SessionId),
+    Peer(PublicKey),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Request {
+    List,
+    Kick(Target),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub sid: SessionId,
+    pub peer: PublicKey,
+    pub ip: IpAddr,
+    pub ip6: Option<Ipv6Addr>,
+    pub endpoint: SocketAddr,
+    pub alg: Alg,
+    /// Seconds since the handshake
+    pub age: u64,
+    /// Seconds since the last packet
+    pub idle: u64,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+}
+
+impl From<&Session> for SessionInfo {
+    fn from(session: &Session) -> Self {
+        let traffic = &session.traffic;
+        Self {
+            sid: session.id,
+            peer: session.peer_pk.clone(),
+            ip: session.holy_ip,
+            ip6: session.holy_ip6,
+            endpoint: session.sock_addr(),
+            alg: session.enc.clone(),
+            age: session.created_at.elapsed().as_secs(),
+            idle: sec_since_start().saturating_sub(session.last_seen.load(Ordering::Relaxed)),
+            rx_bytes: traffic.rx_bytes.load(Ordering::Relaxed),
+            rx_packets: traffic.rx_packets.load(Ordering::Relaxed),
+            tx_bytes: traffic.tx_bytes.load(Ordering::Relaxed),
+            tx_packets: traffic.tx_packets.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Response {
+    Sessions(Vec<SessionInfo>),
+    /// Number of sessions closed
+    Kicked(usize),
+}
+
+async fn write_message<T: Serialize>(stream: &mut UnixStream, message: &T) -> anyhow::Result<()> {
+    let bytes = bincode::serde::encode_to_vec(message, bincode::config::standard())?;
+    stream.write_u32(bytes.len() as u32).await?;
+    stream.write_all(&bytes).await?;
+    Ok(())
+}
+
+async fn read_message<T: DeserializeOwned>(stream: &mut UnixStream) -> anyhow::Result<T> {
+    let len = stream.read_u32().await?;
+    if len > MAX_MESSAGE {
+        anyhow::bail!("control message too large: {} bytes", len);
+    }
+    let mut bytes = vec![0u8; len as usize];
+    stream.read_exact(&mut bytes).await?;
+    let (message, _) = bincode::serde::decode_from_slice(&bytes, bincode::config::standard())?;
+    Ok(message)
+}
+
+/// Send `request` to the server listening on `path` and wait for its answer.
+pub async fn request(path: &Path, request: &Request) -> anyhow::Result<Response> {
+    let mut stream = UnixStream::connect(path).await.map_err(|e| {
+        anyhow::anyhow!(
+            "connect to {}: {} (is the server running?)",
+            path.display(),
+            e
+        )
+    })?;
+    write_message(&mut stream, request).await?;
+    read_message(&mut stream).await
+}
+
+fn handle_request(handle: &ServerHandle, request: Request) -> Response {
+    match request {
+        Request::List => Response::Sessions(
+            handle
+                .sessions()
+                .iter()
+                .map(|session| SessionInfo::from(&**session))
+                .collect(),
+        ),
+        Request::Kick(Target::Sid(sid)) => {
+            Response::Kicked(handle.disconnect(sid, DisconnectReason::Kicked) as usize)
+        }
+        Request::Kick(Target::Peer(pk)) => {
+            Response::Kicked(handle.disconnect_peer(&pk, DisconnectReason::Kicked))
+        }
+    }
+}
+
+async fn serve_one(mut stream: UnixStream, handle: &ServerHandle) -> anyhow::Result<()> {
+    let request = read_message(&mut stream).await?;
+    write_message(&mut stream, &handle_request(handle, request)).await
+}
+
+/// Removes the socket file when the listener goes away.
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Answer control requests on `path` until the task is aborted. Only the
+/// owner of the server process may connect.
+pub async fn serve(path: PathBuf, handle: ServerHandle) {
+    if UnixStream::connect(&path).await.is_ok() {
+        error!(
+            "control socket {}: another server is listening",
+            path.display()
+        );
+        return;
+    }
+    // Nobody answers, so the socket was left behind by a crashed server and
+    // would make bind fail.
+    let _ = std::fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("control socket {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let _file = SocketFile(path.clone());
+    if let Err(e) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)) {
+        error!("restrict control socket {}: {}", path.display(), e);
+        return;
+    }
+    info!("control socket listening on {}", path.display());
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_one(stream, &handle).await {
+                        debug!("control request: {}", e);
+                    }
+                });
+            }
+            Err(e) => debug!("control accept: {}", e),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Enrollment listener of a running server.
+//!
+//! Clients holding a token from `users invite` connect here with a key pair of
+//! their own and get a fresh PSK back; see `holynet_sdk::runtime::enroll`.
+//! The new user is written to the store, where the running server finds them
+//! at their first handshake, and the token is burned. Expired tokens and keys
+//! already enrolled are refused first, before anything is written. If the
+//! answer does not reach the client, the user is deleted again and the token
+//! restored.
+
+use crate::storage::{Client, Clients, Invite, Invites, Leases, database};
+use holynet_sdk::crypto::{PublicKey, SecretKey};
+use holynet_sdk::runtime::enroll::{Enrolled, accept, token_id};
+use holynet_sdk::runtime::server::ServerHandle;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tracing::{debug, error, info, warn};
+
+/// Time a client gets to finish the exchange.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Enrollments answered at once. Each costs Diffie-Hellman work before the
+/// token is checked, so further connections wait in the accept backlog.
+const MAX_ENROLLMENTS: usize = 8;
+
+/// Attempts to undo an enrollment while the store is busy, one per second.
+const UNDO_ATTEMPTS: usize = 5;
+
+/// What the listener needs to answer enrollments.
+pub struct Enrollment {
+    pub storage: PathBuf,
+    pub secret_key: SecretKey,
+    /// Port of the VPN listener handed to clients
+    pub port: u16,
+}
+
+/// Answer enrollments on `addr` until the task is aborted.
+pub async fn serve(addr: SocketAddr, enrollment: Enrollment, handle: ServerHandle) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("enrollment listener on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("accepting enrollments on {}", addr);
+    let enrollment = Arc::new(enrollment);
+    let slots = Arc::new(Semaphore::new(MAX_ENROLLMENTS));
+    loop {
+        let Ok(slot) = slots.clone().acquire_owned().await else {
+            return;
+        };
+        match listener.accept().await {
+            Ok((mut stream, peer)) => {
+                let enrollment = enrollment.clone();
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    let _slot = slot;
+                    let mut redeemed = None;
+                    let exchange = accept(&mut stream, &enrollment.secret_key, |pk, token| {
+                        redeem(&enrollment, &handle, pk, token, &mut redeemed)
+                    });
+                    match tokio::time::timeout(TIMEOUT, exchange).await {
+                        Ok(Ok(pk)) => {
+                            info!("[{}] client {} enrolled", peer, pk);
+                            return;
+                        }
+                        Ok(Err(e)) => warn!("[{}] enrollment: {}", peer, e),
+                        Err(_) => debug!("[{}] enrollment timed out", peer),
+                    }
+                    if let Some(redeemed) = redeemed {
+                        undo(&enrollment.storage, &handle, redeemed).await;
+                    }
+                });
+            }
+            Err(e) => debug!("enrollment accept: {}", e),
+        }
+    }
+}
+
+/// What an enrollment wrote to the store, to undo if the client never learns
+/// its PSK.
+struct Redeemed {
+    id: [u8; 32],
+    invite: Invite,
+    pk: PublicKey,
+}
+
+/// Check `token`, then burn it and register `pk` under a fresh PSK. Once
+/// anything is written, `redeemed` records it.
+async fn redeem(
+    enrollment: &Enrollment,
+    handle: &ServerHandle,
+    pk: PublicKey,
+    token: SecretKey,
+    redeemed: &mut Option<Redeemed>,
+) -> Result<Enrolled, String> {
+    // Usually a `users` command holding the store; the client may retry.
+    let db = database(&enrollment.storage).map_err(|e| {
+        debug!("open store: {}", e);
+        "server busy, try again".to_string()
+    })?;
+    let store = |e: anyhow::Error| {
+        error!("enrollment store: {}", e);
+        "server error".to_string()
+    };
+
+    // Nobody else can redeem the token meanwhile: the store stays locked
+    // until `db` is dropped.
+    let invites = Invites::new(db.clone()).map_err(store)?;
+    let id = token_id(&token);
+    let invite = invites
+        .get(id)
+        .await
+        .map_err(store)?
+        .ok_or("unknown or used token")?;
+    if invite.expires_at < chrono::Utc::now() {
+        return Err("token expired".into());
+    }
+    let clients = Clients::new(db.clone()).map_err(store)?;
+    if clients.get(&pk).await.is_some() {
+        return Err("key already enrolled".into());
+    }
+    invites.remove(id).await.map_err(store)?;
+    let address = invite.address;
+    *redeemed = Some(Redeemed {
+        id,
+        invite,
+        pk: pk.clone(),
+    });
+
+    let psk = SecretKey::generate_x25519();
+    clients.save(Client::new(pk.clone(), psk.clone())).await;
+    if let Some(address) = address {
+        Leases::new(db)
+            .map_err(store)?
+            .pin(&pk, Some(address))
+            .await
+            .map_err(store)?;
+        if !handle.pin_address(&pk, Some(address)) {
+            warn!(
+                "cannot pin {} to {}: outside the subnet or taken",
+                pk, address
+            );
+        }
+    }
+    Ok(Enrolled {
+        psk,
+        port: enrollment.port,
+    })
+}
+
+/// Delete the user `redeemed` added and restore their token, retrying while
+/// the store is busy.
+async fn undo(storage: &Path, handle: &ServerHandle, redeemed: Redeemed) {
+    let pk = &redeemed.pk;
+    for attempt in 1..=UNDO_ATTEMPTS {
+        match restore(storage, &redeemed).await {
+            Ok(()) => {
+                handle.remove_client(pk);
+                info!("enrollment of {} undone, token restored", pk);
+                return;
+            }
+            Err(e) if attempt == UNDO_ATTEMPTS => {
+                error!("undo enrollment of {}: {}", pk, e);
+            }
+            Err(e) => {
+                debug!("undo enrollment of {}: {}", pk, e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+async fn restore(storage: &Path, redeemed: &Redeemed) -> anyhow::Result<()> {
+    let db = database(storage)?;
+    Clients::new(db.clone())?.delete(&redeemed.pk).await?;
+    if redeemed.invite.address.is_some() {
+        Leases::new(db.clone())?.pin(&redeemed.pk, None).await?;
+    }
+    Invites::new(db)?
+        .save(redeemed.id, redeemed.invite.clone())
+        .await
+}
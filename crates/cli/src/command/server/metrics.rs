@@ -0,0 +1,81 @@
+use holynet_sdk::runtime::server::ServerHandle;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tracing::{debug, error, info, warn};
+
+/// Time a scraper gets to send its request and read the response.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests answered at once; further connections wait in the accept backlog.
+const MAX_CONNECTIONS: usize = 8;
+
+/// Serve `GET /metrics` in the Prometheus text format until the task is
+/// aborted, with per-session series if `per_session`. Anything else gets a 404.
+pub async fn serve(addr: SocketAddr, handle: ServerHandle, per_session: bool) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("metrics listener on {}: {}", addr, e);
+            return;
+        }
+    };
+    if !addr.ip().is_loopback() {
+        warn!("metrics on {} are reachable without authentication", addr);
+    }
+    info!("serving metrics on http://{}/metrics", addr);
+    let slots = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let Ok(slot) = slots.clone().acquire_owned().await else {
+            return;
+        };
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    let _slot = slot;
+                    let request = respond(stream, &handle, per_session);
+                    match tokio::time::timeout(TIMEOUT, request).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => debug!("[{}] metrics request: {}", peer, e),
+                        Err(_) => debug!("[{}] metrics request timed out", peer),
+                    }
+                });
+            }
+            Err(e) => debug!("metrics accept: {}", e),
+        }
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    handle: &ServerHandle,
+    per_session: bool,
+) -> anyhow::Result<()> {
+    // Scrapers send a single small GET; the request line is all we look at.
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("GET "))
+        .and_then(|rest| rest.split_whitespace().next());
+
+    let (status, body) = match path {
+        Some("/metrics") => ("200 OK", handle.prometheus(per_session)),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
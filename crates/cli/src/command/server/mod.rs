@@ -1,6 +1,13 @@
+pub mod auth;
+pub mod control;
+pub mod enroll;
+pub mod metrics;
+pub mod sessions;
 pub mod start;
+pub mod usage;
 pub mod users;
 
+use crate::command::server::sessions::SessionsCmd;
 use crate::command::server::start::StartCmd;
 use crate::command::server::users::UsersCmd;
 use clap::{Args, Subcommand};
@@ -24,4 +31,7 @@ pub enum ServerCommands {
     /// Manage users
     #[clap(subcommand)]
     Users(UsersCmd),
+    /// Inspect and disconnect live sessions of the running server
+    #[clap(subcommand)]
+    Sessions(SessionsCmd),
 }
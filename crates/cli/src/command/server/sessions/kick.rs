@@ -0,0 +1,38 @@
+use crate::command::server::control::{self, Request, Response, Target};
+use crate::config::Config;
+use crate::success_ok;
+use anyhow::anyhow;
+use clap::Args;
+use holynet_sdk::crypto::PublicKey;
+use holynet_sdk::protocol::SessionId;
+
+#[derive(Debug, Args)]
+pub struct KickCmd {
+    /// Session id, or a public key (base64) to close all of its sessions
+    #[arg()]
+    target: String,
+}
+
+impl KickCmd {
+    pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        let target = match self.target.parse::<SessionId>() {
+            Ok(sid) => Target::Sid(sid),
+            Err(_) => Target::Peer(
+                PublicKey::try_from(self.target.as_str())
+                    .map_err(|e| anyhow!("parse public key: {}", e))?,
+            ),
+        };
+        let Response::Kicked(count) =
+            control::request(&config.general.control, &Request::Kick(target)).await?
+        else {
+            return Err(anyhow!("unexpected response from server"));
+        };
+        match count {
+            0 => Err(anyhow!("no live session matches {}", self.target)),
+            _ => {
+                success_ok!("Kicked", "{} session(s)", count);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::command::server::control::{self, Request, Response};
+use crate::config::Config;
+use crate::style::format_bytes;
+use crate::success_ok;
+use anyhow::anyhow;
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct ListCmd;
+
+impl ListCmd {
+    pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        let Response::Sessions(mut sessions) =
+            control::request(&config.general.control, &Request::List).await?
+        else {
+            return Err(anyhow!("unexpected response from server"));
+        };
+        if sessions.is_empty() {
+            success_ok!("Sessions", "none");
+            return Ok(());
+        }
+        sessions.sort_by_key(|s| s.sid);
+
+        println!("SID\tPEER\tIP\tENDPOINT\tALG\tAGE\tIDLE\tRX\tTX");
+        for s in sessions {
+            let ip = match s.ip6 {
+                Some(ip6) => format!("{},{}", s.ip, ip6),
+                None => s.ip.to_string(),
+            };
+            println!(
+                "{}\t{:.8}\t{}\t{}\t{:?}\t{}s\t{}s\t{} ({} pkts)\t{} ({} pkts)",
+                s.sid,
+                s.peer.to_string(),
+                ip,
+                s.endpoint,
+                s.alg,
+                s.age,
+                s.idle,
+                format_bytes(s.rx_bytes),
+                s.rx_packets,
+                format_bytes(s.tx_bytes),
+                s.tx_packets,
+            );
+        }
+        Ok(())
+    }
+}
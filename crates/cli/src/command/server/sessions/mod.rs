@@ -0,0 +1,28 @@
+mod kick;
+mod list;
+
+use crate::config::Config;
+use crate::success_err;
+use clap::Subcommand;
+use kick::KickCmd;
+use list::ListCmd;
+
+#[derive(Debug, Subcommand)]
+pub enum SessionsCmd {
+    /// List live sessions of the running server
+    List(ListCmd),
+    /// Disconnect a session by sid, or every session of a public key
+    Kick(KickCmd),
+}
+
+impl SessionsCmd {
+    pub async fn exec(self, config: Config) {
+        if let Err(e) = match self {
+            SessionsCmd::List(cmd) => cmd.exec(config).await,
+            SessionsCmd::Kick(cmd) => cmd.exec(config).await,
+        } {
+            success_err!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
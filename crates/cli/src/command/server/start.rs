@@ -1,16 +1,28 @@
-use crate::config::Config;
-use crate::network::set_ipv4_forwarding;
-use crate::storage::{Clients, database};
+use crate::command::server::auth::{StoreAuthenticator, user_auth};
+use crate::command::server::enroll::{self, Enrollment};
+use crate::command::server::usage::Meter;
+use crate::command::server::{control, metrics};
+use crate::config::{Config, QuotaPeriod};
+use crate::network::{set_ipv4_forwarding, set_ipv6_forwarding};
+use crate::storage::{Client, Clients, LastConnected, Leases, Usage, Usages, database};
 use crate::success_err;
 use crate::success_warn;
+use chrono::{DateTime, Utc};
 use clap::Args;
+use holynet_sdk::crypto::PublicKey;
 use holynet_sdk::gateway::network::tun::TunNetwork;
 use holynet_sdk::gateway::transport::udp::UdpTransport;
-use holynet_sdk::runtime::server::ServerBuilder;
-use std::net::SocketAddr;
-use std::time::Duration;
-use std::{process, thread};
-use tracing::error;
+use holynet_sdk::protocol::DisconnectReason;
+use holynet_sdk::runtime::server::session::{Lease, SessionEvent};
+use holynet_sdk::runtime::server::{RateLimit, ServerBuilder, ServerHandle};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+use tracing::{debug, error, info, warn};
 
 #[derive(Debug, Args)]
 pub struct StartCmd {
@@ -51,26 +63,29 @@ impl StartCmd {
             config.interface.offload = false;
         }
 
-        let clients = match database(&config.general.storage) {
-            Ok(db) => match Clients::new(db) {
-                Ok(store) => store,
-                Err(e) => {
-                    success_err!("failed to create client storage: {}", e);
-                    process::exit(1);
-                }
-            },
+        let runtime = config.runtime.unwrap_or_default();
+        let period = runtime.quota_period.current();
+        let (users, usage, leases) = match load_store(&config.general.storage, period).await {
+            Ok(store) => store,
             Err(e) => {
                 success_err!("load storage: {}", e);
                 process::exit(1);
             }
         };
+        if runtime.users_reload == 0 && usage.values().any(|usage| usage.quota.is_some()) {
+            // Traffic is only counted by the users reload.
+            success_err!("user quotas need runtime.users_reload above 0");
+            process::exit(1);
+        }
 
-        let known_clients: Vec<_> = clients
-            .get_all()
-            .await
-            .into_iter()
-            .map(|cl| (cl.peer_pk, cl.psk))
-            .collect();
+        let authenticator = StoreAuthenticator::new(
+            config.general.storage.clone(),
+            runtime.quota_period,
+            users
+                .clients
+                .iter()
+                .map(|(pk, client)| (pk.clone(), user_auth(client, usage.get(pk)))),
+        );
 
         let addr: SocketAddr =
             match format!("{}:{}", config.general.host, config.general.port).parse() {
@@ -81,7 +96,6 @@ impl StartCmd {
                 }
             };
 
-        let runtime = config.runtime.unwrap_or_default();
         let workers = crate::config::resolve_pool_workers(runtime.workers);
 
         let transports =
@@ -114,6 +128,17 @@ impl StartCmd {
             process::exit(1);
         }
 
+        if let Some(address6) = config.interface.address6 {
+            if let Err(e) = network.configure_ip(address6.into(), config.interface.prefix6) {
+                success_err!("setup ipv6 address: {}", e);
+                process::exit(1);
+            }
+            if let Err(e) = set_ipv6_forwarding(true) {
+                success_err!("enable ipv6 forwarding: {}", e);
+                process::exit(1);
+            }
+        }
+
         let session_timeout = runtime
             .session
             .as_ref()
@@ -124,14 +149,42 @@ impl StartCmd {
             .map(|s| Duration::from_secs(s.cleanup_interval as u64))
             .unwrap_or(Duration::from_secs(60));
 
-        let builder = ServerBuilder::new(transports, network)
-            .secret_key(config.general.secret_key)
-            .known_clients(known_clients)
+        let mut builder = ServerBuilder::new(transports, network)
+            .secret_key(config.general.secret_key.clone())
+            .authenticator(authenticator)
             .ip(config.interface.address, config.interface.prefix)
             .session_timeout(session_timeout)
             .session_cleanup_interval(cleanup_interval)
             .handshake_buf(runtime.handshake_buf)
-            .decrypt_workers(crate::config::resolve_pool_workers(runtime.decrypt_workers));
+            .handshake_workers(crate::config::resolve_pool_workers(
+                runtime.handshake_workers,
+            ))
+            .decrypt_workers(crate::config::resolve_pool_workers(runtime.decrypt_workers))
+            .rate_limit(RateLimit {
+                upload: runtime.upload_limit.0,
+                download: runtime.download_limit.0,
+            });
+        for (pk, ip) in &users.pinned {
+            builder = builder.pin_address(pk.clone(), *ip);
+        }
+        if runtime.lease_grace > 0 {
+            builder = builder
+                .sticky_leases(Duration::from_secs(runtime.lease_grace))
+                .leases(leases);
+        }
+        if runtime.max_devices > 0 {
+            builder = builder.device_limit(runtime.max_devices, runtime.device_policy);
+        }
+        if let Some(address6) = config.interface.address6 {
+            builder = builder.ipv6(address6, config.interface.prefix6);
+        }
+        if let Some(push) = &config.push {
+            builder = builder.network_config(push.settings.resolve(&Default::default()));
+            for (pk, settings) in &push.users {
+                builder =
+                    builder.client_network_config(pk.clone(), settings.resolve(&push.settings));
+            }
+        }
 
         let server = match builder.build() {
             Ok(s) => s,
@@ -141,20 +194,251 @@ impl StartCmd {
             }
         };
 
+        let handle = server.handle();
+        let rt = tokio::runtime::Handle::current();
         ctrlc::set_handler(move || {
             println!("Ctrl-C received, stopping...");
-            let _ = set_ipv4_forwarding(false);
-            thread::sleep(Duration::from_secs(1));
-            process::exit(0);
+            let handle = handle.clone();
+            rt.spawn(async move { handle.shutdown().await });
         })
         .expect("error setting Ctrl-C handler");
 
-        match server.run().await {
-            Ok(_) => unreachable!(),
+        let users_sync = (runtime.users_reload > 0).then(|| {
+            let (stop, stopped) = oneshot::channel();
+            let task = tokio::spawn(sync_users(
+                server.handle(),
+                config.general.storage.clone(),
+                Duration::from_secs(runtime.users_reload),
+                runtime.quota_period,
+                users,
+                usage,
+                stopped,
+            ));
+            (stop, task)
+        });
+
+        let metrics = runtime.metrics.map(|addr| {
+            tokio::spawn(metrics::serve(
+                addr,
+                server.handle(),
+                runtime.metrics_per_session,
+            ))
+        });
+        let enroll = runtime.enroll.map(|addr| {
+            let enrollment = Enrollment {
+                storage: config.general.storage.clone(),
+                secret_key: config.general.secret_key.clone(),
+                port: config.general.port,
+            };
+            tokio::spawn(enroll::serve(addr, enrollment, server.handle()))
+        });
+        let control = tokio::spawn(control::serve(
+            config.general.control.clone(),
+            server.handle(),
+        ));
+
+        let leases_handle = server.handle();
+        let result = server.run().await;
+        if let Some((stop, task)) = users_sync {
+            // Let it write the last traffic counts.
+            let _ = stop.send(());
+            let _ = task.await;
+        }
+        if let Some(task) = metrics {
+            task.abort();
+        }
+        if let Some(task) = enroll {
+            task.abort();
+        }
+        control.abort();
+        if let Err(e) = save_leases(&config.general.storage, &leases_handle).await {
+            error!("save leases: {}", e);
+        }
+        let _ = set_ipv4_forwarding(false);
+        if config.interface.address6.is_some() {
+            let _ = set_ipv6_forwarding(false);
+        }
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+    }
+}
+
+/// Users and their pinned tunnel addresses.
+struct Users {
+    clients: HashMap<PublicKey, Client>,
+    pinned: HashMap<PublicKey, IpAddr>,
+}
+
+/// Read every user from the store. The database is closed again before
+/// returning, so `users` commands can open it while the server runs.
+async fn load_users(path: &Path) -> anyhow::Result<Users> {
+    let db = database(path)?;
+    let clients = Clients::new(db.clone())?;
+    let leases = Leases::new(db)?;
+    Ok(Users {
+        clients: clients
+            .get_all()
+            .await
+            .into_iter()
+            .map(|cl| (cl.peer_pk.clone(), cl))
+            .collect(),
+        pinned: leases.pinned().await,
+    })
+}
+
+/// Users, their usage in `period` and the sticky leases saved by the last
+/// run. Records written by older versions are upgraded first.
+async fn load_store(
+    path: &Path,
+    period: DateTime<Utc>,
+) -> anyhow::Result<(Users, HashMap<PublicKey, Usage>, Vec<(PublicKey, Lease)>)> {
+    let migrated = Clients::new(database(path)?)?.migrate().await?;
+    if migrated > 0 {
+        info!("upgraded {} user record(s)", migrated);
+    }
+    let users = load_users(path).await?;
+    let usage = Usages::new(database(path)?)?.get_all(period).await;
+    let leases = Leases::new(database(path)?)?.sticky().await;
+    Ok((users, usage, leases))
+}
+
+/// Write the server's sticky leases to the store.
+async fn save_leases(path: &Path, handle: &ServerHandle) -> anyhow::Result<()> {
+    Leases::new(database(path)?)?
+        .save_sticky(handle.leases())
+        .await
+}
+
+/// Record when and from where users last connected.
+async fn save_last_connected(
+    path: &Path,
+    seen: &mut HashMap<PublicKey, LastConnected>,
+) -> anyhow::Result<()> {
+    let clients = Clients::new(database(path)?)?;
+    for (pk, last) in seen.drain() {
+        // Re-read, so changes made by `users` commands since the reload stay.
+        if let Some(mut client) = clients.get(&pk).await {
+            client.last_connected = Some(last);
+            clients.save(client).await;
+        }
+    }
+    Ok(())
+}
+
+/// Re-read the user store every `interval` and close the sessions of removed,
+/// re-keyed, disabled and expired users, and apply changed rates and pins to
+/// the running server; handshakes read the store themselves. On the same
+/// schedule, sessions' traffic is added to the `usage`
+/// keyspace and users over their quota are refused and disconnected, and
+/// sticky leases and the last connection of each user are saved. Returns
+/// after a last traffic flush once `stop` fires.
+#[allow(clippy::too_many_arguments)]
+async fn sync_users(
+    handle: ServerHandle,
+    storage: PathBuf,
+    interval: Duration,
+    period: QuotaPeriod,
+    mut known: Users,
+    mut usage: HashMap<PublicKey, Usage>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let mut events = handle.events();
+    let mut seen = HashMap::new();
+    let mut meter = Meter::default();
+    let mut timer = tokio::time::interval(interval);
+    timer.tick().await;
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                match event {
+                    Ok(SessionEvent::Created { sid, peer, endpoint, .. }) => {
+                        if let Some(session) = handle.session(sid) {
+                            meter.track(session);
+                        }
+                        let at = chrono::Utc::now();
+                        seen.insert(peer, LastConnected { at, endpoint });
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return,
+                }
+                continue;
+            }
+            _ = &mut stop => {
+                meter.collect(&handle.sessions());
+                if let Err(e) = meter.flush(&storage, period.current()).await {
+                    error!("save traffic: {}", e);
+                }
+                return;
+            }
+            _ = timer.tick() => {}
+        }
+
+        meter.collect(&handle.sessions());
+        match meter.flush(&storage, period.current()).await {
+            Ok(current) => usage = current,
+            // Counts stay pending and the last known usage applies.
+            Err(e) => debug!("save traffic: {}", e),
+        }
+
+        let current = match load_users(&storage).await {
+            Ok(users) => users,
             Err(e) => {
-                let _ = set_ipv4_forwarding(false);
-                error!("{}", e);
+                // Usually a `users` command holding the lock; retry next tick.
+                debug!("reload users: {}", e);
+                continue;
+            }
+        };
+
+        for pk in known.clients.keys() {
+            if !current.clients.contains_key(pk) {
+                handle.remove_client(pk);
+            }
+        }
+        let now = SystemTime::now();
+        for (pk, client) in &current.clients {
+            let auth = user_auth(client, usage.get(pk));
+            let refused = auth.policy.denies(now);
+            let over_quota = auth.policy.quota_exceeded;
+            let rekeyed = known
+                .clients
+                .get(pk)
+                .is_some_and(|old| old.psk.as_slice() != client.psk.as_slice());
+            handle.set_rate_limit(pk, &auth.policy);
+            if rekeyed {
+                // Sessions keyed with the old PSK must not outlive it. The
+                // user stays, and so do their pin and lease.
+                handle.disconnect_peer(pk, DisconnectReason::Revoked);
+            } else if refused {
+                handle.disconnect_peer(pk, DisconnectReason::Revoked);
+            } else if over_quota {
+                let closed = handle.disconnect_peer(pk, DisconnectReason::QuotaExceeded);
+                if closed > 0 {
+                    info!(
+                        "{} over quota, {} session(s) closed",
+                        client.label(),
+                        closed
+                    );
+                }
             }
         }
+        for pk in known.pinned.keys() {
+            if !current.pinned.contains_key(pk) {
+                handle.pin_address(pk, None);
+            }
+        }
+        for (pk, ip) in &current.pinned {
+            if known.pinned.get(pk) != Some(ip) && !handle.pin_address(pk, Some(*ip)) {
+                warn!("cannot pin {} to {}: outside the subnet or taken", pk, ip);
+            }
+        }
+        known = current;
+
+        if let Err(e) = save_leases(&storage, &handle).await {
+            debug!("save leases: {}", e);
+        }
+        if let Err(e) = save_last_connected(&storage, &mut seen).await {
+            debug!("save last connected: {}", e);
+        }
     }
 }
@@ -0,0 +1,246 @@
+//! Traffic accounting of a running server.
+//!
+//! The SDK counts the bytes of every session. [`Meter`] turns those counters
+//! into per-user deltas that `server start` adds to the `usage` keyspace on
+//! every users reload, where quotas are checked too.
+
+use crate::storage::{Usage, Usages, database};
+use chrono::{DateTime, Utc};
+use holynet_sdk::crypto::PublicKey;
+use holynet_sdk::protocol::SessionId;
+use holynet_sdk::runtime::server::session::Session;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+/// Counted bytes of one session.
+struct Tracked {
+    session: Arc<Session>,
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+impl Tracked {
+    fn new(session: Arc<Session>) -> Self {
+        Self {
+            session,
+            rx_bytes: 0,
+            tx_bytes: 0,
+        }
+    }
+
+    /// Add the bytes since the last count to `pending`.
+    fn count(&mut self, pending: &mut HashMap<PublicKey, (u64, u64)>) {
+        let traffic = &self.session.traffic;
+        let rx = traffic.rx_bytes.load(Ordering::Relaxed);
+        let tx = traffic.tx_bytes.load(Ordering::Relaxed);
+        let delta = (rx - self.rx_bytes, tx - self.tx_bytes);
+        (self.rx_bytes, self.tx_bytes) = (rx, tx);
+        if delta != (0, 0) {
+            let user = pending.entry(self.session.peer_pk.clone()).or_default();
+            user.0 += delta.0;
+            user.1 += delta.1;
+        }
+    }
+}
+
+/// Traffic not yet written to the store.
+#[derive(Default)]
+pub struct Meter {
+    sessions: HashMap<SessionId, Tracked>,
+    pending: HashMap<PublicKey, (u64, u64)>,
+}
+
+impl Meter {
+    /// Count `session` from its first byte on. Sessions are picked up by
+    /// [`collect`](Self::collect) as well; tracking them when they are
+    /// created also covers those that end before the next collect.
+    pub fn track(&mut self, session: Arc<Session>) {
+        match self.sessions.entry(session.id) {
+            Entry::Occupied(mut entry) => {
+                if !Arc::ptr_eq(&entry.get().session, &session) {
+                    // The id was reused; settle the session that had it.
+                    entry.get_mut().count(&mut self.pending);
+                    entry.insert(Tracked::new(session));
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Tracked::new(session));
+            }
+        }
+    }
+
+    /// Move the traffic since the last collect into the pending counts, and
+    /// forget sessions that are not among the `live` ones any more.
+    pub fn collect(&mut self, live: &[Arc<Session>]) {
+        for session in live {
+            self.track(session.clone());
+        }
+        for tracked in self.sessions.values_mut() {
+            tracked.count(&mut self.pending);
+        }
+        let live: HashMap<SessionId, &Arc<Session>> =
+            live.iter().map(|session| (session.id, session)).collect();
+        self.sessions.retain(|sid, tracked| {
+            live.get(sid)
+                .is_some_and(|session| Arc::ptr_eq(session, &tracked.session))
+        });
+    }
+
+    /// Add the pending traffic to the counters of `period` and return every
+    /// user's usage. The counts stay pending when the store is busy.
+    pub async fn flush(
+        &mut self,
+        storage: &Path,
+        period: DateTime<Utc>,
+    ) -> anyhow::Result<HashMap<PublicKey, Usage>> {
+        let usages = Usages::new(database(storage)?)?;
+        let traffic = self
+            .pending
+            .iter()
+            .map(|(pk, (rx, tx))| (pk.clone(), *rx, *tx))
+            .collect();
+        usages.add(traffic, period).await?;
+        self.pending.clear();
+        Ok(usages.get_all(period).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use holynet_sdk::crypto::SecretKey;
+    use holynet_sdk::protocol::Alg;
+    use holynet_sdk::runtime::server::session::Sessions;
+    use std::path::PathBuf;
+
+    fn transport_state() -> snow::StatelessTransportState {
+        let params: snow::params::NoiseParams =
+            "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let mut init = snow::Builder::new(params.clone())
+            .build_initiator()
+            .unwrap();
+        let mut resp = snow::Builder::new(params).build_responder().unwrap();
+        let mut buf = [0u8; 128];
+        let mut scratch = [0u8; 128];
+        let len = init.write_message(&[], &mut buf).unwrap();
+        resp.read_message(&buf[..len], &mut scratch).unwrap();
+        let len = resp.write_message(&[], &mut buf).unwrap();
+        init.read_message(&buf[..len], &mut scratch).unwrap();
+        resp.into_stateless_transport_mode().unwrap()
+    }
+
+    fn peer() -> PublicKey {
+        PublicKey::from_secret(&SecretKey::generate_x25519())
+    }
+
+    fn open(sessions: &Sessions, sid: SessionId, pk: &PublicKey) -> Arc<Session> {
+        let ip = sessions.next_holy_ip().unwrap();
+        sessions.add(
+            sid,
+            ip,
+            None,
+            "127.0.0.1:5000".parse().unwrap(),
+            pk.clone(),
+            Alg::ChaCha20Poly1305,
+            transport_state(),
+        )
+    }
+
+    fn traffic(session: &Session, rx: u64, tx: u64) {
+        session.traffic.rx_bytes.fetch_add(rx, Ordering::Relaxed);
+        session.traffic.tx_bytes.fetch_add(tx, Ordering::Relaxed);
+    }
+
+    fn storage(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("holynet-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    fn period() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_meter_adds_deltas_per_user() {
+        let path = storage("meter-deltas");
+        let sessions = Sessions::new(&"10.0.0.0".parse().unwrap(), 24);
+        let (alice, bob) = (peer(), peer());
+        let phone = open(&sessions, 1, &alice);
+        let laptop = open(&sessions, 2, &alice);
+        let desktop = open(&sessions, 3, &bob);
+        let mut meter = Meter::default();
+
+        traffic(&phone, 100, 10);
+        traffic(&laptop, 200, 20);
+        traffic(&desktop, 5, 5);
+        meter.collect(&sessions.all());
+        let usage = meter.flush(&path, period()).await.unwrap();
+        assert_eq!((usage[&alice].rx_bytes, usage[&alice].tx_bytes), (300, 30));
+        assert_eq!(usage[&bob].total(), 10);
+
+        // Only what is new since the last collect is added.
+        traffic(&phone, 1, 0);
+        meter.collect(&sessions.all());
+        let usage = meter.flush(&path, period()).await.unwrap();
+        assert_eq!(usage[&alice].rx_bytes, 301);
+        assert_eq!(usage[&bob].total(), 10);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[tokio::test]
+    async fn test_meter_settles_ended_and_reused_sessions() {
+        let path = storage("meter-reuse");
+        let sessions = Sessions::new(&"10.0.0.0".parse().unwrap(), 24);
+        let (alice, bob) = (peer(), peer());
+        let mut meter = Meter::default();
+
+        // Tracked when created, gone before the next collect.
+        let short = open(&sessions, 1, &alice);
+        meter.track(short.clone());
+        traffic(&short, 40, 2);
+        sessions.release_by_sid(1);
+        meter.collect(&sessions.all());
+        assert!(meter.sessions.is_empty());
+
+        // The sid goes to another user before the old session is collected.
+        let old = open(&sessions, 1, &alice);
+        meter.track(old.clone());
+        traffic(&old, 7, 0);
+        sessions.release_by_sid(1);
+        let new = open(&sessions, 1, &bob);
+        meter.track(new.clone());
+        traffic(&new, 0, 9);
+        meter.collect(&sessions.all());
+
+        let usage = meter.flush(&path, period()).await.unwrap();
+        assert_eq!((usage[&alice].rx_bytes, usage[&alice].tx_bytes), (47, 2));
+        assert_eq!((usage[&bob].rx_bytes, usage[&bob].tx_bytes), (0, 9));
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[tokio::test]
+    async fn test_meter_keeps_counts_while_store_is_busy() {
+        let path = storage("meter-busy");
+        let sessions = Sessions::new(&"10.0.0.0".parse().unwrap(), 24);
+        let alice = peer();
+        let session = open(&sessions, 1, &alice);
+        let mut meter = Meter::default();
+        traffic(&session, 10, 10);
+        meter.collect(&sessions.all());
+
+        let lock = database(&path).unwrap();
+        assert!(meter.flush(&path, period()).await.is_err());
+        drop(lock);
+        let usage = meter.flush(&path, period()).await.unwrap();
+        assert_eq!(usage[&alice].total(), 20);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -1,19 +1,21 @@
 use crate::config::Config;
 use crate::config::connection::{ConnectionConfig, CredentialsConfig, GeneralConfig};
-use crate::storage::{Client, Clients, database};
+use crate::storage::{Client, Clients, Leases, database};
 use crate::style::{format_opaque_bytes, generate_qrcode};
 use crate::{success_err, success_ok};
+use chrono::{DateTime, Utc};
 use clap::Args;
 use holynet_sdk::crypto::{PublicKey, SecretKey};
 use holynet_sdk::protocol::Alg;
 use inquire::required;
 use inquire::validator::Validation;
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 #[derive(Debug, Args)]
 pub struct AddCmd {
     /// External server host for client
-    #[arg(short, long)]
+    #[arg(long)]
     host: Option<String>,
     /// External server port for client
     #[arg(short, long)]
@@ -22,12 +24,30 @@ pub struct AddCmd {
     #[arg(short, long)]
     sk: Option<String>,
     /// Pre-shared key (base64)
-    #[arg(short, long)]
+    #[arg(long)]
     psk: Option<String>,
+    /// Always give this user the tunnel address
+    #[arg(short, long)]
+    address: Option<IpAddr>,
+    /// Unique name to refer to the user by
+    #[arg(short, long)]
+    name: Option<String>,
+    #[arg(long)]
+    email: Option<String>,
+    #[arg(long)]
+    notes: Option<String>,
+    /// Refuse the user from this moment on: `YYYY-MM-DD` (midnight UTC) or
+    /// an RFC 3339 timestamp
+    #[arg(long, value_parser = super::expiry::parse_expiry)]
+    expires: Option<DateTime<Utc>>,
 }
 
 impl AddCmd {
     pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        if let Some(address) = self.address {
+            super::pin::check_address(&config, address)?;
+        }
+
         let host = match self.host {
             Some(h) => h,
             None => inquire::Text::new("Enter server host:")
@@ -57,6 +77,12 @@ impl AddCmd {
 
         let pk = PublicKey::from_secret(&sk);
 
+        let db = database(&config.general.storage)?;
+        let clients = Clients::new(db.clone())?;
+        if let Some(name) = &self.name {
+            super::check_name(&clients, name, &pk).await?;
+        }
+
         let psk = match self.psk {
             Some(s) => SecretKey::try_from(s.as_str())
                 .map_err(|e| anyhow::anyhow!("parse pre-shared key: {}", e))?,
@@ -69,14 +95,19 @@ impl AddCmd {
         success_ok!("SharedKey", format_opaque_bytes(psk.as_slice()));
         println!();
 
-        let clients = Clients::new(database(&config.general.storage)?)?;
         clients
             .save(Client {
-                psk: psk.clone(),
-                peer_pk: pk.clone(),
-                created_at: chrono::Utc::now(),
+                name: self.name,
+                email: self.email,
+                notes: self.notes,
+                expires_at: self.expires,
+                ..Client::new(pk.clone(), psk.clone())
             })
             .await;
+        if let Some(address) = self.address {
+            Leases::new(db)?.pin(&pk, Some(address)).await?;
+            success_ok!("Pinned", "{}", address);
+        }
 
         let connection_config = ConnectionConfig {
             general: GeneralConfig {
@@ -0,0 +1,28 @@
+use crate::config::Config;
+use crate::storage::{Clients, database};
+use crate::success_ok;
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct EnableCmd {
+    /// Name or public key (base64)
+    #[arg()]
+    user: String,
+}
+
+impl EnableCmd {
+    /// Shared by `enable` and `disable`; a running server applies the change
+    /// on its next users reload.
+    pub async fn exec(self, config: Config, enabled: bool) -> anyhow::Result<()> {
+        let clients = Clients::new(database(&config.general.storage)?)?;
+        let mut client = super::find_user(&clients, &self.user).await?;
+        let label = client.label();
+        client.enabled = enabled;
+        clients.save(client).await;
+        match enabled {
+            true => success_ok!("Enabled", "user {}", label),
+            false => success_ok!("Disabled", "user {}", label),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use crate::config::Config;
+use crate::storage::{Clients, database};
+use crate::success_ok;
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct SetExpiryCmd {
+    /// Name or public key (base64)
+    #[arg()]
+    user: String,
+    /// `YYYY-MM-DD` (midnight UTC) or an RFC 3339 timestamp; omit to never
+    /// expire
+    #[arg(value_parser = parse_expiry)]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl SetExpiryCmd {
+    pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        let clients = Clients::new(database(&config.general.storage)?)?;
+        let mut client = super::find_user(&clients, &self.user).await?;
+        let label = client.label();
+        client.expires_at = self.expires_at;
+        clients.save(client).await;
+        match self.expires_at {
+            Some(at) => success_ok!(
+                "Expires",
+                "user {} at {}",
+                label,
+                at.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            None => success_ok!("Expires", "user {} never", label),
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_expiry(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(s) {
+        return Ok(at.to_utc());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_time(Default::default()).and_utc())
+        .map_err(|_| anyhow!("expected YYYY-MM-DD or an RFC 3339 timestamp"))
+}
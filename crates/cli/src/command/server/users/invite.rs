@@ -0,0 +1,81 @@
+use crate::config::Config;
+use crate::config::connection::EnrollToken;
+use crate::storage::{Invite, Invites, database};
+use crate::style::generate_qrcode;
+use crate::{success_err, success_ok};
+use anyhow::anyhow;
+use clap::Args;
+use holynet_sdk::crypto::{PublicKey, SecretKey};
+use holynet_sdk::runtime::enroll::token_id;
+use inquire::required;
+use std::net::IpAddr;
+
+#[derive(Debug, Args)]
+pub struct InviteCmd {
+    /// External server host for client
+    #[arg(long)]
+    host: Option<String>,
+    /// External port of the enrollment listener (defaults to `runtime.enroll`)
+    #[arg(short, long)]
+    port: Option<u16>,
+    /// Hours until the token expires
+    #[arg(short, long, default_value_t = 24)]
+    expires: u32,
+    /// Always give the enrolled user this tunnel address
+    #[arg(short, long)]
+    address: Option<IpAddr>,
+}
+
+impl InviteCmd {
+    pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        let Some(listener) = config.runtime.as_ref().and_then(|r| r.enroll) else {
+            return Err(anyhow!("set runtime.enroll to accept enrollments"));
+        };
+        if let Some(address) = self.address {
+            super::pin::check_address(&config, address)?;
+        }
+
+        let host = match self.host {
+            Some(h) => h,
+            None => inquire::Text::new("Enter server host:")
+                .with_default(&config.general.host)
+                .with_validator(required!("This field is required"))
+                .prompt()?
+                .trim()
+                .to_string(),
+        };
+
+        let token = SecretKey::generate_x25519();
+        let created_at = chrono::Utc::now();
+        let expires_at = created_at + chrono::Duration::hours(self.expires.into());
+        Invites::new(database(&config.general.storage)?)?
+            .save(
+                token_id(&token),
+                Invite {
+                    created_at,
+                    expires_at,
+                    address: self.address,
+                },
+            )
+            .await?;
+
+        let token = EnrollToken {
+            host,
+            port: self.port.unwrap_or(listener.port()),
+            server_public_key: PublicKey::from_secret(&config.general.secret_key),
+            token,
+        }
+        .to_base64();
+
+        println!();
+        match generate_qrcode(token.as_bytes()) {
+            Ok(qr) => println!("{}\n", qr),
+            Err(e) => success_err!("generate qrcode: {}", e),
+        }
+        success_ok!("Expires", "{}", expires_at.format("%Y-%m-%d %H:%M:%S UTC"));
+        success_ok!("Token", "{}", token);
+        println!("\nOn the client: holynet enroll <TOKEN>");
+
+        Ok(())
+    }
+}
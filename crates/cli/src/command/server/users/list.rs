@@ -1,18 +1,44 @@
 use crate::config::Config;
-use crate::storage::{Clients, database};
-use crate::style::format_opaque_bytes;
+use crate::storage::{Client, Clients, Usage, Usages, database};
+use crate::style::{format_bytes, format_opaque_bytes};
 use crate::success_ok;
+use chrono::{DateTime, Utc};
 use clap::Args;
 use derive_more::Display;
-use holynet_sdk::crypto::{PublicKey, SecretKey};
 use inquire::Select;
 
+fn format_time(at: &DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
 #[derive(Clone, Display)]
-#[display("{:.8}\t{}", pk.to_string(), created_at)]
+#[display("{}\t{:.8}\t{}\t{}\t{}", client.label(), client.peer_pk.to_string(), self.status(), self.usage(), format_time(&client.created_at))]
 pub struct UserRow {
-    pub pk: PublicKey,
-    pub psk: SecretKey,
-    pub created_at: String,
+    pub client: Client,
+    pub usage: Usage,
+}
+
+impl UserRow {
+    fn status(&self) -> &'static str {
+        match (self.client.enabled, self.client.is_expired()) {
+            (false, _) => "disabled",
+            (true, true) => "expired",
+            (true, false) if self.usage.over_quota() => "over quota",
+            (true, false) => "active",
+        }
+    }
+
+    /// Traffic this period, and the quota if any.
+    fn usage(&self) -> String {
+        match self.usage.quota {
+            Some(quota) => format!(
+                "{} / {}",
+                format_bytes(self.usage.total()),
+                format_bytes(quota)
+            ),
+            None => format_bytes(self.usage.total()),
+        }
+    }
 }
 
 #[derive(Debug, Args)]
@@ -20,25 +46,71 @@ pub struct ListCmd;
 
 impl ListCmd {
     pub async fn exec(self, config: Config) -> anyhow::Result<()> {
-        let clients = Clients::new(database(&config.general.storage)?)?;
+        let db = database(&config.general.storage)?;
+        let clients = Clients::new(db.clone())?;
+        let runtime = config.runtime.unwrap_or_default();
+        let period = runtime.quota_period.current();
+        let mut usages = Usages::new(db)?.get_all(period).await;
         let mut users: Vec<_> = clients
             .get_all()
             .await
             .into_iter()
             .map(|client| UserRow {
-                pk: client.peer_pk,
-                psk: client.psk,
-                created_at: client.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                usage: usages.remove(&client.peer_pk).unwrap_or_else(|| Usage {
+                    period,
+                    ..Usage::default()
+                }),
+                client,
             })
             .collect();
-        users.sort_by_key(|u| u.created_at.clone());
+        users.sort_by_key(|u| u.client.created_at);
 
         let selected = Select::new("Select user", users).prompt()?;
+        let (status, traffic) = (selected.status(), selected.usage());
+        let (client, usage) = (selected.client, selected.usage);
 
         println!();
-        success_ok!("PubKey", selected.pk);
-        success_ok!("SharedKey", format_opaque_bytes(selected.psk.as_slice()));
-        success_ok!("CreatedAt", selected.created_at);
+        if let Some(name) = &client.name {
+            success_ok!("Name", name);
+        }
+        success_ok!("PubKey", client.peer_pk);
+        success_ok!("SharedKey", format_opaque_bytes(client.psk.as_slice()));
+        if let Some(email) = &client.email {
+            success_ok!("Email", email);
+        }
+        if let Some(notes) = &client.notes {
+            success_ok!("Notes", notes);
+        }
+        success_ok!("Status", status);
+        success_ok!(
+            "Traffic",
+            format!(
+                "{} (rx {}, tx {}) since {}",
+                traffic,
+                format_bytes(usage.rx_bytes),
+                format_bytes(usage.tx_bytes),
+                usage.period.format("%Y-%m-%d")
+            )
+        );
+        success_ok!(
+            "Rate",
+            format!(
+                "upload {}, download {}",
+                super::rate::describe(client.upload_limit, runtime.upload_limit),
+                super::rate::describe(client.download_limit, runtime.download_limit)
+            )
+        );
+        if let Some(expires_at) = &client.expires_at {
+            success_ok!("ExpiresAt", format_time(expires_at));
+        }
+        success_ok!("CreatedAt", format_time(&client.created_at));
+        match &client.last_connected {
+            Some(last) => success_ok!(
+                "Connected",
+                format!("{} from {}", format_time(&last.at), last.endpoint)
+            ),
+            None => success_ok!("Connected", "never"),
+        }
         println!();
 
         Ok(())
@@ -1,33 +1,96 @@
 mod add;
+mod enable;
+mod expiry;
+mod invite;
 mod list;
+mod pin;
+mod quota;
+mod rate;
 mod remove;
+mod rename;
 
 use crate::config::Config;
+use crate::storage::{Client, Clients};
 use crate::success_err;
 use add::AddCmd;
+use anyhow::anyhow;
 use clap::Subcommand;
+use enable::EnableCmd;
+use expiry::SetExpiryCmd;
+use holynet_sdk::crypto::PublicKey;
+use invite::InviteCmd;
 use list::ListCmd;
+use pin::PinCmd;
+use quota::SetQuotaCmd;
+use rate::SetRateCmd;
 use remove::RemoveCmd;
+use rename::RenameCmd;
 
 #[derive(Debug, Subcommand)]
 pub enum UsersCmd {
-    /// Add a new user
+    /// Add a new user, generating their keys on the server
     Add(AddCmd),
+    /// Issue a one-time token a new user enrolls with, using keys of their own
+    Invite(InviteCmd),
     /// List all users
     List(ListCmd),
     /// Remove a user
     Remove(RemoveCmd),
+    /// Pin a user's tunnel address, or drop the pin
+    Pin(PinCmd),
+    /// Name a user, or drop the name
+    Rename(RenameCmd),
+    /// Let a disabled user connect again
+    Enable(EnableCmd),
+    /// Refuse a user's handshakes and close their sessions
+    Disable(EnableCmd),
+    /// Set the moment a user's access ends, or lift it
+    SetExpiry(SetExpiryCmd),
+    /// Cap a user's traffic per accounting period, or lift the cap
+    SetQuota(SetQuotaCmd),
+    /// Limit a user's bandwidth per session, or follow the server default
+    SetRate(SetRateCmd),
 }
 
 impl UsersCmd {
     pub async fn exec(self, config: Config) {
         if let Err(e) = match self {
             UsersCmd::Add(cmd) => cmd.exec(config).await,
+            UsersCmd::Invite(cmd) => cmd.exec(config).await,
             UsersCmd::List(cmd) => cmd.exec(config).await,
             UsersCmd::Remove(cmd) => cmd.exec(config).await,
+            UsersCmd::Pin(cmd) => cmd.exec(config).await,
+            UsersCmd::Rename(cmd) => cmd.exec(config).await,
+            UsersCmd::Enable(cmd) => cmd.exec(config, true).await,
+            UsersCmd::Disable(cmd) => cmd.exec(config, false).await,
+            UsersCmd::SetExpiry(cmd) => cmd.exec(config).await,
+            UsersCmd::SetQuota(cmd) => cmd.exec(config).await,
+            UsersCmd::SetRate(cmd) => cmd.exec(config).await,
         } {
             success_err!("{}", e);
             std::process::exit(1);
         }
     }
 }
+
+/// Find the user named `user`, or holding the public key `user` (base64).
+pub async fn find_user(clients: &Clients, user: &str) -> anyhow::Result<Client> {
+    clients
+        .find(user)
+        .await
+        .ok_or_else(|| anyhow!("user {} not found", user))
+}
+
+/// A name must not read as a public key and must not be taken by another user.
+pub async fn check_name(clients: &Clients, name: &str, pk: &PublicKey) -> anyhow::Result<()> {
+    if name.trim().is_empty() || name.trim() != name {
+        return Err(anyhow!("name must not be empty or padded with spaces"));
+    }
+    if PublicKey::try_from(name).is_ok() {
+        return Err(anyhow!("name {} reads as a public key", name));
+    }
+    match clients.find(name).await {
+        Some(other) if other.peer_pk != *pk => Err(anyhow!("name {} is taken", name)),
+        _ => Ok(()),
+    }
+}
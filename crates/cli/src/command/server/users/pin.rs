@@ -0,0 +1,45 @@
+use crate::config::Config;
+use crate::storage::{Clients, Leases, database};
+use crate::success_ok;
+use anyhow::anyhow;
+use clap::Args;
+use std::net::IpAddr;
+
+#[derive(Debug, Args)]
+pub struct PinCmd {
+    /// Name or public key (base64)
+    #[arg()]
+    user: String,
+    /// Tunnel address to pin; omit to drop the pin
+    #[arg()]
+    address: Option<IpAddr>,
+}
+
+impl PinCmd {
+    pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        let db = database(&config.general.storage)?;
+        let client = super::find_user(&Clients::new(db.clone())?, &self.user).await?;
+        let (pk, label) = (client.peer_pk.clone(), client.label());
+        if let Some(address) = self.address {
+            check_address(&config, address)?;
+        }
+        Leases::new(db)?.pin(&pk, self.address).await?;
+        match self.address {
+            Some(address) => success_ok!("Pinned", "user {} to {}", label, address),
+            None => success_ok!("Unpinned", "user {}", label),
+        }
+        Ok(())
+    }
+}
+
+/// The address must be a host address inside the server's tunnel subnet.
+pub fn check_address(config: &Config, address: IpAddr) -> anyhow::Result<()> {
+    let subnet = ipnetwork::IpNetwork::new(config.interface.address, config.interface.prefix)?;
+    if !subnet.contains(address) || address == subnet.network() {
+        return Err(anyhow!("{} is not a host address in {}", address, subnet));
+    }
+    if address == config.interface.address {
+        return Err(anyhow!("{} is the server's own address", address));
+    }
+    Ok(())
+}
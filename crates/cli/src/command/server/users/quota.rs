@@ -0,0 +1,69 @@
+use crate::config::Config;
+use crate::storage::{Clients, Usages, database};
+use crate::style::format_bytes;
+use crate::success_ok;
+use anyhow::anyhow;
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct SetQuotaCmd {
+    /// Name or public key (base64)
+    #[arg()]
+    user: String,
+    /// Traffic allowed per period in both directions, e.g. `50GiB`, `500M`
+    /// or a number of bytes; omit to lift the quota
+    #[arg(value_parser = parse_bytes)]
+    quota: Option<u64>,
+}
+
+impl SetQuotaCmd {
+    pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        let runtime = config.runtime.unwrap_or_default();
+        if self.quota.is_some() && runtime.users_reload == 0 {
+            return Err(anyhow!(
+                "quotas need runtime.users_reload above 0, which counts the traffic"
+            ));
+        }
+        let db = database(&config.general.storage)?;
+        let client = super::find_user(&Clients::new(db.clone())?, &self.user).await?;
+        let usages = Usages::new(db)?;
+        let period = runtime.quota_period.current();
+        let mut usage = usages.get(&client.peer_pk, period).await;
+        usage.quota = self.quota;
+        usages.save(&client.peer_pk, &usage).await?;
+        match self.quota {
+            Some(quota) => success_ok!(
+                "Quota",
+                "user {} {} per period, {} used",
+                client.label(),
+                format_bytes(quota),
+                format_bytes(usage.total())
+            ),
+            None => success_ok!("Quota", "user {} unlimited", client.label()),
+        }
+        Ok(())
+    }
+}
+
+/// `50GiB`, `50G` and `50GB` are all 50 × 1024³ bytes.
+fn parse_bytes(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| anyhow!("expected e.g. 50GiB"))?;
+    let shift = match unit
+        .trim()
+        .to_ascii_uppercase()
+        .trim_end_matches(['B', 'I'])
+    {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(anyhow!("unknown unit {}", unit)),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| anyhow!("{} is too large", s))
+}
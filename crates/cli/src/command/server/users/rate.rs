@@ -0,0 +1,76 @@
+use crate::config::{Config, Rate};
+use crate::storage::{Clients, database};
+use crate::success_ok;
+use anyhow::anyhow;
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct SetRateCmd {
+    /// Name or public key (base64)
+    #[arg()]
+    user: String,
+    /// Rate the user may send at, e.g. `20Mbit`, `unlimited`, or `default` to
+    /// follow `runtime.upload_limit`
+    #[arg(long, value_parser = parse_limit)]
+    upload: Option<Limit>,
+    /// Rate the user may receive at, like `--upload`
+    #[arg(long, value_parser = parse_limit)]
+    download: Option<Limit>,
+}
+
+impl SetRateCmd {
+    pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        if self.upload.is_none() && self.download.is_none() {
+            return Err(anyhow!("pass --upload, --download or both"));
+        }
+        let clients = Clients::new(database(&config.general.storage)?)?;
+        let mut client = super::find_user(&clients, &self.user).await?;
+        if let Some(upload) = self.upload {
+            client.upload_limit = upload.bytes();
+        }
+        if let Some(download) = self.download {
+            client.download_limit = download.bytes();
+        }
+        let runtime = config.runtime.unwrap_or_default();
+        success_ok!(
+            "Rate",
+            "user {} upload {}, download {}",
+            client.label(),
+            describe(client.upload_limit, runtime.upload_limit),
+            describe(client.download_limit, runtime.download_limit)
+        );
+        clients.save(client).await;
+        Ok(())
+    }
+}
+
+/// A user's limit in one direction, as `list` shows it too.
+pub fn describe(limit: Option<u64>, default: Rate) -> String {
+    match limit {
+        Some(rate) => Rate(rate).to_string(),
+        None => format!("{} (default)", default),
+    }
+}
+
+/// A per-user override, or `default` to drop it.
+#[derive(Clone, Copy, Debug)]
+enum Limit {
+    Default,
+    Rate(Rate),
+}
+
+impl Limit {
+    fn bytes(self) -> Option<u64> {
+        match self {
+            Limit::Default => None,
+            Limit::Rate(rate) => Some(rate.0),
+        }
+    }
+}
+
+fn parse_limit(s: &str) -> anyhow::Result<Limit> {
+    if s.trim().eq_ignore_ascii_case("default") {
+        return Ok(Limit::Default);
+    }
+    s.parse().map(Limit::Rate)
+}
@@ -1,30 +1,24 @@
 use crate::config::Config;
-use crate::storage::{Clients, database};
+use crate::storage::{Clients, Leases, Usages, database};
 use crate::success_ok;
-use anyhow::anyhow;
 use clap::Args;
-use holynet_sdk::crypto::PublicKey;
 
 #[derive(Debug, Args)]
 pub struct RemoveCmd {
-    /// Public key (base64)
+    /// Name or public key (base64)
     #[arg()]
-    pk: String,
+    user: String,
 }
 
 impl RemoveCmd {
     pub async fn exec(self, config: Config) -> anyhow::Result<()> {
-        let pk = PublicKey::try_from(self.pk.as_str())
-            .map_err(|e| anyhow::anyhow!("parse public key: {}", e))?;
-
-        let clients = Clients::new(database(&config.general.storage)?)?;
-        match clients.get(&pk).await {
-            Some(_) => {
-                clients.delete(&pk).await?;
-                success_ok!("Removed", "client {:.8}", pk);
-                Ok(())
-            }
-            None => Err(anyhow!("client {:.8} not found", pk)),
-        }
+        let db = database(&config.general.storage)?;
+        let clients = Clients::new(db.clone())?;
+        let client = super::find_user(&clients, &self.user).await?;
+        clients.delete(&client.peer_pk).await?;
+        Leases::new(db.clone())?.pin(&client.peer_pk, None).await?;
+        Usages::new(db)?.delete(&client.peer_pk).await?;
+        success_ok!("Removed", "user {}", client.label());
+        Ok(())
     }
 }
@@ -0,0 +1,30 @@
+use crate::config::Config;
+use crate::storage::{Clients, database};
+use crate::success_ok;
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct RenameCmd {
+    /// Name or public key (base64)
+    #[arg()]
+    user: String,
+    /// New name; omit to drop the name
+    #[arg()]
+    name: Option<String>,
+}
+
+impl RenameCmd {
+    pub async fn exec(self, config: Config) -> anyhow::Result<()> {
+        let clients = Clients::new(database(&config.general.storage)?)?;
+        let mut client = super::find_user(&clients, &self.user).await?;
+        if let Some(name) = &self.name {
+            super::check_name(&clients, name, &client.peer_pk).await?;
+        }
+        let old = client.label();
+        client.name = self.name;
+        let new = client.label();
+        clients.save(client).await;
+        success_ok!("Renamed", "{} to {}", old, new);
+        Ok(())
+    }
+}
@@ -3,8 +3,10 @@ use base64::Engine;
 use base64::engine::general_purpose::STANDARD_NO_PAD;
 use holynet_sdk::crypto::{PublicKey, SecretKey};
 use holynet_sdk::protocol::Alg;
+use holynet_sdk::runtime::client::Backoff;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize)]
 pub struct GeneralConfig {
@@ -24,6 +26,14 @@ fn default_offload() -> bool {
     true
 }
 
+fn default_rekey_after() -> Option<u64> {
+    Some(120)
+}
+
+fn default_dead_peer_after() -> Option<u32> {
+    Some(3)
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct InterfaceConfig {
     pub name: String,
@@ -34,10 +44,62 @@ pub struct InterfaceConfig {
     pub offload: bool,
 }
 
+/// Delays between failed handshakes; see `holynet_sdk::runtime::client::Backoff`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReconnectConfig {
+    /// Delay before the first retry in milliseconds
+    pub initial_delay: u64,
+    /// Growth of the delay per failed attempt
+    pub multiplier: f64,
+    /// Upper bound of the delay in milliseconds
+    pub max_delay: u64,
+    /// Random spread of each delay as a fraction of it, between 0 and 1
+    pub jitter: f64,
+    /// Give up after this many failed handshakes in a row. `None` retries
+    /// forever.
+    pub max_attempts: Option<u32>,
+    /// Exit when the very first handshake fails instead of retrying.
+    pub fail_fast: bool,
+}
+
+impl ReconnectConfig {
+    pub fn backoff(&self) -> Backoff {
+        Backoff::default()
+            .initial(Duration::from_millis(self.initial_delay))
+            .multiplier(self.multiplier)
+            .max(Duration::from_millis(self.max_delay))
+            .jitter(self.jitter)
+            .max_attempts(self.max_attempts)
+            .fail_fast(self.fail_fast)
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: 1000,
+            multiplier: 2.0,
+            max_delay: 60_000,
+            jitter: 0.2,
+            max_attempts: None,
+            fail_fast: true,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RuntimeConfig {
     pub handshake_timeout: u64,
     pub keepalive: Option<u64>,
+    /// Rotate session keys every N seconds. `None` disables time-based rekeying.
+    #[serde(default = "default_rekey_after")]
+    pub rekey_after: Option<u64>,
+    /// Reconnect after N keepalives in a row got no reply. `0` disables
+    /// dead-server detection.
+    #[serde(default = "default_dead_peer_after")]
+    pub dead_peer_after: Option<u32>,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
     /// Parallel encrypt workers on the send path. `0` auto-sizes to one worker
     /// per logical CPU; `1` keeps the single-task path; `>= 2` sets an explicit
     /// WireGuard-style encrypt pool.
@@ -96,11 +158,39 @@ impl ConnectionConfig {
     }
 }
 
+/// Everything a client needs to enroll: where to reach the server, which key
+/// the server must prove, and the one-time token.
+#[derive(Serialize, Deserialize)]
+pub struct EnrollToken {
+    pub host: String,
+    /// Port of the enrollment listener
+    pub port: u16,
+    pub server_public_key: PublicKey,
+    pub token: SecretKey,
+}
+
+impl EnrollToken {
+    pub fn to_base64(&self) -> String {
+        let bytes = bincode::serde::encode_to_vec(self, bincode::config::standard())
+            .expect("failed to serialize enrollment token");
+        STANDARD_NO_PAD.encode(bytes)
+    }
+
+    pub fn from_base64(base64: &str) -> anyhow::Result<Self> {
+        let bytes = STANDARD_NO_PAD.decode(base64.trim())?;
+        let (token, _) = bincode::serde::decode_from_slice(&bytes, bincode::config::standard())?;
+        Ok(token)
+    }
+}
+
 impl Default for RuntimeConfig {
     fn default() -> Self {
         Self {
             handshake_timeout: 3000,
             keepalive: Some(5),
+            rekey_after: default_rekey_after(),
+            dead_peer_after: default_dead_peer_after(),
+            reconnect: ReconnectConfig::default(),
             encrypt_workers: 0,
             decrypt_workers: 0,
             so_rcvbuf: 1024 * 1024 * 1024,
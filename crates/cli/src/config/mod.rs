@@ -1,10 +1,18 @@
 pub mod connection;
 
 use crate::network::find_available_ifname;
-use holynet_sdk::crypto::SecretKey;
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, NaiveTime, Utc};
+use holynet_sdk::crypto::{PublicKey, SecretKey};
+use holynet_sdk::protocol::NetworkConfig;
+use holynet_sdk::runtime::server::DeviceLimitPolicy;
+use ipnetwork::IpNetwork;
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{LazyLock, Mutex};
 
 static PATH: LazyLock<Mutex<PathBuf>> = LazyLock::new(|| Mutex::new(PathBuf::from("config.toml")));
@@ -15,12 +23,27 @@ pub struct GeneralConfig {
     pub port: u16,
     pub secret_key: SecretKey,
     pub storage: PathBuf,
+    /// Unix socket the running server listens on for `server sessions`
+    #[serde(default = "default_control")]
+    pub control: PathBuf,
+}
+
+fn default_control() -> PathBuf {
+    PathBuf::from("holynet.sock")
 }
 
 fn default_offload() -> bool {
     true
 }
 
+fn default_prefix6() -> u8 {
+    64
+}
+
+fn default_users_reload() -> u64 {
+    5
+}
+
 /// Resolve a worker-pool size where `0` means "auto" (one worker per logical
 /// CPU) and any other value is taken verbatim. A configured `1` therefore keeps
 /// the single-task path, so pools can still be disabled explicitly.
@@ -40,6 +63,12 @@ pub struct InterfaceConfig {
     pub mtu: u16,
     pub address: IpAddr,
     pub prefix: u8,
+    /// IPv6 subnet for dual-stack tunnels. Every client then gets an address
+    /// from both `address/prefix` and `address6/prefix6`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address6: Option<Ipv6Addr>,
+    #[serde(default = "default_prefix6")]
+    pub prefix6: u8,
     /// Enable Linux TUN GRO/TSO offload (batched recv_multiple/send_multiple).
     /// Falls back to per-packet automatically if the kernel rejects it, or when
     /// the `--no-offload` CLI flag is passed.
@@ -61,6 +90,10 @@ pub struct RuntimeConfig {
     /// sets an explicit WireGuard-style decrypt pool.
     #[serde(default)]
     pub decrypt_workers: usize,
+    /// Handshake workers shared by every socket. `0` auto-sizes to one worker
+    /// per logical CPU.
+    #[serde(default)]
+    pub handshake_workers: usize,
     pub so_rcvbuf: usize,
     pub so_sndbuf: usize,
     pub out_udp_buf: usize,
@@ -69,6 +102,217 @@ pub struct RuntimeConfig {
     pub data_udp_buf: usize,
     pub data_tun_buf: usize,
     pub session: Option<SessionConfig>,
+    /// Concurrent sessions allowed per user. `0` means unlimited.
+    #[serde(default)]
+    pub max_devices: u32,
+    /// What a handshake over `max_devices` does: `replace-oldest` closes the
+    /// user's oldest session, `reject-new` refuses the handshake.
+    #[serde(default)]
+    pub device_policy: DeviceLimitPolicy,
+    /// Seconds a user's tunnel address stays reserved after its last session
+    /// ends, so a reconnect gets the same address back. `0` disables sticky
+    /// leases; pinned addresses (`users pin`) apply either way.
+    #[serde(default)]
+    pub lease_grace: u64,
+    /// Seconds between re-reads of the user store while the server runs.
+    /// Handshakes read the store themselves; the reload closes the sessions of
+    /// removed, disabled and expired users and applies new rates and pins.
+    /// Traffic is written to the store and quotas enforced on the same
+    /// schedule. `0` disables reloading and traffic accounting, and with it
+    /// quotas: the server refuses to start while a user has one.
+    #[serde(default = "default_users_reload")]
+    pub users_reload: u64,
+    /// When traffic counters and quotas start over: `daily` or `monthly`, at
+    /// midnight UTC.
+    #[serde(default)]
+    pub quota_period: QuotaPeriod,
+    /// Rate a session may send at, e.g. `20Mbit`; `users set-rate` overrides
+    /// it per user. `0` or unset is unlimited. Excess packets are dropped.
+    #[serde(default, skip_serializing_if = "Rate::is_unlimited")]
+    pub upload_limit: Rate,
+    /// Rate a session may receive at, like `upload_limit`.
+    #[serde(default, skip_serializing_if = "Rate::is_unlimited")]
+    pub download_limit: Rate,
+    /// Address of an HTTP listener serving Prometheus metrics on `/metrics`,
+    /// e.g. `127.0.0.1:9586`. Unset disables it. The listener has no
+    /// authentication and lists users' keys, so bind it to loopback or a
+    /// private network the scraper shares.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<SocketAddr>,
+    /// Also export traffic per session, labelled with the session id and
+    /// tunnel address, not only per user.
+    #[serde(default)]
+    pub metrics_per_session: bool,
+    /// Address of the TCP listener where clients trade the tokens of `users
+    /// invite` for their credentials, e.g. `0.0.0.0:26257`. Unset disables
+    /// enrollment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enroll: Option<SocketAddr>,
+}
+
+/// Accounting period of user traffic.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuotaPeriod {
+    Daily,
+    #[default]
+    Monthly,
+}
+
+impl QuotaPeriod {
+    /// Start of the period `now` falls in.
+    pub fn start(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let date = now.date_naive();
+        let first = match self {
+            QuotaPeriod::Daily => date,
+            QuotaPeriod::Monthly => date.with_day(1).expect("first day of month"),
+        };
+        first.and_time(NaiveTime::MIN).and_utc()
+    }
+
+    /// Start of the current period.
+    pub fn current(self) -> DateTime<Utc> {
+        self.start(Utc::now())
+    }
+}
+
+/// Bandwidth in bits per second with a decimal unit, as `tc` writes it:
+/// `100Kbit`, `20Mbit`, `1Gbit`. Kept in bytes per second, the unit of the
+/// SDK's [`RateLimit`](holynet_sdk::runtime::server::RateLimit); `0` is
+/// unlimited.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rate(pub u64);
+
+impl Rate {
+    const UNITS: [(&str, u64); 4] = [
+        ("Gbit", 1_000_000_000),
+        ("Mbit", 1_000_000),
+        ("Kbit", 1_000),
+        ("bit", 1),
+    ];
+
+    pub fn is_unlimited(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromStr for Rate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if s == "0" || s.eq_ignore_ascii_case("unlimited") {
+            return Ok(Rate(0));
+        }
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| anyhow!("expected e.g. 20Mbit, got {}", s))?;
+        let (_, scale) = Self::UNITS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(unit.trim()))
+            .ok_or_else(|| anyhow!("unknown unit {:?}, expected Kbit, Mbit or Gbit", unit))?;
+        let bits = number
+            .checked_mul(*scale)
+            .ok_or_else(|| anyhow!("{} is too large", s))?;
+        if bits > 0 && bits < 8 {
+            return Err(anyhow!("{} is less than a byte per second", s));
+        }
+        Ok(Rate(bits / 8))
+    }
+}
+
+impl TryFrom<String> for Rate {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Rate> for String {
+    fn from(rate: Rate) -> Self {
+        rate.to_string()
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_unlimited() {
+            return f.write_str("unlimited");
+        }
+        let bits = self.0.saturating_mul(8);
+        let (name, scale) = Self::UNITS
+            .iter()
+            .find(|(_, scale)| bits.is_multiple_of(*scale))
+            .expect("bit divides everything");
+        write!(f, "{}{}", bits / scale, name)
+    }
+}
+
+/// Tunnel settings pushed to clients in the handshake response. Unset fields
+/// leave the client's own defaults in place.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PushSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<Vec<IpAddr>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_domains: Option<Vec<String>>,
+    /// Networks routed through the tunnel; unset routes everything.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routes: Option<Vec<IpNetwork>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_routes: Option<Vec<IpNetwork>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<u16>,
+}
+
+impl PushSettings {
+    /// Resolve against `base`: every field set here wins over the base one.
+    pub fn resolve(&self, base: &PushSettings) -> NetworkConfig {
+        NetworkConfig {
+            mtu: self.mtu.or(base.mtu),
+            dns: self
+                .dns
+                .as_ref()
+                .or(base.dns.as_ref())
+                .cloned()
+                .unwrap_or_default(),
+            search_domains: self
+                .search_domains
+                .as_ref()
+                .or(base.search_domains.as_ref())
+                .cloned()
+                .unwrap_or_default(),
+            routes: self
+                .routes
+                .as_ref()
+                .or(base.routes.as_ref())
+                .cloned()
+                .unwrap_or_default(),
+            exclude_routes: self
+                .exclude_routes
+                .as_ref()
+                .or(base.exclude_routes.as_ref())
+                .cloned()
+                .unwrap_or_default(),
+            keepalive: self.keepalive.or(base.keepalive),
+        }
+    }
+}
+
+/// `[push]` section: server-wide settings plus per-user overrides under
+/// `[push.users."<public key>"]`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PushConfig {
+    #[serde(flatten)]
+    pub settings: PushSettings,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub users: HashMap<PublicKey, PushSettings>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -76,6 +320,8 @@ pub struct Config {
     pub general: GeneralConfig,
     pub interface: InterfaceConfig,
     pub runtime: Option<RuntimeConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push: Option<PushConfig>,
 }
 
 impl Config {
@@ -104,6 +350,7 @@ impl Default for Config {
             general: GeneralConfig::default(),
             interface: InterfaceConfig::default(),
             runtime: Some(RuntimeConfig::default()),
+            push: None,
         }
     }
 }
@@ -115,6 +362,7 @@ impl Default for GeneralConfig {
             port: 26256,
             secret_key: SecretKey::generate_x25519(),
             storage: PathBuf::from("database"),
+            control: default_control(),
         }
     }
 }
@@ -126,6 +374,8 @@ impl Default for InterfaceConfig {
             mtu: 1420,
             address: IpAddr::from([10, 8, 0, 0]),
             prefix: 24,
+            address6: None,
+            prefix6: default_prefix6(),
             offload: true,
         }
     }
@@ -145,6 +395,7 @@ impl Default for RuntimeConfig {
         Self {
             workers: 0,
             decrypt_workers: 0,
+            handshake_workers: 0,
             so_rcvbuf: 1024 * 1024 * 1024,
             so_sndbuf: 1024 * 1024 * 1024,
             out_udp_buf: 1000,
@@ -153,6 +404,46 @@ impl Default for RuntimeConfig {
             data_udp_buf: 1000,
             data_tun_buf: 1000,
             session: Some(SessionConfig::default()),
+            max_devices: 0,
+            device_policy: DeviceLimitPolicy::default(),
+            lease_grace: 0,
+            users_reload: default_users_reload(),
+            quota_period: QuotaPeriod::default(),
+            upload_limit: Rate::default(),
+            download_limit: Rate::default(),
+            metrics: None,
+            metrics_per_session: false,
+            enroll: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_quota_period_start() {
+        let now = Utc.with_ymd_and_hms(2026, 12, 31, 23, 59, 59).unwrap();
+        assert_eq!(
+            QuotaPeriod::Daily.start(now),
+            Utc.with_ymd_and_hms(2026, 12, 31, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            QuotaPeriod::Monthly.start(now),
+            Utc.with_ymd_and_hms(2026, 12, 1, 0, 0, 0).unwrap()
+        );
+        let midnight = Utc.with_ymd_and_hms(2027, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(QuotaPeriod::Monthly.start(midnight), midnight);
+    }
+
+    #[test]
+    fn test_quota_period_current() {
+        let current = QuotaPeriod::Daily.current();
+        let now = Utc::now();
+        assert!(current <= now && now - current <= chrono::Duration::hours(25));
+        assert_eq!(current.time(), NaiveTime::MIN);
+        assert_eq!(QuotaPeriod::Monthly.current().day(), 1);
+    }
+}
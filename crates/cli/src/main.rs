@@ -28,6 +28,7 @@ async fn main() {
 
     match opt.cmd {
         Commands::Connect(cmd) => cmd.exec().await,
+        Commands::Enroll(cmd) => cmd.exec().await,
         Commands::Server(server_cmd) => {
             let config = match server_cmd.config.exists() {
                 true => match config::Config::load(&server_cmd.config) {
@@ -50,6 +51,7 @@ async fn main() {
             match server_cmd.cmd {
                 ServerCommands::Start(cmd) => cmd.exec(config).await,
                 ServerCommands::Users(cmd) => cmd.exec(config).await,
+                ServerCommands::Sessions(cmd) => cmd.exec(config).await,
             }
         }
     }
@@ -2,13 +2,13 @@
 compile_error!("holynet-cli: network routing is only supported on Linux");
 
 use anyhow::format_err;
+use holynet_sdk::protocol::NetworkConfig;
 use ipnetwork::{IpNetwork, NetworkSize};
 use std::collections::HashSet;
 use std::fmt::Write;
 use std::io;
 use std::net::IpAddr;
 use std::process::{Command, Stdio};
-use std::str::FromStr;
 use tracing::{debug, info, warn};
 
 pub fn find_available_ifname(base_name: &str) -> String {
@@ -39,16 +39,27 @@ pub fn set_ipv4_forwarding(value: bool) -> io::Result<()> {
         .map(|_| ())
 }
 
+pub fn set_ipv6_forwarding(value: bool) -> io::Result<()> {
+    Command::new("sysctl")
+        .arg("-w")
+        .arg(format!(
+            "net.ipv6.conf.all.forwarding={}",
+            if value { 1 } else { 0 }
+        ))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|_| ())
+}
+
 pub struct RouteState {
-    dev: String,
     default_gateway: Option<IpAddr>,
     exclude: Vec<IpNetwork>,
 }
 
 impl RouteState {
-    pub fn new(remote: IpAddr, dev: String) -> Self {
+    pub fn new(remote: IpAddr) -> Self {
         Self {
-            dev,
             default_gateway: None,
             exclude: vec![IpNetwork::from(remote)],
         }
@@ -63,14 +74,6 @@ impl RouteState {
             default_gateway, default_dev_name
         );
 
-        add_route(&IpNetwork::from_str("0.0.0.0/1")?, None, &self.dev, Some(1))?;
-        add_route(
-            &IpNetwork::from_str("128.0.0.0/1")?,
-            None,
-            &self.dev,
-            Some(1),
-        )?;
-
         for addr in self.exclude.iter() {
             add_route(addr, Some(default_gateway), &default_dev_name, None)?;
         }
@@ -88,6 +91,110 @@ impl RouteState {
     }
 }
 
+/// Routes and DNS settings installed from a server-pushed [`NetworkConfig`].
+/// `undo` removes exactly what `apply` managed to install.
+pub struct PushedNetwork {
+    dev: String,
+    routes: Vec<IpNetwork>,
+    exclude: Vec<(IpNetwork, IpAddr)>,
+    dns: bool,
+}
+
+impl PushedNetwork {
+    pub fn apply(dev: &str, config: &NetworkConfig) -> Self {
+        let mut pushed = Self {
+            dev: dev.to_string(),
+            routes: Vec::new(),
+            exclude: Vec::new(),
+            dns: false,
+        };
+
+        for route in &config.routes {
+            match add_route(route, None, dev, Some(1)) {
+                Ok(()) => pushed.routes.push(*route),
+                Err(e) => warn!("add pushed route {}: {}", route, e),
+            }
+        }
+
+        if !config.exclude_routes.is_empty() {
+            match default_device() {
+                Ok((gateway, gateway_dev)) => {
+                    for route in &config.exclude_routes {
+                        match add_route(route, Some(gateway), &gateway_dev, None) {
+                            Ok(()) => pushed.exclude.push((*route, gateway)),
+                            Err(e) => warn!("add pushed exclude route {}: {}", route, e),
+                        }
+                    }
+                }
+                Err(e) => warn!("exclude routes skipped, no default gateway: {}", e),
+            }
+        }
+
+        if !config.dns.is_empty() || !config.search_domains.is_empty() {
+            match set_dns(dev, &config.dns, &config.search_domains) {
+                Ok(()) => pushed.dns = true,
+                Err(e) => warn!("set pushed dns: {}", e),
+            }
+        }
+
+        pushed
+    }
+
+    pub fn undo(&self) {
+        for route in &self.routes {
+            if let Err(e) = delete_dev_route(route, &self.dev) {
+                warn!("delete pushed route {}: {}", route, e);
+            }
+        }
+        for (route, via) in &self.exclude {
+            if let Err(e) = delete_route(route, via) {
+                warn!("delete pushed exclude route {}: {}", route, e);
+            }
+        }
+        if self.dns
+            && let Err(e) = run("resolvectl", &["revert", &self.dev])
+        {
+            warn!("revert dns on {}: {}", self.dev, e);
+        }
+    }
+}
+
+/// Point `dev` at the pushed DNS servers and search domains through
+/// systemd-resolved.
+fn set_dns(dev: &str, servers: &[IpAddr], domains: &[String]) -> anyhow::Result<()> {
+    info!("setting dns on {}: {:?} {:?}", dev, servers, domains);
+    if !servers.is_empty() {
+        let servers: Vec<String> = servers.iter().map(IpAddr::to_string).collect();
+        let mut args = vec!["dns", dev];
+        args.extend(servers.iter().map(String::as_str));
+        run("resolvectl", &args)?;
+    }
+    if !domains.is_empty() {
+        let mut args = vec!["domain", dev];
+        args.extend(domains.iter().map(String::as_str));
+        run("resolvectl", &args)?;
+    }
+    Ok(())
+}
+
+fn run(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    let status = Command::new(program).args(args).status()?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "{} {}: {}",
+            program,
+            args.join(" "),
+            status
+        ));
+    }
+    Ok(())
+}
+
+pub fn delete_dev_route(route: &IpNetwork, dev: &str) -> anyhow::Result<()> {
+    info!("deleting route: {} dev {}", route, dev);
+    run("ip", &["route", "del", &route.to_string(), "dev", dev])
+}
+
 pub fn delete_route(route: &IpNetwork, via: &IpAddr) -> anyhow::Result<()> {
     info!("deleting route: {} via {}", route, via);
     let (formatted, _) = match route.size() {
@@ -1,14 +1,170 @@
+use bincode::error::DecodeError;
 use chrono::{DateTime, Utc};
 use fjall::{Database, Keyspace, KeyspaceCreateOptions};
 use holynet_sdk::crypto::{PublicKey, SecretKey};
+use holynet_sdk::runtime::server::{ClientAuth, ClientPolicy};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use tokio::task;
+use tracing::warn;
 
-#[derive(Serialize, Deserialize)]
+/// Records are this byte followed by the bincode-encoded [`Client`]. Version 1
+/// records have no prefix and start with the length of the PSK, 32.
+const VERSION: u8 = 3;
+const VERSION_2: u8 = 2;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Client {
     pub psk: SecretKey,
     pub peer_pk: PublicKey,
     pub created_at: DateTime<Utc>,
+    /// Unique name the `users` commands accept in place of the key
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub notes: Option<String>,
+    /// Disabled users are refused and their sessions closed
+    pub enabled: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_connected: Option<LastConnected>,
+    /// Bytes per second from the user, overriding `runtime.upload_limit`;
+    /// `Some(0)` is unlimited
+    pub upload_limit: Option<u64>,
+    /// Bytes per second to the user, like `upload_limit`
+    pub download_limit: Option<u64>,
+}
+
+/// The newest session the server opened for a user.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LastConnected {
+    pub at: DateTime<Utc>,
+    pub endpoint: SocketAddr,
+}
+
+/// Layout of version 1 records.
+#[derive(Deserialize)]
+struct ClientV1 {
+    psk: SecretKey,
+    peer_pk: PublicKey,
+    created_at: DateTime<Utc>,
+}
+
+/// Layout of version 2 records.
+#[derive(Deserialize)]
+struct ClientV2 {
+    psk: SecretKey,
+    peer_pk: PublicKey,
+    created_at: DateTime<Utc>,
+    name: Option<String>,
+    email: Option<String>,
+    notes: Option<String>,
+    enabled: bool,
+    expires_at: Option<DateTime<Utc>>,
+    last_connected: Option<LastConnected>,
+}
+
+impl Client {
+    /// An enabled user without metadata, created now.
+    pub fn new(peer_pk: PublicKey, psk: SecretKey) -> Self {
+        Self {
+            psk,
+            peer_pk,
+            created_at: Utc::now(),
+            name: None,
+            email: None,
+            notes: None,
+            enabled: true,
+            expires_at: None,
+            last_connected: None,
+            upload_limit: None,
+            download_limit: None,
+        }
+    }
+
+    /// The name, or the key prefix for unnamed users.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{:.8}", self.peer_pk.to_string()),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires| expires <= Utc::now())
+    }
+
+    /// What the server lets this user do. Pinned addresses are applied
+    /// separately, from the `pinned` keyspace.
+    pub fn auth(&self) -> ClientAuth {
+        ClientAuth {
+            psk: self.psk.clone(),
+            policy: ClientPolicy {
+                allowed: self.enabled,
+                expires_at: self.expires_at.map(Into::into),
+                upload_limit: self.upload_limit,
+                download_limit: self.download_limit,
+                ..ClientPolicy::default()
+            },
+        }
+    }
+}
+
+impl From<ClientV1> for Client {
+    fn from(v1: ClientV1) -> Self {
+        Self {
+            created_at: v1.created_at,
+            ..Client::new(v1.peer_pk, v1.psk)
+        }
+    }
+}
+
+impl From<ClientV2> for Client {
+    fn from(v2: ClientV2) -> Self {
+        Self {
+            psk: v2.psk,
+            peer_pk: v2.peer_pk,
+            created_at: v2.created_at,
+            name: v2.name,
+            email: v2.email,
+            notes: v2.notes,
+            enabled: v2.enabled,
+            expires_at: v2.expires_at,
+            last_connected: v2.last_connected,
+            upload_limit: None,
+            download_limit: None,
+        }
+    }
+}
+
+fn encode(client: &Client) -> Vec<u8> {
+    let mut data = vec![VERSION];
+    bincode::serde::encode_into_std_write(client, &mut data, bincode::config::standard())
+        .expect("serialize client");
+    data
+}
+
+/// Decode a record of any version; `true` marks one in an older layout.
+fn decode(bytes: &[u8]) -> Result<(Client, bool), DecodeError> {
+    let config = bincode::config::standard();
+    match bytes.split_first() {
+        Some((&VERSION, rest)) => {
+            bincode::serde::decode_from_slice(rest, config).map(|(client, _)| (client, false))
+        }
+        Some((&VERSION_2, rest)) => bincode::serde::decode_from_slice::<ClientV2, _>(rest, config)
+            .map(|(v2, _)| (v2.into(), true)),
+        _ => bincode::serde::decode_from_slice::<ClientV1, _>(bytes, config)
+            .map(|(v1, _)| (v1.into(), true)),
+    }
+}
+
+/// Decode the record stored under `key`, logging and skipping it if it is
+/// unreadable.
+fn decode_or_skip(key: &[u8], bytes: &[u8]) -> Option<(Client, bool)> {
+    decode(bytes)
+        .inspect_err(|err| {
+            let key = PublicKey::try_from(key).map_or_else(|_| "?".to_owned(), |pk| pk.to_string());
+            warn!("skipped unreadable user record {}: {}", key, err);
+        })
+        .ok()
 }
 
 #[derive(Clone)]
@@ -27,10 +183,7 @@ impl Clients {
         let key = *pk.as_bytes();
         task::spawn_blocking(move || {
             let bytes = db.get(key.as_slice()).expect("get client from db")?;
-            match bincode::serde::decode_from_slice(&bytes, bincode::config::standard()) {
-                Ok((client, _)) => Some(client),
-                Err(err) => panic!("deserialize client from db: {}", err),
-            }
+            decode_or_skip(&key, &bytes).map(|(client, _)| client)
         })
         .await
         .unwrap()
@@ -40,12 +193,9 @@ impl Clients {
         let db = self.db.clone();
         task::spawn_blocking(move || {
             db.iter()
-                .map(|guard| {
-                    let value = guard.value().expect("failed to read from the db iter");
-                    match bincode::serde::decode_from_slice(&value, bincode::config::standard()) {
-                        Ok((client, _)) => client,
-                        Err(err) => panic!("deserialize client from db: {}", err),
-                    }
+                .filter_map(|guard| {
+                    let (key, value) = guard.into_inner().expect("failed to read from the db iter");
+                    decode_or_skip(&key, &value).map(|(client, _)| client)
                 })
                 .collect()
         })
@@ -53,11 +203,22 @@ impl Clients {
         .unwrap()
     }
 
+    /// Look a user up by public key (base64) or by name.
+    pub async fn find(&self, user: &str) -> Option<Client> {
+        match PublicKey::try_from(user) {
+            Ok(pk) => self.get(&pk).await,
+            Err(_) => self
+                .get_all()
+                .await
+                .into_iter()
+                .find(|client| client.name.as_deref() == Some(user)),
+        }
+    }
+
     pub async fn save(&self, client: Client) {
         let db = self.db.clone();
         let key = *client.peer_pk.as_bytes();
-        let data = bincode::serde::encode_to_vec(&client, bincode::config::standard())
-            .expect("serialize client");
+        let data = encode(&client);
         task::spawn_blocking(move || {
             db.insert(key.as_slice(), &data).expect("save client to db");
         })
@@ -70,4 +231,164 @@ impl Clients {
         let key = *pk.as_bytes();
         task::spawn_blocking(move || db.remove(key.as_slice()).map_err(anyhow::Error::from)).await?
     }
+
+    /// Rewrite records of older versions in the current layout. Returns how
+    /// many were rewritten; unreadable records are left as they are.
+    pub async fn migrate(&self) -> anyhow::Result<usize> {
+        let db = self.db.clone();
+        task::spawn_blocking(move || {
+            let mut legacy = Vec::new();
+            for guard in db.iter() {
+                let (key, value) = guard.into_inner()?;
+                if let Some((client, true)) = decode_or_skip(&key, &value) {
+                    legacy.push((key, client));
+                }
+            }
+            let count = legacy.len();
+            for (key, client) in legacy {
+                db.insert(key, encode(&client))?;
+            }
+            Ok(count)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::database;
+    use chrono::TimeZone;
+
+    fn keys() -> (PublicKey, SecretKey) {
+        let pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        (pk, SecretKey::generate_x25519())
+    }
+
+    fn created() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 3, 14, 15, 9, 26).unwrap()
+    }
+
+    /// A version 1 record: the fields of `ClientV1`, no prefix.
+    fn v1_record(pk: &PublicKey, psk: &SecretKey) -> Vec<u8> {
+        bincode::serde::encode_to_vec((psk, pk, created()), bincode::config::standard()).unwrap()
+    }
+
+    /// A version 2 record: its prefix, then the fields of `ClientV2`.
+    fn v2_record(pk: &PublicKey, psk: &SecretKey) -> Vec<u8> {
+        let last_connected = LastConnected {
+            at: created(),
+            endpoint: "198.51.100.7:26969".parse().unwrap(),
+        };
+        let fields = (
+            psk,
+            pk,
+            created(),
+            Some("alice"),
+            Some("alice@example.com"),
+            None::<String>,
+            false,
+            Some(created()),
+            Some(last_connected),
+        );
+        let mut data = vec![VERSION_2];
+        bincode::serde::encode_into_std_write(fields, &mut data, bincode::config::standard())
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_decode_v1_record() {
+        let (pk, psk) = keys();
+        let (client, legacy) = decode(&v1_record(&pk, &psk)).unwrap();
+        assert!(legacy);
+        assert!(client.peer_pk == pk);
+        assert_eq!(client.psk.as_bytes(), psk.as_bytes());
+        assert_eq!(client.created_at, created());
+        assert!(client.enabled);
+        assert!(client.name.is_none() && client.expires_at.is_none());
+        assert!(client.last_connected.is_none());
+        assert_eq!((client.upload_limit, client.download_limit), (None, None));
+    }
+
+    #[test]
+    fn test_decode_v2_record() {
+        let (pk, psk) = keys();
+        let (client, legacy) = decode(&v2_record(&pk, &psk)).unwrap();
+        assert!(legacy);
+        assert!(client.peer_pk == pk);
+        assert_eq!(client.psk.as_bytes(), psk.as_bytes());
+        assert_eq!(client.name.as_deref(), Some("alice"));
+        assert_eq!(client.email.as_deref(), Some("alice@example.com"));
+        assert!(client.notes.is_none());
+        assert!(!client.enabled);
+        assert_eq!(client.expires_at, Some(created()));
+        let last = client.last_connected.unwrap();
+        assert_eq!(last.endpoint, "198.51.100.7:26969".parse().unwrap());
+        assert_eq!((client.upload_limit, client.download_limit), (None, None));
+    }
+
+    #[test]
+    fn test_decode_current_record() {
+        let (pk, psk) = keys();
+        let client = Client {
+            upload_limit: Some(1000),
+            ..Client::new(pk.clone(), psk)
+        };
+        let (decoded, legacy) = decode(&encode(&client)).unwrap();
+        assert!(!legacy);
+        assert!(decoded.peer_pk == pk);
+        assert_eq!(decoded.upload_limit, Some(1000));
+    }
+
+    #[test]
+    fn test_decode_rejects_garbage() {
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[VERSION, 0xff]).is_err());
+        assert!(decode(&[VERSION_2, 1, 2, 3]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_rewrites_legacy_records() {
+        let path = std::env::temp_dir().join(format!("holynet-clients-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let clients = Clients::new(database(&path).unwrap()).unwrap();
+        let (v1_pk, v1_psk) = keys();
+        let (v2_pk, v2_psk) = keys();
+        let (v3_pk, v3_psk) = keys();
+        let (bad_pk, _) = keys();
+        let db = &clients.db;
+        db.insert(v1_pk.as_slice(), v1_record(&v1_pk, &v1_psk))
+            .unwrap();
+        db.insert(v2_pk.as_slice(), v2_record(&v2_pk, &v2_psk))
+            .unwrap();
+        clients.save(Client::new(v3_pk.clone(), v3_psk)).await;
+        db.insert(bad_pk.as_slice(), [VERSION, 0xff]).unwrap();
+
+        assert_eq!(clients.migrate().await.unwrap(), 2);
+        for pk in [&v1_pk, &v2_pk, &v3_pk] {
+            let bytes = db.get(pk.as_slice()).unwrap().unwrap();
+            assert!(
+                !decode(&bytes).unwrap().1,
+                "rewritten in the current layout"
+            );
+        }
+        let v2 = clients.get(&v2_pk).await.unwrap();
+        assert_eq!(v2.name.as_deref(), Some("alice"));
+        assert_eq!(clients.get(&v1_pk).await.unwrap().created_at, created());
+
+        assert!(clients.get(&bad_pk).await.is_none());
+        assert_eq!(
+            clients.get_all().await.len(),
+            3,
+            "the bad record is skipped"
+        );
+        assert_eq!(clients.migrate().await.unwrap(), 0);
+        assert_eq!(
+            &*db.get(bad_pk.as_slice()).unwrap().unwrap(),
+            &[VERSION, 0xff]
+        );
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
 }
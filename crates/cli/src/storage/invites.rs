@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use fjall::{Database, Keyspace, KeyspaceCreateOptions};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use tokio::task;
+
+/// An enrollment token that has not been used yet. Stored under the token's
+/// `token_id`, never the token itself.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Tunnel address to pin the enrolled user to
+    pub address: Option<IpAddr>,
+}
+
+#[derive(Clone)]
+pub struct Invites {
+    pub db: Keyspace,
+}
+
+impl Invites {
+    pub fn new(db: Database) -> anyhow::Result<Self> {
+        let items = db.keyspace("invites", KeyspaceCreateOptions::default)?;
+        Ok(Self { db: items })
+    }
+
+    pub async fn save(&self, id: [u8; 32], invite: Invite) -> anyhow::Result<()> {
+        let db = self.db.clone();
+        let data = bincode::serde::encode_to_vec(&invite, bincode::config::standard())?;
+        task::spawn_blocking(move || db.insert(id.as_slice(), &data))
+            .await?
+            .map_err(anyhow::Error::from)
+    }
+
+    pub async fn get(&self, id: [u8; 32]) -> anyhow::Result<Option<Invite>> {
+        let db = self.db.clone();
+        task::spawn_blocking(move || {
+            let Some(bytes) = db.get(id.as_slice())? else {
+                return Ok(None);
+            };
+            let (invite, _) =
+                bincode::serde::decode_from_slice(&bytes, bincode::config::standard())?;
+            Ok(Some(invite))
+        })
+        .await?
+    }
+
+    /// Burn the invite `id`, so its token works only once.
+    pub async fn remove(&self, id: [u8; 32]) -> anyhow::Result<()> {
+        let db = self.db.clone();
+        task::spawn_blocking(move || db.remove(id.as_slice()))
+            .await?
+            .map_err(anyhow::Error::from)
+    }
+}
@@ -0,0 +1,81 @@
+use fjall::{Database, Keyspace, KeyspaceCreateOptions};
+use holynet_sdk::crypto::PublicKey;
+use holynet_sdk::runtime::server::session::Lease;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tokio::task;
+
+/// Tunnel address assignments: addresses pinned by the operator, and the
+/// sticky leases the server saves so they survive a restart.
+#[derive(Clone)]
+pub struct Leases {
+    pub pinned: Keyspace,
+    pub sticky: Keyspace,
+}
+
+fn read_all<T: DeserializeOwned>(keyspace: &Keyspace) -> Vec<(PublicKey, T)> {
+    keyspace
+        .iter()
+        .map(|guard| {
+            let (key, value) = guard.into_inner().expect("failed to read from the db iter");
+            let pk = PublicKey::try_from(&*key)
+                .unwrap_or_else(|err| panic!("invalid public key in db: {}", err));
+            match bincode::serde::decode_from_slice(&value, bincode::config::standard()) {
+                Ok((item, _)) => (pk, item),
+                Err(err) => panic!("deserialize lease from db: {}", err),
+            }
+        })
+        .collect()
+}
+
+fn encode<T: Serialize>(item: &T) -> Vec<u8> {
+    bincode::serde::encode_to_vec(item, bincode::config::standard()).expect("serialize lease")
+}
+
+impl Leases {
+    pub fn new(db: Database) -> anyhow::Result<Self> {
+        Ok(Self {
+            pinned: db.keyspace("pinned", KeyspaceCreateOptions::default)?,
+            sticky: db.keyspace("leases", KeyspaceCreateOptions::default)?,
+        })
+    }
+
+    pub async fn pinned(&self) -> HashMap<PublicKey, IpAddr> {
+        let db = self.pinned.clone();
+        task::spawn_blocking(move || read_all(&db).into_iter().collect())
+            .await
+            .unwrap()
+    }
+
+    /// Pin `pk` to `address`, or drop its pin with `None`.
+    pub async fn pin(&self, pk: &PublicKey, address: Option<IpAddr>) -> anyhow::Result<()> {
+        let db = self.pinned.clone();
+        let key = *pk.as_bytes();
+        task::spawn_blocking(move || match address {
+            Some(address) => db.insert(key.as_slice(), encode(&address)),
+            None => db.remove(key.as_slice()),
+        })
+        .await?
+        .map_err(anyhow::Error::from)
+    }
+
+    pub async fn sticky(&self) -> Vec<(PublicKey, Lease)> {
+        let db = self.sticky.clone();
+        task::spawn_blocking(move || read_all(&db)).await.unwrap()
+    }
+
+    /// Replace every saved sticky lease with `leases`.
+    pub async fn save_sticky(&self, leases: Vec<(PublicKey, Lease)>) -> anyhow::Result<()> {
+        let db = self.sticky.clone();
+        task::spawn_blocking(move || {
+            db.clear()?;
+            for (pk, lease) in leases {
+                db.insert(pk.as_bytes().as_slice(), encode(&lease))?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
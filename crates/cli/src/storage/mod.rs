@@ -1,6 +1,12 @@
 mod clients;
+mod invites;
+mod leases;
+mod usage;
 
-pub use clients::{Client, Clients};
+pub use clients::{Client, Clients, LastConnected};
+pub use invites::{Invite, Invites};
+pub use leases::Leases;
+pub use usage::{Usage, Usages};
 
 use fjall::{Config, Database};
 use std::path::Path;
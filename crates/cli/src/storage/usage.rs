@@ -0,0 +1,212 @@
+use chrono::{DateTime, Utc};
+use fjall::{Database, Keyspace, KeyspaceCreateOptions};
+use holynet_sdk::crypto::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::task;
+
+/// Traffic of one user in an accounting period, and their quota.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Usage {
+    /// Start of the period the counters belong to
+    pub period: DateTime<Utc>,
+    /// Bytes from the user
+    pub rx_bytes: u64,
+    /// Bytes to the user
+    pub tx_bytes: u64,
+    /// Bytes allowed per period, both directions together
+    pub quota: Option<u64>,
+}
+
+impl Usage {
+    pub fn total(&self) -> u64 {
+        self.rx_bytes.saturating_add(self.tx_bytes)
+    }
+
+    /// Start the counters over if they belong to a period before `period`.
+    pub fn roll(&mut self, period: DateTime<Utc>) {
+        if self.period != period {
+            self.period = period;
+            self.rx_bytes = 0;
+            self.tx_bytes = 0;
+        }
+    }
+
+    pub fn over_quota(&self) -> bool {
+        self.quota.is_some_and(|quota| self.total() >= quota)
+    }
+}
+
+fn encode(usage: &Usage) -> Vec<u8> {
+    bincode::serde::encode_to_vec(usage, bincode::config::standard()).expect("serialize usage")
+}
+
+fn decode(bytes: &[u8]) -> Usage {
+    match bincode::serde::decode_from_slice(bytes, bincode::config::standard()) {
+        Ok((usage, _)) => usage,
+        Err(err) => panic!("deserialize usage from db: {}", err),
+    }
+}
+
+#[derive(Clone)]
+pub struct Usages {
+    pub db: Keyspace,
+}
+
+impl Usages {
+    pub fn new(db: Database) -> anyhow::Result<Self> {
+        let items = db.keyspace("usage", KeyspaceCreateOptions::default)?;
+        Ok(Self { db: items })
+    }
+
+    /// Usage of `pk` in `period`.
+    pub async fn get(&self, pk: &PublicKey, period: DateTime<Utc>) -> Usage {
+        let db = self.db.clone();
+        let key = *pk.as_bytes();
+        let mut usage = task::spawn_blocking(move || {
+            db.get(key.as_slice())
+                .expect("get usage from db")
+                .map(|bytes| decode(&bytes))
+                .unwrap_or_default()
+        })
+        .await
+        .unwrap();
+        usage.roll(period);
+        usage
+    }
+
+    /// Usage of every user with traffic or a quota, in `period`.
+    pub async fn get_all(&self, period: DateTime<Utc>) -> HashMap<PublicKey, Usage> {
+        let db = self.db.clone();
+        task::spawn_blocking(move || {
+            db.iter()
+                .map(|guard| {
+                    let (key, value) = guard.into_inner().expect("failed to read from the db iter");
+                    let pk = PublicKey::try_from(&*key)
+                        .unwrap_or_else(|err| panic!("invalid public key in db: {}", err));
+                    let mut usage = decode(&value);
+                    usage.roll(period);
+                    (pk, usage)
+                })
+                .collect()
+        })
+        .await
+        .unwrap()
+    }
+
+    pub async fn save(&self, pk: &PublicKey, usage: &Usage) -> anyhow::Result<()> {
+        let db = self.db.clone();
+        let key = *pk.as_bytes();
+        let data = encode(usage);
+        task::spawn_blocking(move || db.insert(key.as_slice(), &data))
+            .await?
+            .map_err(anyhow::Error::from)
+    }
+
+    /// Add `(pk, rx, tx)` bytes to the counters of `period`.
+    pub async fn add(
+        &self,
+        traffic: Vec<(PublicKey, u64, u64)>,
+        period: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let db = self.db.clone();
+        task::spawn_blocking(move || {
+            for (pk, rx, tx) in traffic {
+                let key = pk.as_bytes();
+                let mut usage = db
+                    .get(key.as_slice())?
+                    .map(|b| decode(&b))
+                    .unwrap_or_default();
+                usage.roll(period);
+                usage.rx_bytes = usage.rx_bytes.saturating_add(rx);
+                usage.tx_bytes = usage.tx_bytes.saturating_add(tx);
+                db.insert(key.as_slice(), encode(&usage))?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    pub async fn delete(&self, pk: &PublicKey) -> anyhow::Result<()> {
+        let db = self.db.clone();
+        let key = *pk.as_bytes();
+        task::spawn_blocking(move || db.remove(key.as_slice()).map_err(anyhow::Error::from)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QuotaPeriod;
+    use crate::storage::database;
+    use chrono::TimeZone;
+    use holynet_sdk::crypto::SecretKey;
+
+    fn at(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_roll_starts_over_in_a_new_period() {
+        let october = QuotaPeriod::Monthly.start(at(2026, 10, 31, 23));
+        let mut usage = Usage {
+            period: october,
+            rx_bytes: 10,
+            tx_bytes: 20,
+            quota: Some(25),
+        };
+        usage.roll(QuotaPeriod::Monthly.start(at(2026, 10, 2, 5)));
+        assert_eq!(usage.total(), 30);
+
+        let november = QuotaPeriod::Monthly.start(at(2026, 11, 1, 0));
+        usage.roll(november);
+        assert_eq!((usage.period, usage.total()), (november, 0));
+        assert_eq!(usage.quota, Some(25), "the quota outlives the period");
+    }
+
+    #[test]
+    fn test_over_quota() {
+        let mut usage = Usage {
+            rx_bytes: 60,
+            tx_bytes: 39,
+            ..Usage::default()
+        };
+        assert!(!usage.over_quota(), "no quota is unlimited");
+        usage.quota = Some(100);
+        assert!(!usage.over_quota());
+        usage.tx_bytes += 1;
+        assert!(usage.over_quota());
+        usage.rx_bytes = u64::MAX;
+        assert_eq!(usage.total(), u64::MAX);
+        assert!(usage.over_quota());
+    }
+
+    #[tokio::test]
+    async fn test_add_rolls_stored_counters() {
+        let path = std::env::temp_dir().join(format!("holynet-usages-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let usages = Usages::new(database(&path).unwrap()).unwrap();
+        let pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let (october, november) = (at(2026, 10, 1, 0), at(2026, 11, 1, 0));
+
+        let mut usage = usages.get(&pk, october).await;
+        usage.quota = Some(1000);
+        usages.save(&pk, &usage).await.unwrap();
+        usages
+            .add(vec![(pk.clone(), 100, 50)], october)
+            .await
+            .unwrap();
+        usages.add(vec![(pk.clone(), 1, 1)], october).await.unwrap();
+        assert_eq!(usages.get(&pk, october).await.total(), 152);
+
+        usages
+            .add(vec![(pk.clone(), 5, 0)], november)
+            .await
+            .unwrap();
+        let usage = &usages.get_all(november).await[&pk];
+        assert_eq!((usage.period, usage.total()), (november, 5));
+        assert_eq!(usage.quota, Some(1000));
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
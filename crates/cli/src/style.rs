@@ -71,6 +71,21 @@ pub fn render_config() -> RenderConfig<'static> {
     render_config
 }
 
+/// `1536` → `1.5 KiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
 pub fn format_opaque_bytes(bytes: &[u8]) -> String {
     if bytes.len() < 8 {
         return String::new();
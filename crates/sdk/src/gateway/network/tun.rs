@@ -56,6 +56,12 @@ impl TunNetwork {
         }
     }
 
+    /// Change the interface MTU. Only lowering is safe: buffers were sized
+    /// for the MTU given to `new`.
+    pub fn set_mtu(&self, mtu: u16) -> io::Result<()> {
+        self.device.set_mtu(mtu.min(self.mtu))
+    }
+
     pub fn name(&self) -> io::Result<String> {
         self.device.name()
     }
@@ -137,4 +137,24 @@ pub trait Transport: TransportSender + TransportReceiver {}
 /// Only client transports (`UdpTransport::new`, `WsClientTransport`) implement this.
 pub trait ClientTransport: Transport {
     fn connect<'a>(&'a self) -> impl Future<Output = io::Result<()>> + Send + 'a;
+
+    /// Local address the transport sends from. Transports that cannot tell
+    /// keep the default, which refuses.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "transport cannot report its local address",
+        ))
+    }
+
+    /// Point the transport at another server. Takes effect on the next
+    /// [`connect`](Self::connect) at the latest. Transports tied to one
+    /// server keep the default, which refuses.
+    fn set_endpoint(&self, addr: SocketAddr) -> io::Result<()> {
+        let _ = addr;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "transport cannot change its endpoint",
+        ))
+    }
 }
@@ -6,13 +6,15 @@ use tokio::sync::mpsc;
 use tracing::info;
 
 struct MockTransportInner {
-    tx: mpsc::Sender<Vec<u8>>,
     rx: mpsc::Receiver<Vec<u8>>,
     peer_addr: SocketAddr,
 }
 
+/// The sender lives outside the mutex so sends never wait behind a pending
+/// `recv`, just like a real socket.
 pub struct MockTransport {
     inner: Arc<tokio::sync::Mutex<MockTransportInner>>,
+    tx: mpsc::Sender<Vec<u8>>,
     local_addr: SocketAddr,
 }
 
@@ -28,10 +30,10 @@ impl MockTransport {
 
         MockTransport {
             inner: Arc::new(tokio::sync::Mutex::new(MockTransportInner {
-                tx,
                 rx,
                 peer_addr,
             })),
+            tx,
             local_addr,
         }
     }
@@ -45,19 +47,19 @@ impl MockTransport {
 
         let transport1 = MockTransport {
             inner: Arc::new(tokio::sync::Mutex::new(MockTransportInner {
-                tx: tx1,
                 rx: rx2,
                 peer_addr: addr2,
             })),
+            tx: tx1,
             local_addr: addr1,
         };
 
         let transport2 = MockTransport {
             inner: Arc::new(tokio::sync::Mutex::new(MockTransportInner {
-                tx: tx2,
                 rx: rx1,
                 peer_addr: addr1,
             })),
+            tx: tx2,
             local_addr: addr2,
         };
 
@@ -65,8 +67,9 @@ impl MockTransport {
     }
 
     pub fn create_sender(&self) -> MockTransportSender {
-        let inner = Arc::clone(&self.inner);
-        MockTransportSender { inner }
+        MockTransportSender {
+            tx: self.tx.clone(),
+        }
     }
 
     pub fn local_addr(&self) -> SocketAddr {
@@ -82,35 +85,33 @@ impl MockTransport {
 }
 
 pub struct MockTransportSender {
-    inner: Arc<tokio::sync::Mutex<MockTransportInner>>,
+    tx: mpsc::Sender<Vec<u8>>,
 }
 
 impl MockTransportSender {
     pub async fn send(&self, data: Vec<u8>) -> Result<(), RuntimeError> {
-        let inner = self.inner.lock().await;
-        inner
-            .tx
+        self.tx
             .send(data)
             .await
-            .map_err(|e| RuntimeError::IO(format!("Failed to send data: {}", e)))?;
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::BrokenPipe, e.to_string()))?;
         Ok(())
     }
 }
 
 impl TransportSender for MockTransport {
     async fn send_to(&self, data: &[u8], _addr: &SocketAddr) -> std::io::Result<usize> {
-        let inner = self.inner.lock().await;
-        inner.tx.send(data.to_vec()).await.map_err(|e| {
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Send error: {}", e))
-        })?;
+        self.tx
+            .send(data.to_vec())
+            .await
+            .map_err(|e| std::io::Error::other(format!("Send error: {}", e)))?;
         Ok(data.len())
     }
 
     async fn send(&self, data: &[u8]) -> std::io::Result<usize> {
-        let inner = self.inner.lock().await;
-        inner.tx.send(data.to_vec()).await.map_err(|e| {
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Send error: {}", e))
-        })?;
+        self.tx
+            .send(data.to_vec())
+            .await
+            .map_err(|e| std::io::Error::other(format!("Send error: {}", e)))?;
         Ok(data.len())
     }
 }
@@ -156,12 +157,22 @@ impl ClientTransport for MockTransport {
         info!("MockTransport::connect called - ready for communication");
         Ok(())
     }
+
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    /// The pair is wired together; there is nothing to re-point.
+    fn set_endpoint(&self, _addr: SocketAddr) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 impl Clone for MockTransport {
     fn clone(&self) -> Self {
         MockTransport {
             inner: Arc::clone(&self.inner),
+            tx: self.tx.clone(),
             local_addr: self.local_addr,
         }
     }
@@ -173,7 +184,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_mock_transport_pair() {
-        let (mut transport1, mut transport2) = MockTransport::create_pair();
+        let (transport1, transport2) = MockTransport::create_pair();
+        assert_eq!(transport1.peer_addr(), transport2.local_addr());
 
         let test_data = b"Hello from transport1";
         transport1
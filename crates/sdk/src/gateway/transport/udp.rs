@@ -1,6 +1,6 @@
 use crate::gateway::transport::{ClientTransport, Transport, TransportReceiver, TransportSender};
 use crate::runtime::error::RuntimeError;
-use socket2::{Domain, Protocol, Socket, Type};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Duration;
 use tokio::net::UdpSocket;
@@ -42,9 +42,9 @@ impl UdpTransport {
             socket.set_recv_buffer_size(so_rcvbuf)?;
             socket.set_send_buffer_size(so_sndbuf)?;
             socket.set_tos_v4(0b101110 << 2)?;
-            socket
-                .bind(&addr.into())
-                .map_err(|err| RuntimeError::IO(format!("bind socket #{}: {}", i, err)))?;
+            socket.bind(&addr.into()).map_err(|err| {
+                std::io::Error::new(err.kind(), format!("bind socket #{}: {}", i, err))
+            })?;
 
             sockets.push(Self {
                 socket: UdpSocket::from_std(socket.into())?,
@@ -277,4 +277,14 @@ impl ClientTransport for UdpTransport {
             _ = tokio::time::sleep(Duration::from_secs(5)) => Err(std::io::Error::other("connection timeout"))
         }
     }
+
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Re-connect the socket to `addr`; datagrams from the old server are
+    /// no longer delivered. The address family must match the local bind.
+    fn set_endpoint(&self, addr: SocketAddr) -> std::io::Result<()> {
+        SockRef::from(&self.socket).connect(&addr.into())
+    }
 }
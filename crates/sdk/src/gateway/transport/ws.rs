@@ -171,7 +171,7 @@ type ClientSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>
 type ClientStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
 
 pub struct WsClientTransport {
-    addr: SocketAddr,
+    addr: std::sync::Mutex<SocketAddr>,
     write: Arc<Mutex<Option<ClientSink>>>,
     read: Arc<Mutex<Option<ClientStream>>>,
 }
@@ -179,7 +179,7 @@ pub struct WsClientTransport {
 impl WsClientTransport {
     pub fn new(addr: SocketAddr) -> Self {
         Self {
-            addr,
+            addr: std::sync::Mutex::new(addr),
             write: Arc::new(Mutex::new(None)),
             read: Arc::new(Mutex::new(None)),
         }
@@ -250,8 +250,9 @@ impl Transport for WsClientTransport {}
 
 impl ClientTransport for WsClientTransport {
     async fn connect(&self) -> io::Result<()> {
-        info!("connecting to ws://{}", self.addr);
-        let request = format!("ws://{}", self.addr)
+        let addr = *self.addr.lock().unwrap();
+        info!("connecting to ws://{}", addr);
+        let request = format!("ws://{}", addr)
             .into_client_request()
             .map_err(|e| io::Error::other(e.to_string()))?;
 
@@ -263,4 +264,9 @@ impl ClientTransport for WsClientTransport {
 
         Ok(())
     }
+
+    fn set_endpoint(&self, addr: SocketAddr) -> io::Result<()> {
+        *self.addr.lock().unwrap() = addr;
+        Ok(())
+    }
 }
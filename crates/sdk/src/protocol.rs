@@ -1,20 +1,31 @@
 mod data;
 pub mod handshake;
+mod network;
 mod primitives;
 mod session;
 pub(crate) mod varint;
 
 use bincode::{Decode, Encode};
 use bytes::Bytes;
-pub use data::{DataClientBody, DataServerBody};
+pub use data::{DataClientBody, DataServerBody, DisconnectReason};
 pub(crate) use data::{DataClientBodyRef, DataServerBodyRef};
-pub use handshake::{HandshakeError, HandshakeResponderBody, HandshakeResponderPayload};
+pub use handshake::{
+    HandshakeError, HandshakeInitiatorPayload, HandshakeResponderBody, HandshakeResponderPayload,
+    Rekey, Tai64N,
+};
+pub use network::{NETWORK_CONFIG_VERSION, NetworkConfig};
 use primitives::VecU16;
 pub use session::{Alg, SessionId};
 use varint::{read_u16, read_u32};
 
 pub type EncryptedHandshake = VecU16<u8>;
 
+/// Wire type byte of a cookie reply (see [`PacketRef`]).
+pub(crate) const COOKIE_REPLY_TYPE: u8 = 4;
+/// Cookie reply body after the type byte: 24-byte nonce, then the 16-byte
+/// cookie sealed with XChaCha20-Poly1305.
+pub(crate) const COOKIE_REPLY_LEN: usize = 56;
+
 /// Backed by `bytes::Bytes` (Arc-counted) so that passing through channels
 /// is a cheap pointer move.
 ///
@@ -80,7 +91,8 @@ impl<'de, Context> bincode::BorrowDecode<'de, Context> for EncryptedData {
 /// Data frames are **not** part of this enum: they use a fixed-size header and
 /// are encoded/parsed manually (see `runtime::crypto` and [`PacketRef`]) so a
 /// batch of equal-size packets stays byte-uniform for UDP GSO. The wire type
-/// bytes are shared across both: 0/1 = handshakes, 2 = DataClient, 3 = DataServer.
+/// bytes are shared across both: 0/1 = handshakes, 2 = DataClient, 3 = DataServer,
+/// 4 = cookie reply (see `runtime::cookie`).
 #[derive(Decode, Encode)]
 pub enum Packet {
     HandshakeInitial(EncryptedHandshake),
@@ -115,20 +127,24 @@ impl Packet {
 /// - Handshakes (type 0/1): `varint-u32 type | varint-u16 len | raw bytes`.
 /// - Data (type 2/3): fixed header, then ciphertext to the **end of the
 ///   datagram** (no length field):
-///   - DataClient: `type(1) | sid(u32 BE) | nonce(u64 BE) | ciphertext`
-///   - DataServer: `type(1) | nonce(u64 BE) | ciphertext`
+///   - DataClient: `type(1) | epoch(u8) | sid(u32 BE) | nonce(u64 BE) | ciphertext`
+///   - DataServer: `type(1) | epoch(u8) | nonce(u64 BE) | ciphertext`
+/// - Cookie reply (type 4): `type(1) | nonce(24) | sealed cookie(32)`.
 pub(crate) enum PacketRef<'a> {
     HandshakeInitial(&'a [u8]),
     HandshakeResponder(&'a [u8]),
     DataClient {
+        epoch: u8,
         sid: SessionId,
         nonce: u64,
         ciphertext: &'a [u8],
     },
     DataServer {
+        epoch: u8,
         nonce: u64,
         ciphertext: &'a [u8],
     },
+    CookieReply(&'a [u8; COOKIE_REPLY_LEN]),
 }
 
 impl<'a> PacketRef<'a> {
@@ -150,22 +166,30 @@ impl<'a> PacketRef<'a> {
                 Some(PacketRef::HandshakeResponder(data))
             }
             2 => {
-                // DataClient: fixed [sid: u32 BE | nonce: u64 BE] then ciphertext.
-                let sid = u32::from_be_bytes(buf.get(..4)?.try_into().ok()?);
-                let nonce = u64::from_be_bytes(buf.get(4..12)?.try_into().ok()?);
-                let ciphertext = buf.get(12..)?;
+                // DataClient: fixed [epoch | sid: u32 BE | nonce: u64 BE] then ciphertext.
+                let epoch = *buf.first()?;
+                let sid = u32::from_be_bytes(buf.get(1..5)?.try_into().ok()?);
+                let nonce = u64::from_be_bytes(buf.get(5..13)?.try_into().ok()?);
+                let ciphertext = buf.get(13..)?;
                 Some(PacketRef::DataClient {
+                    epoch,
                     sid,
                     nonce,
                     ciphertext,
                 })
             }
             3 => {
-                // DataServer: fixed [nonce: u64 BE] then ciphertext.
-                let nonce = u64::from_be_bytes(buf.get(..8)?.try_into().ok()?);
-                let ciphertext = buf.get(8..)?;
-                Some(PacketRef::DataServer { nonce, ciphertext })
+                // DataServer: fixed [epoch | nonce: u64 BE] then ciphertext.
+                let epoch = *buf.first()?;
+                let nonce = u64::from_be_bytes(buf.get(1..9)?.try_into().ok()?);
+                let ciphertext = buf.get(9..)?;
+                Some(PacketRef::DataServer {
+                    epoch,
+                    nonce,
+                    ciphertext,
+                })
             }
+            4 => Some(PacketRef::CookieReply(buf.try_into().ok()?)),
             _ => None,
         }
     }
@@ -199,16 +223,16 @@ mod tests {
     }
 
     // Build fixed-header data frames by hand (mirrors runtime::crypto encoders).
-    fn data_client_frame(sid: u32, nonce: u64, cipher: &[u8]) -> Vec<u8> {
-        let mut v = vec![2u8];
+    fn data_client_frame(epoch: u8, sid: u32, nonce: u64, cipher: &[u8]) -> Vec<u8> {
+        let mut v = vec![2u8, epoch];
         v.extend_from_slice(&sid.to_be_bytes());
         v.extend_from_slice(&nonce.to_be_bytes());
         v.extend_from_slice(cipher);
         v
     }
 
-    fn data_server_frame(nonce: u64, cipher: &[u8]) -> Vec<u8> {
-        let mut v = vec![3u8];
+    fn data_server_frame(epoch: u8, nonce: u64, cipher: &[u8]) -> Vec<u8> {
+        let mut v = vec![3u8, epoch];
         v.extend_from_slice(&nonce.to_be_bytes());
         v.extend_from_slice(cipher);
         v
@@ -234,14 +258,16 @@ mod tests {
     #[test]
     fn test_packet_ref_data_client() {
         let cipher = vec![0xAAu8; 32];
-        let raw = data_client_frame(42, 999, &cipher);
+        let raw = data_client_frame(7, 42, 999, &cipher);
 
         match PacketRef::from_bytes(&raw).unwrap() {
             PacketRef::DataClient {
+                epoch,
                 sid,
                 nonce,
                 ciphertext,
             } => {
+                assert_eq!(epoch, 7);
                 assert_eq!(sid, 42);
                 assert_eq!(nonce, 999);
                 assert_eq!(ciphertext, &cipher[..]);
@@ -253,10 +279,15 @@ mod tests {
     #[test]
     fn test_packet_ref_data_server() {
         let cipher = vec![0xBBu8; 48];
-        let raw = data_server_frame(12345, &cipher);
+        let raw = data_server_frame(3, 12345, &cipher);
 
         match PacketRef::from_bytes(&raw).unwrap() {
-            PacketRef::DataServer { nonce, ciphertext } => {
+            PacketRef::DataServer {
+                epoch,
+                nonce,
+                ciphertext,
+            } => {
+                assert_eq!(epoch, 3);
                 assert_eq!(nonce, 12345);
                 assert_eq!(ciphertext, &cipher[..]);
             }
@@ -267,14 +298,16 @@ mod tests {
     #[test]
     fn test_packet_ref_large_ciphertext() {
         let cipher = vec![0xCCu8; 1416]; // typical MTU-sized encrypted packet
-        let raw = data_client_frame(0xDEAD_BEEF, u64::MAX, &cipher);
+        let raw = data_client_frame(u8::MAX, 0xDEAD_BEEF, u64::MAX, &cipher);
 
         match PacketRef::from_bytes(&raw).unwrap() {
             PacketRef::DataClient {
+                epoch,
                 sid,
                 nonce,
                 ciphertext,
             } => {
+                assert_eq!(epoch, u8::MAX);
                 assert_eq!(sid, 0xDEAD_BEEF);
                 assert_eq!(nonce, u64::MAX);
                 assert_eq!(ciphertext, &cipher[..]);
@@ -283,10 +316,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_packet_ref_cookie_reply() {
+        let mut raw = vec![COOKIE_REPLY_TYPE];
+        raw.extend_from_slice(&[0xDDu8; COOKIE_REPLY_LEN]);
+        match PacketRef::from_bytes(&raw).unwrap() {
+            PacketRef::CookieReply(body) => assert_eq!(body, &[0xDDu8; COOKIE_REPLY_LEN]),
+            _ => panic!("wrong variant"),
+        }
+        assert!(PacketRef::from_bytes(&raw[..COOKIE_REPLY_LEN]).is_none());
+    }
+
     #[test]
     fn test_packet_ref_truncated_returns_none() {
-        // A DataClient header needs 13 bytes; 3 is far too short.
-        let raw = data_client_frame(1, 0, &[0xAAu8; 32]);
+        // A DataClient header needs 14 bytes; 3 is far too short.
+        let raw = data_client_frame(0, 1, 0, &[0xAAu8; 32]);
         assert!(PacketRef::from_bytes(&raw[..3]).is_none());
     }
 }
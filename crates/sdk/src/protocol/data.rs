@@ -1,16 +1,89 @@
+use std::fmt;
+
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
 use super::varint::{read_u32, read_u128, read_usize};
 
+/// Why the server closed a session.
+///
+/// Travels as a single `u8` code inside `DataServerBody::Disconnect`. Codes this
+/// build does not know decode to `Unknown`, so older clients still disconnect
+/// cleanly when a newer server adds a reason.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(from = "u8", into = "u8")]
+pub enum DisconnectReason {
+    /// The server is shutting down.
+    Shutdown,
+    /// The user's credentials were removed from the server.
+    Revoked,
+    /// The session was dropped after a period without traffic.
+    IdleTimeout,
+    /// An operator closed the session.
+    Kicked,
+    /// The session keys can no longer be used; a fresh handshake is needed.
+    RekeyRequired,
+    /// The user's traffic quota is used up.
+    QuotaExceeded,
+    /// A newer device of the same user took this session's slot.
+    Replaced,
+    /// A code not known to this build.
+    Unknown(u8),
+}
+
+impl From<u8> for DisconnectReason {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => DisconnectReason::Shutdown,
+            1 => DisconnectReason::Revoked,
+            2 => DisconnectReason::IdleTimeout,
+            3 => DisconnectReason::Kicked,
+            4 => DisconnectReason::RekeyRequired,
+            5 => DisconnectReason::QuotaExceeded,
+            6 => DisconnectReason::Replaced,
+            code => DisconnectReason::Unknown(code),
+        }
+    }
+}
+
+impl From<DisconnectReason> for u8 {
+    fn from(reason: DisconnectReason) -> Self {
+        match reason {
+            DisconnectReason::Shutdown => 0,
+            DisconnectReason::Revoked => 1,
+            DisconnectReason::IdleTimeout => 2,
+            DisconnectReason::Kicked => 3,
+            DisconnectReason::RekeyRequired => 4,
+            DisconnectReason::QuotaExceeded => 5,
+            DisconnectReason::Replaced => 6,
+            DisconnectReason::Unknown(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::Shutdown => write!(f, "server shutdown"),
+            DisconnectReason::Revoked => write!(f, "credentials revoked"),
+            DisconnectReason::IdleTimeout => write!(f, "idle timeout"),
+            DisconnectReason::Kicked => write!(f, "kicked by operator"),
+            DisconnectReason::RekeyRequired => write!(f, "rekey required"),
+            DisconnectReason::QuotaExceeded => write!(f, "quota exceeded"),
+            DisconnectReason::Replaced => write!(f, "replaced by another device"),
+            DisconnectReason::Unknown(code) => write!(f, "unknown reason {}", code),
+        }
+    }
+}
+
 /// Bodies encrypted inside a Noise transport message.
 #[derive(Serialize, Deserialize)]
 pub enum DataServerBody {
     Packet(Bytes),
     /// Contains the client's timestamp (microseconds since process start)
     KeepAlive(u128),
-    /// Contains the shutdown initiation code
-    Disconnect(u8),
+    /// Tells the client why its session was closed
+    Disconnect(DisconnectReason),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -18,6 +91,8 @@ pub enum DataClientBody {
     Packet(Bytes),
     /// Contains timestamp (microseconds since process start)
     KeepAlive(u128),
+    /// The client is leaving; the server may free the session at once
+    Disconnect,
 }
 
 // Zero-copy borrowed views decoded from PLAIN_BUF
@@ -35,6 +110,7 @@ pub enum DataClientBody {
 pub(crate) enum DataClientBodyRef<'a> {
     Packet(&'a [u8]),
     KeepAlive(u128),
+    Disconnect,
 }
 
 impl<'a> DataClientBodyRef<'a> {
@@ -54,6 +130,8 @@ impl<'a> DataClientBodyRef<'a> {
                 let (ts, _) = read_u128(buf)?;
                 Some(DataClientBodyRef::KeepAlive(ts))
             }
+            // Disconnect — a unit variant, nothing follows the tag
+            2 => Some(DataClientBodyRef::Disconnect),
             _ => None,
         }
     }
@@ -63,7 +141,7 @@ impl<'a> DataClientBodyRef<'a> {
 pub(crate) enum DataServerBodyRef<'a> {
     Packet(&'a [u8]),
     KeepAlive(u128),
-    Disconnect(u8),
+    Disconnect(DisconnectReason),
 }
 
 impl<'a> DataServerBodyRef<'a> {
@@ -82,9 +160,9 @@ impl<'a> DataServerBodyRef<'a> {
                 Some(DataServerBodyRef::KeepAlive(ts))
             }
             2 => {
-                // Disconnect(u8) — u8 is always 1 byte in bincode
+                // Disconnect(DisconnectReason) — a u8 code, always 1 byte in bincode
                 let (&code, _) = buf.split_first()?;
-                Some(DataServerBodyRef::Disconnect(code))
+                Some(DataServerBodyRef::Disconnect(code.into()))
             }
             _ => None,
         }
@@ -162,19 +240,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_client_disconnect_roundtrip() {
+        let enc = encode_client(&DataClientBody::Disconnect);
+        assert!(matches!(
+            DataClientBodyRef::from_plain_buf(&enc),
+            Some(DataClientBodyRef::Disconnect)
+        ));
+    }
+
     #[test]
     fn test_server_disconnect_roundtrip() {
         for code in [0u8, 1, 42, 255] {
-            let body = DataServerBody::Disconnect(code);
+            let body = DataServerBody::Disconnect(code.into());
             let enc = encode_server(&body);
             let dec = DataServerBodyRef::from_plain_buf(&enc).unwrap();
             match dec {
-                DataServerBodyRef::Disconnect(c) => assert_eq!(c, code),
+                DataServerBodyRef::Disconnect(reason) => assert_eq!(u8::from(reason), code),
                 _ => panic!("wrong variant"),
             }
         }
     }
 
+    #[test]
+    fn test_disconnect_reason_codes() {
+        let known = [
+            DisconnectReason::Shutdown,
+            DisconnectReason::Revoked,
+            DisconnectReason::IdleTimeout,
+            DisconnectReason::Kicked,
+            DisconnectReason::RekeyRequired,
+            DisconnectReason::QuotaExceeded,
+            DisconnectReason::Replaced,
+        ];
+        for (code, reason) in known.into_iter().enumerate() {
+            assert_eq!(DisconnectReason::from(code as u8), reason);
+            assert_eq!(u8::from(reason), code as u8);
+        }
+        assert_eq!(DisconnectReason::from(200), DisconnectReason::Unknown(200));
+    }
+
     #[test]
     fn test_large_payload_1400_bytes() {
         let payload = vec![0xABu8; 1400];
@@ -1,10 +1,12 @@
 use super::Alg;
+use super::network::NetworkConfig;
 use super::session::SessionId;
 use serde::{Deserialize, Serialize};
 use snow::params::NoiseParams;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::str::FromStr;
-use std::sync::LazyLock;
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Single-byte algorithm hint prepended to every `HandshakeInitial` payload.
 ///
@@ -38,6 +40,88 @@ pub fn params_from_alg(alg: &Alg) -> &'static NoiseParams {
     }
 }
 
+/// Payload carried inside the (encrypted) `HandshakeInitial` message.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HandshakeInitiatorPayload {
+    /// Re-key an existing session instead of creating a new one
+    pub rekey: Option<Rekey>,
+    /// When the initiation was created. The server drops initiations that are
+    /// not newer than the last one it accepted from the same key.
+    pub timestamp: Tai64N,
+    /// Session this client held before reconnecting. The server releases it
+    /// if it still belongs to the same key.
+    pub previous: Option<SessionId>,
+}
+
+impl HandshakeInitiatorPayload {
+    /// Payload stamped with [`Tai64N::now`].
+    pub fn new(rekey: Option<Rekey>) -> Self {
+        Self {
+            rekey,
+            timestamp: Tai64N::now(),
+            previous: None,
+        }
+    }
+
+    /// Ask the server to release `previous` when this handshake succeeds.
+    pub fn replacing(mut self, previous: Option<SessionId>) -> Self {
+        self.previous = previous;
+        self
+    }
+}
+
+/// `2^62 + 10`: TAI64 label of the Unix epoch (TAI was 10s ahead of UTC).
+const TAI64_EPOCH: u64 = 0x4000_0000_0000_000a;
+
+/// Last value handed out by [`Tai64N::now`].
+static LAST_TAI64N: Mutex<Tai64N> = Mutex::new(Tai64N { secs: 0, nanos: 0 });
+
+/// TAI64N timestamp: TAI64 seconds label and nanoseconds.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tai64N {
+    secs: u64,
+    nanos: u32,
+}
+
+impl Tai64N {
+    /// Current time, strictly greater than every value returned before in
+    /// this process even if the wall clock steps back.
+    pub fn now() -> Self {
+        let mut last = LAST_TAI64N.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Self::from_system_time(SystemTime::now()).max(last.next());
+        *last = now;
+        now
+    }
+
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self {
+            secs: TAI64_EPOCH + since_epoch.as_secs(),
+            nanos: since_epoch.subsec_nanos(),
+        }
+    }
+
+    fn next(self) -> Self {
+        match self.nanos + 1 {
+            1_000_000_000 => Self {
+                secs: self.secs + 1,
+                nanos: 0,
+            },
+            nanos => Self {
+                secs: self.secs,
+                nanos,
+            },
+        }
+    }
+}
+
+/// Request to move session `sid` onto fresh transport keys under `epoch`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Rekey {
+    pub sid: SessionId,
+    pub epoch: u8,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum HandshakeResponderBody {
     Complete(HandshakeResponderPayload),
@@ -48,14 +132,51 @@ pub enum HandshakeResponderBody {
 pub struct HandshakeResponderPayload {
     pub sid: SessionId,
     pub ipaddr: IpAddr,
+    /// Second tunnel address when the server runs dual-stack
+    pub ipv6: Option<Ipv6Addr>,
+    /// Tunnel settings pushed by the server
+    pub network: NetworkConfig,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, thiserror::Error)]
 pub enum HandshakeError {
     /// Server limit on connected devices per credential
+    #[error("max connected devices: {0}")]
     MaxConnectedDevices(u32),
     /// No available IP addresses or session identifiers
+    #[error("server overloaded")]
     ServerOverloaded,
     /// Malformed request
+    #[error("unexpected server error: {0}")]
     Unexpected(String),
+    /// The client is disabled or expired
+    #[error("access denied")]
+    Denied,
+    /// The client's traffic quota is used up until the next period
+    #[error("traffic quota exceeded")]
+    QuotaExceeded,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_tai64n_orders_by_time() {
+        let earlier = Tai64N::from_system_time(UNIX_EPOCH + Duration::new(100, 999_999_999));
+        let later = Tai64N::from_system_time(UNIX_EPOCH + Duration::from_secs(101));
+        assert!(earlier < later);
+        assert_eq!(earlier.next(), later);
+    }
+
+    #[test]
+    fn test_tai64n_now_is_strictly_increasing() {
+        let mut last = Tai64N::now();
+        for _ in 0..1000 {
+            let now = Tai64N::now();
+            assert!(now > last);
+            last = now;
+        }
+    }
 }
@@ -0,0 +1,162 @@
+use std::net::IpAddr;
+
+use bytes::Bytes;
+use ipnetwork::IpNetwork;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Layout version of [`NetworkConfig`] written by this build.
+pub const NETWORK_CONFIG_VERSION: u8 = 1;
+
+const TAG_MTU: u8 = 0;
+const TAG_DNS: u8 = 1;
+const TAG_SEARCH_DOMAINS: u8 = 2;
+const TAG_ROUTES: u8 = 3;
+const TAG_EXCLUDE_ROUTES: u8 = 4;
+const TAG_KEEPALIVE: u8 = 5;
+
+/// Tunnel settings the server pushes to the client in
+/// [`HandshakeResponderPayload`](super::HandshakeResponderPayload).
+///
+/// Empty fields are not pushed and leave the client's own defaults in place.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(into = "TaggedOptions", try_from = "TaggedOptions")]
+pub struct NetworkConfig {
+    /// Tunnel MTU
+    pub mtu: Option<u16>,
+    /// DNS servers to use while connected
+    pub dns: Vec<IpAddr>,
+    /// DNS search domains
+    pub search_domains: Vec<String>,
+    /// Networks to route through the tunnel; empty means everything
+    pub routes: Vec<IpNetwork>,
+    /// Networks to keep outside the tunnel
+    pub exclude_routes: Vec<IpNetwork>,
+    /// Suggested keepalive interval in seconds
+    pub keepalive: Option<u16>,
+}
+
+/// Wire form of [`NetworkConfig`]: a version byte and `(tag, value)` pairs,
+/// each value bincode-encoded on its own. Decoders skip tags they do not know,
+/// so a server can push new options without breaking older clients.
+#[derive(Serialize, Deserialize)]
+struct TaggedOptions {
+    version: u8,
+    options: Vec<(u8, Bytes)>,
+}
+
+fn encode_option<T: Serialize>(options: &mut Vec<(u8, Bytes)>, tag: u8, value: &T) {
+    let bytes = bincode::serde::encode_to_vec(value, bincode::config::standard())
+        .expect("network option encodes");
+    options.push((tag, bytes.into()));
+}
+
+fn decode_option<T: DeserializeOwned>(tag: u8, value: &[u8]) -> Result<T, String> {
+    bincode::serde::decode_from_slice(value, bincode::config::standard())
+        .map(|(value, _)| value)
+        .map_err(|e| format!("network option {}: {}", tag, e))
+}
+
+impl From<NetworkConfig> for TaggedOptions {
+    fn from(config: NetworkConfig) -> Self {
+        let mut options = Vec::new();
+        if let Some(mtu) = config.mtu {
+            encode_option(&mut options, TAG_MTU, &mtu);
+        }
+        if !config.dns.is_empty() {
+            encode_option(&mut options, TAG_DNS, &config.dns);
+        }
+        if !config.search_domains.is_empty() {
+            encode_option(&mut options, TAG_SEARCH_DOMAINS, &config.search_domains);
+        }
+        if !config.routes.is_empty() {
+            encode_option(&mut options, TAG_ROUTES, &config.routes);
+        }
+        if !config.exclude_routes.is_empty() {
+            encode_option(&mut options, TAG_EXCLUDE_ROUTES, &config.exclude_routes);
+        }
+        if let Some(keepalive) = config.keepalive {
+            encode_option(&mut options, TAG_KEEPALIVE, &keepalive);
+        }
+        TaggedOptions {
+            version: NETWORK_CONFIG_VERSION,
+            options,
+        }
+    }
+}
+
+impl TryFrom<TaggedOptions> for NetworkConfig {
+    type Error = String;
+
+    fn try_from(tagged: TaggedOptions) -> Result<Self, Self::Error> {
+        let mut config = NetworkConfig::default();
+        for (tag, value) in tagged.options {
+            match tag {
+                TAG_MTU => config.mtu = Some(decode_option(tag, &value)?),
+                TAG_DNS => config.dns = decode_option(tag, &value)?,
+                TAG_SEARCH_DOMAINS => config.search_domains = decode_option(tag, &value)?,
+                TAG_ROUTES => config.routes = decode_option(tag, &value)?,
+                TAG_EXCLUDE_ROUTES => config.exclude_routes = decode_option(tag, &value)?,
+                TAG_KEEPALIVE => config.keepalive = Some(decode_option(tag, &value)?),
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(config: &NetworkConfig) -> NetworkConfig {
+        let bytes = bincode::serde::encode_to_vec(config, bincode::config::standard()).unwrap();
+        bincode::serde::decode_from_slice(&bytes, bincode::config::standard())
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_full_config_roundtrip() {
+        let config = NetworkConfig {
+            mtu: Some(1380),
+            dns: vec!["10.8.0.1".parse().unwrap(), "fd00::1".parse().unwrap()],
+            search_domains: vec!["corp.example".into()],
+            routes: vec!["10.0.0.0/8".parse().unwrap()],
+            exclude_routes: vec!["10.1.0.0/16".parse().unwrap()],
+            keepalive: Some(25),
+        };
+        assert_eq!(roundtrip(&config), config);
+    }
+
+    #[test]
+    fn test_empty_config_is_two_bytes() {
+        let bytes =
+            bincode::serde::encode_to_vec(NetworkConfig::default(), bincode::config::standard())
+                .unwrap();
+        assert_eq!(bytes, [NETWORK_CONFIG_VERSION, 0]);
+    }
+
+    #[test]
+    fn test_unknown_tags_are_skipped() {
+        let tagged = TaggedOptions {
+            version: NETWORK_CONFIG_VERSION + 1,
+            options: vec![
+                (200, Bytes::from_static(b"from a newer server")),
+                (
+                    TAG_MTU,
+                    bincode::serde::encode_to_vec(1280u16, bincode::config::standard())
+                        .unwrap()
+                        .into(),
+                ),
+            ],
+        };
+        let bytes = bincode::serde::encode_to_vec(&tagged, bincode::config::standard()).unwrap();
+        let config: NetworkConfig =
+            bincode::serde::decode_from_slice(&bytes, bincode::config::standard())
+                .unwrap()
+                .0;
+        assert_eq!(config.mtu, Some(1280));
+        assert!(config.routes.is_empty());
+    }
+}
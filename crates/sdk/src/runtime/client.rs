@@ -1,13 +1,25 @@
 mod connector;
+mod event;
+mod handle;
 mod keepalive;
 mod network;
 mod network_pool;
+mod reconnect;
 mod recv;
 mod recv_pool;
+mod rekey;
+mod stats;
+
+pub use self::event::ClientEvent;
+pub use self::handle::ClientHandle;
+pub use self::reconnect::{Backoff, ReconnectAction, ReconnectPolicy};
+pub use self::stats::{ClientStats, StatsReader};
+
+pub(crate) use self::stats::Counters;
 
 use std::{sync::Arc, time::Duration};
 
-use tokio::sync::watch;
+use tokio::sync::{broadcast, mpsc, watch};
 use tokio::task::JoinSet;
 use tracing::{debug, warn};
 
@@ -17,7 +29,9 @@ use crate::{
     runtime::{
         client::{
             keepalive::keepalive_sender, network::encrypt_forward, recv::recv_decrypt_forward,
+            rekey::rekey_executor,
         },
+        cookie::CookieJar,
         cred::Cred,
         error::{BuildError, RuntimeError},
         state::RuntimeState,
@@ -26,14 +40,40 @@ use crate::{
 
 pub(super) const AWAIT_STATE_DELAY: Duration = Duration::from_secs(1);
 pub(super) const MAX_PACKET_SIZE: usize = 65536;
+/// Rekey responses and cookie replies buffered between the receive task and
+/// the rekey task.
+const REKEY_QUEUE_CAP: usize = 4;
+/// Events buffered per subscriber before the slowest one starts losing them.
+const EVENT_BUFFER: usize = 256;
+
+/// Credentials for the server the client talks to, with the cookie jar keyed
+/// by its public key. Replaced as a whole by [`ClientHandle::reconnect_to`].
+#[derive(Clone)]
+pub(crate) struct Target {
+    pub(crate) cred: Cred,
+    pub(crate) cookies: Arc<CookieJar>,
+}
+
+impl Target {
+    pub(crate) fn new(cred: Cred) -> Self {
+        Self {
+            cookies: Arc::new(CookieJar::new(&cred.spk)),
+            cred,
+        }
+    }
+}
 
 pub struct ClientBuilder<T: ClientTransport + 'static, N: Network + 'static> {
     transport: Arc<T>,
     network: Arc<N>,
     alg: Option<Alg>,
     keepalive: Option<Duration>,
+    dead_peer_after: Option<u32>,
     handshake_timeout: Duration,
-    reconnect_delay: Duration,
+    backoff: Backoff,
+    reconnect_policy: ReconnectPolicy,
+    rekey_after_time: Option<Duration>,
+    rekey_after_messages: u64,
     cred: Option<Cred>,
     encrypt_workers: usize,
     decrypt_workers: usize,
@@ -46,8 +86,12 @@ impl<T: ClientTransport + 'static, N: Network + 'static> ClientBuilder<T, N> {
             network: Arc::new(network),
             alg: None,
             keepalive: Some(Duration::from_secs(15)),
+            dead_peer_after: Some(3),
             handshake_timeout: Duration::from_secs(5),
-            reconnect_delay: Duration::from_secs(3),
+            backoff: Backoff::default(),
+            reconnect_policy: ReconnectPolicy::default(),
+            rekey_after_time: Some(Duration::from_secs(120)),
+            rekey_after_messages: 1 << 60,
             cred: None,
             encrypt_workers: 0,
             decrypt_workers: 0,
@@ -61,18 +105,50 @@ impl<T: ClientTransport + 'static, N: Network + 'static> ClientBuilder<T, N> {
     }
 
     /// Set keepalive interval. Useful when behind NAT. `None` disables it.
+    /// While enabled, an interval pushed by the server takes precedence.
     pub fn keepalive(mut self, value: Option<Duration>) -> Self {
         self.keepalive = value;
         self
     }
 
+    /// Handshake again after this many keepalives in a row got no
+    /// authenticated packet back from the server. Needs keepalive enabled;
+    /// `None` turns detection off.
+    pub fn dead_peer_after(mut self, missed: Option<u32>) -> Self {
+        self.dead_peer_after = missed.filter(|&missed| missed > 0);
+        self
+    }
+
     pub fn handshake_timeout(mut self, value: Duration) -> Self {
         self.handshake_timeout = value;
         self
     }
 
-    pub fn reconnect_delay(mut self, value: Duration) -> Self {
-        self.reconnect_delay = value;
+    /// Delays between failed handshakes, when to give up, and whether the
+    /// first connect may be retried at all.
+    pub fn backoff(mut self, value: Backoff) -> Self {
+        self.backoff = value;
+        self
+    }
+
+    /// Decide per [`DisconnectReason`](crate::protocol::DisconnectReason)
+    /// whether to stop, back off or reconnect at once after the server
+    /// disconnects the client.
+    pub fn reconnect_policy(mut self, value: ReconnectPolicy) -> Self {
+        self.reconnect_policy = value;
+        self
+    }
+
+    /// Rotate session keys once they are this old. `None` disables time-based
+    /// rekeying.
+    pub fn rekey_after_time(mut self, value: Option<Duration>) -> Self {
+        self.rekey_after_time = value;
+        self
+    }
+
+    /// Rotate session keys after this many packets were sealed under them.
+    pub fn rekey_after_messages(mut self, value: u64) -> Self {
+        self.rekey_after_messages = value;
         self
     }
 
@@ -101,17 +177,24 @@ impl<T: ClientTransport + 'static, N: Network + 'static> ClientBuilder<T, N> {
 
     pub fn build(self) -> Result<Client<T, N>, BuildError> {
         let (state, _) = watch::channel(RuntimeState::Connecting);
+        let cred = self.cred.ok_or(BuildError::MissingRequiredField("cred"))?;
         Ok(Client {
             transport: self.transport,
             network: self.network,
             alg: self.alg.unwrap_or_default(),
             keepalive: self.keepalive,
+            dead_peer_after: self.dead_peer_after,
             handshake_timeout: self.handshake_timeout,
-            reconnect_delay: self.reconnect_delay,
-            cred: self.cred.ok_or(BuildError::MissingRequiredField("cred"))?,
+            backoff: self.backoff,
+            reconnect_policy: self.reconnect_policy,
+            rekey_after_time: self.rekey_after_time,
+            rekey_after_messages: self.rekey_after_messages,
+            target: watch::channel(Target::new(cred)).0,
             encrypt_workers: self.encrypt_workers,
             decrypt_workers: self.decrypt_workers,
             state,
+            stats: Arc::default(),
+            events: broadcast::channel(EVENT_BUFFER).0,
         })
     }
 }
@@ -121,12 +204,18 @@ pub struct Client<T: ClientTransport + 'static, N: Network + 'static> {
     network: Arc<N>,
     alg: Alg,
     keepalive: Option<Duration>,
+    dead_peer_after: Option<u32>,
     handshake_timeout: Duration,
-    reconnect_delay: Duration,
-    cred: Cred,
+    backoff: Backoff,
+    reconnect_policy: ReconnectPolicy,
+    rekey_after_time: Option<Duration>,
+    rekey_after_messages: u64,
+    target: watch::Sender<Target>,
     encrypt_workers: usize,
     decrypt_workers: usize,
     state: watch::Sender<RuntimeState>,
+    stats: Arc<Counters>,
+    events: broadcast::Sender<ClientEvent>,
 }
 
 impl<T: ClientTransport + 'static, N: Network + 'static> Client<T, N> {
@@ -134,8 +223,38 @@ impl<T: ClientTransport + 'static, N: Network + 'static> Client<T, N> {
         self.state.subscribe()
     }
 
+    /// Control handle to stop, pause or re-target the client while it runs.
+    pub fn handle(&self) -> ClientHandle<T> {
+        ClientHandle {
+            state: self.state.clone(),
+            transport: self.transport.clone(),
+            target: self.target.clone(),
+            events: self.events.clone(),
+        }
+    }
+
+    /// Subscribe to [`ClientEvent`]s: handshake attempts and their outcome,
+    /// RTT samples, roaming, disconnect notices from the server and rekeys.
+    /// Unlike the [`RuntimeState`] watch, short-lived transitions are not
+    /// lost; a receiver more than `EVENT_BUFFER` events behind gets
+    /// [`RecvError::Lagged`](broadcast::error::RecvError::Lagged) instead.
+    pub fn events(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    /// Reader for traffic and link-quality statistics. Stays valid after
+    /// [`run`](Self::run) takes the client.
+    pub fn stats(&self) -> StatsReader {
+        StatsReader(self.stats.clone())
+    }
+
+    /// Run until the client stops: after [`ClientHandle::stop`] with
+    /// [`RuntimeError::StopSignal`], otherwise with the error that ended it.
     pub async fn run(self) -> Result<std::convert::Infallible, RuntimeError> {
         let mut set: JoinSet<()> = JoinSet::new();
+        // Rekey responses arrive on the data socket, so the receive path hands
+        // them over to the rekey task.
+        let (handshake_tx, handshake_rx) = mpsc::channel(REKEY_QUEUE_CAP);
 
         // Hot path 1: UDP → decrypt → network. With >= 2 decrypt workers, spread
         // one flow's decryption across cores via the pool; else single-task.
@@ -144,6 +263,7 @@ impl<T: ClientTransport + 'static, N: Network + 'static> Client<T, N> {
                 self.state.clone(),
                 self.transport.clone(),
                 self.network.clone(),
+                handshake_tx,
                 self.decrypt_workers,
             ));
         } else {
@@ -151,6 +271,7 @@ impl<T: ClientTransport + 'static, N: Network + 'static> Client<T, N> {
                 self.state.clone(),
                 self.transport.clone(),
                 self.network.clone(),
+                handshake_tx,
             ));
         }
 
@@ -178,18 +299,34 @@ impl<T: ClientTransport + 'static, N: Network + 'static> Client<T, N> {
                 self.state.clone(),
                 self.transport.clone(),
                 duration,
+                self.dead_peer_after,
             ));
         } else {
             debug!("keepalive disabled");
         }
 
+        // Rekey: rotates session keys while connected
+        set.spawn(rekey_executor(
+            self.state.clone(),
+            self.transport.clone(),
+            handshake_rx,
+            self.target.subscribe(),
+            self.alg.clone(),
+            self.rekey_after_time,
+            self.rekey_after_messages,
+            self.handshake_timeout,
+        ));
+
         // Connector: handles connect + handshake + reconnect
         set.spawn(connector::executor(
             self.state.clone(),
             self.transport.clone(),
-            self.cred,
+            self.target.subscribe(),
             self.alg,
-            self.reconnect_delay,
+            self.stats,
+            self.events,
+            self.backoff,
+            self.reconnect_policy,
             self.handshake_timeout,
         ));
 
@@ -1,81 +1,161 @@
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::watch;
+use tokio::sync::{broadcast, watch};
 use tracing::{debug, error};
 
 use crate::gateway::transport::ClientTransport;
 use crate::protocol::Alg;
-use crate::runtime::cred::Cred;
+use crate::runtime::client::{
+    Backoff, ClientEvent, Counters, ReconnectAction, ReconnectPolicy, Target,
+};
 use crate::runtime::error::RuntimeError;
 use crate::runtime::handshake::handshake_step;
 use crate::runtime::state::{ClientSession, RuntimeState};
 
+/// Sleep for `delay`, or less if the state changes meanwhile.
+async fn wait(state_rx: &mut watch::Receiver<RuntimeState>, delay: Duration) {
+    let _ = tokio::time::timeout(delay, state_rx.changed()).await;
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn executor<T: ClientTransport>(
     state: watch::Sender<RuntimeState>,
     transport: Arc<T>,
-    cred: Cred,
+    mut target: watch::Receiver<Target>,
     alg: Alg,
-    reconnect_delay: Duration,
+    stats: Arc<Counters>,
+    events: broadcast::Sender<ClientEvent>,
+    backoff: Backoff,
+    policy: ReconnectPolicy,
     timeout: Duration,
 ) {
     let mut state_rx = state.subscribe();
     state_rx.mark_changed();
-    let mut ticker = tokio::time::interval(reconnect_delay);
     let mut is_reconnect = false;
+    // Handshakes failed in a row.
+    let mut failures = 0;
+    // Last session we held, handed to the server on reconnect so it can drop it.
+    let mut previous = None;
+    // Local address of the last session, to notice the client moving networks.
+    let mut local_addr = None;
+    let emit = |event| {
+        let _ = events.send(event);
+    };
 
     loop {
         match state_rx.changed().await {
             Ok(_) => {
                 let current = state_rx.borrow().clone();
                 match current {
-                    RuntimeState::Connecting => match transport.connect().await {
-                        Ok(_) => {
-                            match handshake_step(transport.clone(), &cred, &alg, timeout).await {
-                                Ok((payload, transport_state)) => {
-                                    is_reconnect = true;
-                                    state
-                                        .send(RuntimeState::Connected((
-                                            payload,
-                                            ClientSession::new(transport_state),
-                                        )))
-                                        .expect("broken runtime state pipe");
-                                    continue;
-                                }
-                                Err(err) => match is_reconnect {
-                                    false => {
-                                        state
-                                            .send(RuntimeState::Error(err))
-                                            .expect("broken runtime state pipe");
-                                        return;
-                                    }
-                                    true => {
-                                        error!("{}, trying again in {:?}", err, reconnect_delay);
-                                        state_rx.mark_changed();
-                                        ticker.tick().await;
-                                    }
-                                },
-                            }
+                    RuntimeState::Connecting => {
+                        // A new server knows nothing of our last session.
+                        if target.has_changed().unwrap_or(false) {
+                            previous = None;
                         }
-                        Err(err) => match is_reconnect {
-                            false => {
-                                state
-                                    .send(RuntimeState::Error(RuntimeError::IO(format!(
-                                        "connecting error: {}",
-                                        err
-                                    ))))
-                                    .expect("broken runtime state pipe");
-                                return;
+                        let current = target.borrow_and_update().clone();
+                        emit(ClientEvent::HandshakeStarted);
+                        let result = match transport.connect().await {
+                            Ok(_) => {
+                                handshake_step(
+                                    transport.clone(),
+                                    &current.cred,
+                                    &alg,
+                                    &current.cookies,
+                                    previous,
+                                    timeout,
+                                )
+                                .await
                             }
-                            true => {
-                                error!(
-                                    "failed to reconnect: {}, trying again in {:?}",
-                                    err, reconnect_delay
+                            Err(err) => Err(err.into()),
+                        };
+                        match result {
+                            Ok((payload, transport_state)) => {
+                                failures = 0;
+                                stats.handshake_completed();
+                                if is_reconnect {
+                                    stats.reconnected();
+                                }
+                                is_reconnect = true;
+                                if let Ok(to) = transport.local_addr()
+                                    && let Some(from) = local_addr.replace(to)
+                                    && from != to
+                                {
+                                    emit(ClientEvent::Roamed { from, to });
+                                }
+                                let completed = ClientEvent::HandshakeCompleted {
+                                    payload: payload.clone(),
+                                    alg: alg.clone(),
+                                };
+                                let session = ClientSession::new(
+                                    transport_state,
+                                    stats.clone(),
+                                    events.clone(),
                                 );
+                                // Paused or stopped while the handshake ran.
+                                let connected = state.send_if_modified(|current| {
+                                    let connecting = matches!(current, RuntimeState::Connecting);
+                                    if connecting {
+                                        *current = RuntimeState::Connected((payload, session));
+                                    }
+                                    connecting
+                                });
+                                if connected {
+                                    emit(completed);
+                                }
+                            }
+                            Err(err) => {
+                                failures += 1;
+                                // Once connected, nothing short of a stop ends
+                                // the client: the server may just be restarting.
+                                let retry_in = match is_reconnect || err.is_retryable() {
+                                    true => backoff.retry_in(failures, is_reconnect),
+                                    false => None,
+                                };
+                                emit(ClientEvent::HandshakeFailed {
+                                    error: err.clone(),
+                                    retry_in,
+                                });
+                                let Some(delay) = retry_in else {
+                                    state
+                                        .send(RuntimeState::Error(err))
+                                        .expect("broken runtime state pipe");
+                                    return;
+                                };
+                                error!("{}, trying again in {:?}", err, delay);
+                                wait(&mut state_rx, delay).await;
                                 state_rx.mark_changed();
-                                ticker.tick().await;
                             }
-                        },
+                        }
+                    }
+                    RuntimeState::Disconnected(reason) => match policy.action(reason) {
+                        ReconnectAction::Stop => {
+                            state
+                                .send(RuntimeState::Error(RuntimeError::Disconnected(reason)))
+                                .expect("broken runtime state pipe");
+                            return;
+                        }
+                        ReconnectAction::Backoff => {
+                            let delay = backoff.delay(0);
+                            debug!("{}, reconnecting in {:?}", reason, delay);
+                            wait(&mut state_rx, delay).await;
+                            state_rx.mark_changed();
+                            // Leave any state set during the delay (e.g. an error) alone.
+                            state.send_if_modified(|current| {
+                                let disconnected = matches!(current, RuntimeState::Disconnected(_));
+                                if disconnected {
+                                    *current = RuntimeState::Connecting;
+                                }
+                                disconnected
+                            });
+                        }
+                        ReconnectAction::Immediate => {
+                            debug!("{}, reconnecting now", reason);
+                            state
+                                .send(RuntimeState::Connecting)
+                                .expect("broken runtime state pipe");
+                        }
                     },
+                    RuntimeState::Connected((payload, _)) => previous = Some(payload.sid),
                     RuntimeState::Error(_) => {
                         debug!("connector executor stopped by error state");
                         break;
@@ -0,0 +1,34 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::protocol::{Alg, DisconnectReason, HandshakeResponderPayload, SessionId};
+use crate::runtime::error::RuntimeError;
+
+/// Something that happened in the client runtime, published in order to every
+/// [`Client::events`](super::Client::events) subscriber. Unlike
+/// [`RuntimeState`](crate::runtime::state::RuntimeState), short-lived
+/// transitions such as a failed handshake followed by a retry are not lost.
+#[derive(Clone, Debug)]
+pub enum ClientEvent {
+    /// An initiation for a new session is going out.
+    HandshakeStarted,
+    /// The server accepted the handshake; the session is up.
+    HandshakeCompleted {
+        payload: HandshakeResponderPayload,
+        alg: Alg,
+    },
+    /// Connecting or handshaking failed. `retry_in` is `None` when the client
+    /// gives up.
+    HandshakeFailed {
+        error: RuntimeError,
+        retry_in: Option<Duration>,
+    },
+    /// Round-trip time of one keepalive echo, before smoothing.
+    RttSample(Duration),
+    /// A new session runs from another local address than the last one.
+    Roamed { from: SocketAddr, to: SocketAddr },
+    /// The server closed the session.
+    DisconnectReceived(DisconnectReason),
+    /// Session `sid` rotated its keys to `epoch`.
+    Rekeyed { sid: SessionId, epoch: u8 },
+}
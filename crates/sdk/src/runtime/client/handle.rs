@@ -0,0 +1,126 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, warn};
+
+use crate::gateway::transport::ClientTransport;
+use crate::protocol::{DataClientBody, SessionId};
+use crate::runtime::client::{ClientEvent, Target};
+use crate::runtime::cred::Cred;
+use crate::runtime::crypto::{encode_data_client_frame, noise_encrypt};
+use crate::runtime::error::RuntimeError;
+use crate::runtime::state::{ClientSession, RuntimeState};
+
+/// Cloneable control handle for a [`Client`](super::Client).
+///
+/// Obtained via [`Client::handle`](super::Client::handle) before calling
+/// `run`, and stays valid for the whole lifetime of the client. Every method
+/// that leaves a live session first tells the server, so it frees the session
+/// at once instead of waiting for it to time out.
+pub struct ClientHandle<T: ClientTransport> {
+    pub(super) state: watch::Sender<RuntimeState>,
+    pub(super) transport: Arc<T>,
+    pub(super) target: watch::Sender<Target>,
+    pub(super) events: broadcast::Sender<ClientEvent>,
+}
+
+impl<T: ClientTransport> Clone for ClientHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            transport: self.transport.clone(),
+            target: self.target.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<T: ClientTransport> ClientHandle<T> {
+    /// Subscribe to runtime events, like
+    /// [`Client::events`](super::Client::events).
+    pub fn events(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    /// Close the session and stop the client. `Client::run` then returns
+    /// [`RuntimeError::StopSignal`]. No-op once the client has stopped.
+    pub async fn stop(&self) {
+        if self
+            .switch(RuntimeState::Error(RuntimeError::StopSignal))
+            .await
+        {
+            info!("client stopped");
+        }
+    }
+
+    /// Close the session and idle until [`resume`](Self::resume). The TUN and
+    /// the transport stay open; nothing is sent or received meanwhile.
+    /// `false` when the client has already stopped.
+    pub async fn pause(&self) -> bool {
+        self.switch(RuntimeState::Paused).await
+    }
+
+    /// Handshake again after [`pause`](Self::pause). `false` when the client
+    /// is not paused.
+    pub fn resume(&self) -> bool {
+        self.state.send_if_modified(|current| {
+            let paused = matches!(current, RuntimeState::Paused);
+            if paused {
+                *current = RuntimeState::Connecting;
+            }
+            paused
+        })
+    }
+
+    /// Close the session and handshake with the server at `endpoint` using
+    /// `cred`, keeping the TUN and the transport. Also resumes a paused
+    /// client. When the transport cannot move to `endpoint` the client is
+    /// left paused and the error returned.
+    pub async fn reconnect_to(&self, cred: Cred, endpoint: SocketAddr) -> Result<(), RuntimeError> {
+        if let RuntimeState::Error(err) = &*self.state.borrow() {
+            return Err(err.clone());
+        }
+        // Pause first: the goodbye must reach the old server, and no task may
+        // handshake with it while the transport is re-pointed.
+        self.switch(RuntimeState::Paused).await;
+        self.transport.set_endpoint(endpoint)?;
+        self.target.send_replace(Target::new(cred));
+        info!("switching to server {}", endpoint);
+        self.resume();
+        Ok(())
+    }
+
+    /// Move to `next` unless the client has stopped, then send `Disconnect`
+    /// for the session that was up, if any. `false` when stopped.
+    async fn switch(&self, next: RuntimeState) -> bool {
+        let mut previous = None;
+        let switched = self.state.send_if_modified(|current| {
+            if matches!(current, RuntimeState::Error(_)) {
+                return false;
+            }
+            previous = Some(std::mem::replace(current, next));
+            true
+        });
+        if let Some(RuntimeState::Connected((payload, session))) = previous {
+            self.send_disconnect(payload.sid, &session).await;
+        }
+        switched
+    }
+
+    async fn send_disconnect(&self, sid: SessionId, session: &ClientSession) {
+        let key = session.keys.current();
+        let nonce = key.next_nonce();
+        match noise_encrypt(&DataClientBody::Disconnect, &key.noise, nonce) {
+            Err(e) => warn!("failed to encrypt disconnect: {}", e),
+            Ok(encrypted) => {
+                let mut buf = [0u8; 128];
+                let n = encode_data_client_frame(key.epoch, sid, nonce, &encrypted, &mut buf);
+                match self.transport.send(&buf[..n]).await {
+                    Ok(_) => debug!("session {} closed", sid),
+                    Err(e) => debug!("failed to send disconnect: {}", e),
+                }
+            }
+        }
+    }
+}
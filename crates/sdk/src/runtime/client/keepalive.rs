@@ -1,6 +1,11 @@
 //! Keepalive sender task (client side).
 //!
-//! Sends encrypted keepalive packets at a fixed interval.
+//! Sends encrypted keepalive packets at a fixed interval: the one the server
+//! pushed in the handshake response, or the configured one otherwise.
+//!
+//! Also detects a dead server: the server echoes every keepalive, so when
+//! `dead_after` keepalives in a row pass without any authenticated packet
+//! coming back, the client moves to `Connecting` and handshakes again.
 //!
 //! ## Zero-allocation hot path
 //!
@@ -13,7 +18,6 @@
 
 use std::ops::Deref;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use tokio::sync::watch;
@@ -31,6 +35,7 @@ pub(super) async fn keepalive_sender<T: ClientTransport>(
     state_tx: watch::Sender<RuntimeState>,
     transport: Arc<T>,
     duration: Duration,
+    dead_after: Option<u32>,
 ) {
     let mut state_rx = state_tx.subscribe();
     let mut encode_buf = [0u8; MAX_PACKET_SIZE + 64];
@@ -40,6 +45,9 @@ pub(super) async fn keepalive_sender<T: ClientTransport>(
     let mut is_connected = false;
     let mut sid = SessionId::default();
     let mut transport_state: Option<ClientSession> = None;
+    // When the last keepalive went out, and how many went unanswered in a row.
+    let mut last_sent: Option<u64> = None;
+    let mut missed = 0u32;
 
     loop {
         // Check for state changes without blocking.
@@ -48,7 +56,9 @@ pub(super) async fn keepalive_sender<T: ClientTransport>(
                 state_rx.mark_unchanged();
                 match state_rx.borrow().deref() {
                     RuntimeState::Error(_) => break,
-                    RuntimeState::Connecting => {
+                    RuntimeState::Connecting
+                    | RuntimeState::Disconnected(_)
+                    | RuntimeState::Paused => {
                         is_connected = false;
                         transport_state = None;
                     }
@@ -56,6 +66,15 @@ pub(super) async fn keepalive_sender<T: ClientTransport>(
                         sid = payload.sid;
                         transport_state = Some(session.clone());
                         is_connected = true;
+                        last_sent = None;
+                        missed = 0;
+                        let interval = match payload.network.keepalive {
+                            Some(secs) if secs > 0 => Duration::from_secs(secs.into()),
+                            _ => duration,
+                        };
+                        if interval != keepalive_timer.period() {
+                            keepalive_timer = tokio::time::interval(interval);
+                        }
                     }
                     _ => {}
                 }
@@ -75,15 +94,27 @@ pub(super) async fn keepalive_sender<T: ClientTransport>(
             }
             _ = keepalive_timer.tick() => {
                 let Some(ref session) = transport_state else { continue; };
-                let nonce = session.send_nonce.fetch_add(1, Ordering::Relaxed);
-                match noise_encrypt(&DataClientBody::KeepAlive(micros_since_start()), &session.noise, nonce) {
+                if let Some(sent) = last_sent {
+                    missed = if session.last_recv() < sent { missed + 1 } else { 0 };
+                }
+                if dead_after.is_some_and(|limit| missed >= limit) {
+                    warn!("no reply to {} keepalives, reconnecting", missed);
+                    is_connected = false;
+                    transport_state = None;
+                    if state_tx.send(RuntimeState::Connecting).is_err() { break; }
+                    continue;
+                }
+                last_sent = Some(micros_since_start() as u64);
+                let key = session.keys.current();
+                let nonce = key.next_nonce();
+                match noise_encrypt(&DataClientBody::KeepAlive(micros_since_start()), &key.noise, nonce) {
                     Err(e) => {
                         if state_tx.send(RuntimeState::Error(
-                            RuntimeError::Unexpected(format!("failed to encrypt keepalive: {}", e))
+                            RuntimeError::crypto(e)
                         )).is_err() { break; }
                     }
                     Ok(encrypted) => {
-                        let n = encode_data_client_frame(sid, nonce, &encrypted, &mut encode_buf);
+                        let n = encode_data_client_frame(key.epoch, sid, nonce, &encrypted, &mut encode_buf);
                         if let Err(e) = transport.send(&encode_buf[..n]).await {
                             warn!("keepalive send error, reconnecting: {}", e);
                             if state_tx.send(RuntimeState::Connecting).is_err() { break; }
@@ -94,3 +125,80 @@ pub(super) async fn keepalive_sender<T: ClientTransport>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::gateway::transport::TransportReceiver;
+    use crate::gateway::transport::mock::MockTransport;
+    use crate::protocol::handshake::HandshakeResponderPayload;
+    use crate::runtime::crypto::make_noise_pair_for_test;
+    use tokio::sync::broadcast;
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Start the keepalive task and connect it. Returns the state channel, the
+    /// session and a counter of keepalives that reached the server.
+    async fn connected(
+        dead_after: Option<u32>,
+    ) -> (watch::Sender<RuntimeState>, ClientSession, Arc<AtomicUsize>) {
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let (client_state, _) = make_noise_pair_for_test();
+        let session = ClientSession::new(client_state, Default::default(), broadcast::channel(1).0);
+        let (state_tx, _) = watch::channel(RuntimeState::Connecting);
+        tokio::spawn(keepalive_sender(
+            state_tx.clone(),
+            Arc::new(client_tp),
+            INTERVAL,
+            dead_after,
+        ));
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 256];
+            while server_tp.recv(&mut buf).await.is_ok() {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        // Let the task subscribe before the state changes.
+        tokio::task::yield_now().await;
+
+        let payload = HandshakeResponderPayload {
+            sid: 1,
+            ipaddr: "10.8.0.2".parse().unwrap(),
+            ipv6: None,
+            network: Default::default(),
+        };
+        state_tx.send_replace(RuntimeState::Connected((payload, session.clone())));
+        (state_tx, session, received)
+    }
+
+    #[tokio::test]
+    async fn test_silent_server_triggers_reconnect() {
+        let (state_tx, _session, received) = connected(Some(3)).await;
+
+        let mut state_rx = state_tx.subscribe();
+        tokio::time::timeout(
+            AWAIT_STATE_DELAY * 3,
+            state_rx.wait_for(|state| matches!(state, RuntimeState::Connecting)),
+        )
+        .await
+        .expect("client must give up on a silent server")
+        .unwrap();
+        assert_eq!(received.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_answered_keepalives_keep_the_session() {
+        let (state_tx, session, received) = connected(Some(3)).await;
+
+        // Authenticated traffic keeps arriving faster than keepalives go out.
+        while received.load(Ordering::Relaxed) < 10 {
+            session.touch();
+            tokio::time::sleep(INTERVAL / 2).await;
+        }
+        assert!(matches!(*state_tx.borrow(), RuntimeState::Connected(_)));
+    }
+}
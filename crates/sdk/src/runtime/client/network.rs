@@ -13,7 +13,6 @@
 
 use std::ops::Deref;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
 
 use tokio::sync::watch;
 use tracing::warn;
@@ -91,7 +90,7 @@ pub(super) async fn encrypt_forward<T: ClientTransport, N: Network>(
             _ = state_rx.changed() => {
                 match state_rx.borrow().deref() {
                     RuntimeState::Error(_) => break,
-                    RuntimeState::Connecting => {
+                    RuntimeState::Connecting | RuntimeState::Disconnected(_) | RuntimeState::Paused => {
                         is_connected = false;
                         transport_state = None;
                     }
@@ -105,9 +104,7 @@ pub(super) async fn encrypt_forward<T: ClientTransport, N: Network>(
             }
             result = network.recv_multiple(&mut orig, &mut bufs, &mut sizes, 0) => match result {
                 Err(e) => {
-                    let state = RuntimeState::Error(RuntimeError::IO(
-                        format!("failed to receive from network: {}", e)
-                    ));
+                    let state = RuntimeState::Error(RuntimeError::tun(e));
                     if state_tx.send(state).is_err() { break; }
                 }
                 Ok(count) => {
@@ -122,14 +119,16 @@ pub(super) async fn encrypt_forward<T: ClientTransport, N: Network>(
                         if pkt.is_empty() {
                             continue;
                         }
-                        let nonce = session.send_nonce.fetch_add(1, Ordering::Relaxed);
-                        match encode_data_client_packet(pkt, sid, &session.noise, nonce, &mut gso_buf[off..]) {
+                        let key = session.keys.current();
+                        let nonce = key.next_nonce();
+                        match encode_data_client_packet(pkt, sid, &key, nonce, &mut gso_buf[off..]) {
                             Err(e) => {
                                 if state_tx.send(RuntimeState::Error(
-                                    RuntimeError::Unexpected(format!("failed to encrypt data: {}", e))
+                                    RuntimeError::crypto(e)
                                 )).is_err() { break 'main; }
                             }
                             Ok(n) => {
+                                session.stats.sent(pkt.len());
                                 frames.push((off, n));
                                 off += n;
                             }
@@ -20,7 +20,6 @@
 
 use std::ops::Deref;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
 
 use tokio::sync::{mpsc, watch};
 use tokio::task::JoinSet;
@@ -32,6 +31,7 @@ use crate::protocol::SessionId;
 use crate::runtime::client::AWAIT_STATE_DELAY;
 use crate::runtime::crypto::encode_data_client_packet;
 use crate::runtime::error::RuntimeError;
+use crate::runtime::keys::TransportKey;
 use crate::runtime::state::{ClientSession, RuntimeState};
 
 /// In-flight slots per worker.
@@ -47,7 +47,8 @@ struct Slot {
     ip_len: usize,
     nonce: u64,
     sid: SessionId,
-    session: Option<ClientSession>,
+    /// Key the nonce was drawn from; the worker seals with it.
+    key: Option<Arc<TransportKey>>,
     /// Encoded `DataClient` datagram (`[..out_len]`), filled by the worker.
     out: Vec<u8>,
     out_len: usize,
@@ -63,7 +64,7 @@ impl Slot {
             ip_len: 0,
             nonce: 0,
             sid: SessionId::default(),
-            session: None,
+            key: None,
             out: vec![0u8; cap + 64],
             out_len: 0,
             ok: false,
@@ -168,7 +169,7 @@ async fn reader<N: Network>(
             _ = state_rx.changed() => {
                 match state_rx.borrow().deref() {
                     RuntimeState::Error(_) => break,
-                    RuntimeState::Connecting => {
+                    RuntimeState::Connecting | RuntimeState::Disconnected(_) | RuntimeState::Paused => {
                         is_connected = false;
                         session = None;
                     }
@@ -182,9 +183,7 @@ async fn reader<N: Network>(
             }
             result = network.recv_multiple(&mut orig, &mut bufs, &mut sizes, 0) => match result {
                 Err(e) => {
-                    let st = RuntimeState::Error(RuntimeError::IO(
-                        format!("failed to receive from network: {}", e)
-                    ));
+                    let st = RuntimeState::Error(RuntimeError::tun(e));
                     if state_tx.send(st).is_err() { break; }
                 }
                 Ok(count) => {
@@ -203,11 +202,15 @@ async fn reader<N: Network>(
                             }
                             s = free_rx.recv() => match s { Some(s) => s, None => break 'main },
                         };
+                        // Counted when handed to the workers; a failed seal
+                        // stops the client anyway.
+                        sess.stats.sent(sizes[i]);
                         std::mem::swap(&mut slot.ip, &mut bufs[i]);
                         slot.ip_len = sizes[i];
                         slot.sid = sid;
-                        slot.nonce = sess.send_nonce.fetch_add(1, Ordering::Relaxed);
-                        slot.session = Some(sess.clone());
+                        let key = sess.keys.current();
+                        slot.nonce = key.next_nonce();
+                        slot.key = Some(key);
                         slot.ok = false;
                         slot.seq = seq;
                         let k = (seq % w) as usize;
@@ -232,7 +235,7 @@ async fn worker(
 ) {
     while let Some(mut slot) = work_rx.recv().await {
         slot.ok = false;
-        if let Some(session) = slot.session.clone() {
+        if let Some(key) = slot.key.take() {
             let ip_len = slot.ip_len;
             let nonce = slot.nonce;
             let sid = slot.sid;
@@ -241,16 +244,13 @@ async fn worker(
                 let s = &mut *slot;
                 (&s.ip[..ip_len], &mut s.out)
             };
-            match encode_data_client_packet(ip, sid, &session.noise, nonce, out) {
+            match encode_data_client_packet(ip, sid, &key, nonce, out) {
                 Ok(n) => {
                     slot.out_len = n;
                     slot.ok = true;
                 }
                 Err(e) => {
-                    let _ = state_tx.send(RuntimeState::Error(RuntimeError::Unexpected(format!(
-                        "failed to encrypt data: {}",
-                        e
-                    ))));
+                    let _ = state_tx.send(RuntimeState::Error(RuntimeError::crypto(e)));
                 }
             }
         }
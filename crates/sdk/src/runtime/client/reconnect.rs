@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use crate::protocol::DisconnectReason;
+
+/// What the client does after the server disconnects it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReconnectAction {
+    /// Give up: the client moves to `RuntimeState::Error` and `run` returns.
+    Stop,
+    /// Wait the first [`Backoff`] delay, then handshake again.
+    Backoff,
+    /// Handshake again at once.
+    Immediate,
+}
+
+/// Maps a [`DisconnectReason`] to a [`ReconnectAction`].
+///
+/// Defaults: `Revoked` and `Replaced` stop the client, `IdleTimeout` and
+/// `RekeyRequired` reconnect immediately, everything else backs off.
+/// Reconnecting after `Replaced` would only push another device of the same
+/// user out in turn. After `QuotaExceeded` the handshakes fail with
+/// [`HandshakeError::QuotaExceeded`](crate::protocol::HandshakeError::QuotaExceeded)
+/// and are retried with [`Backoff`] until the quota period starts over.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    rules: Vec<(DisconnectReason, ReconnectAction)>,
+    fallback: ReconnectAction,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                (DisconnectReason::Revoked, ReconnectAction::Stop),
+                (DisconnectReason::Replaced, ReconnectAction::Stop),
+                (DisconnectReason::IdleTimeout, ReconnectAction::Immediate),
+                (DisconnectReason::RekeyRequired, ReconnectAction::Immediate),
+            ],
+            fallback: ReconnectAction::Backoff,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Override the action for one reason.
+    pub fn on(mut self, reason: DisconnectReason, action: ReconnectAction) -> Self {
+        self.rules.retain(|(r, _)| *r != reason);
+        self.rules.push((reason, action));
+        self
+    }
+
+    /// Action for reasons without an explicit rule, including unknown codes.
+    pub fn fallback(mut self, action: ReconnectAction) -> Self {
+        self.fallback = action;
+        self
+    }
+
+    pub fn action(&self, reason: DisconnectReason) -> ReconnectAction {
+        self.rules
+            .iter()
+            .find(|(r, _)| *r == reason)
+            .map_or(self.fallback, |(_, action)| *action)
+    }
+}
+
+/// How long the client waits between failed handshakes, and when it gives up.
+///
+/// The n-th retry in a row waits `initial * multiplier^(n-1)`, capped at `max`
+/// and spread by up to `jitter` of itself either way, so clients cut off
+/// together do not come back in lockstep. Defaults: 1 s doubling up to 60 s,
+/// 20% jitter, no attempt limit, fail fast.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    multiplier: f64,
+    max: Duration,
+    jitter: f64,
+    max_attempts: Option<u32>,
+    fail_fast: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            multiplier: 2.0,
+            max: Duration::from_secs(60),
+            jitter: 0.2,
+            max_attempts: None,
+            fail_fast: true,
+        }
+    }
+}
+
+impl Backoff {
+    /// Wait `delay` between every attempt.
+    pub fn fixed(delay: Duration) -> Self {
+        Self::default()
+            .initial(delay)
+            .max(delay)
+            .multiplier(1.0)
+            .jitter(0.0)
+    }
+
+    /// Delay before the first retry.
+    pub fn initial(mut self, value: Duration) -> Self {
+        self.initial = value;
+        self
+    }
+
+    /// Growth of the delay per failed attempt; values below 1 are raised to 1.
+    pub fn multiplier(mut self, value: f64) -> Self {
+        self.multiplier = value.max(1.0);
+        self
+    }
+
+    /// Upper bound of the delay before jitter.
+    pub fn max(mut self, value: Duration) -> Self {
+        self.max = value;
+        self
+    }
+
+    /// Spread of the delay as a fraction of it, between 0 and 1.
+    pub fn jitter(mut self, value: f64) -> Self {
+        self.jitter = value.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Give up after this many handshakes in a row failed. `None` retries
+    /// forever.
+    pub fn max_attempts(mut self, value: Option<u32>) -> Self {
+        self.max_attempts = value.filter(|&max| max > 0);
+        self
+    }
+
+    /// Give up at once when the very first handshake fails, which most often
+    /// means a wrong configuration rather than an unreachable server. When
+    /// off, the first connect retries like a reconnect.
+    pub fn fail_fast(mut self, value: bool) -> Self {
+        self.fail_fast = value;
+        self
+    }
+
+    /// Delay before retry number `retry`, counted from 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        // In float seconds: the growth overflows `Duration` long before the
+        // attempts run out.
+        let base = (self.initial.as_secs_f64() * self.multiplier.powi(retry.min(1024) as i32))
+            .min(self.max.as_secs_f64());
+        let spread = if self.jitter > 0.0 {
+            1.0 + rand::random_range(-self.jitter..=self.jitter)
+        } else {
+            1.0
+        };
+        Duration::try_from_secs_f64(base * spread).unwrap_or(self.max)
+    }
+
+    /// How long to wait after `failures` handshakes in a row failed, or
+    /// `None` to give up. `connected` tells whether the client ever had a
+    /// session.
+    pub(crate) fn retry_in(&self, failures: u32, connected: bool) -> Option<Duration> {
+        if !connected && self.fail_fast {
+            return None;
+        }
+        if self.max_attempts.is_some_and(|max| failures >= max) {
+            return None;
+        }
+        Some(self.delay(failures.saturating_sub(1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_to_max_within_jitter() {
+        let backoff = Backoff::default()
+            .initial(Duration::from_millis(100))
+            .multiplier(3.0)
+            .max(Duration::from_secs(1))
+            .jitter(0.0);
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(900));
+        assert_eq!(backoff.delay(3), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+
+        let backoff = backoff.jitter(0.5);
+        for _ in 0..100 {
+            let delay = backoff.delay(0);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(150));
+        }
+    }
+
+    #[test]
+    fn test_backoff_gives_up() {
+        let backoff = Backoff::fixed(Duration::from_millis(10));
+        assert_eq!(backoff.retry_in(1, false), None);
+        assert_eq!(backoff.retry_in(100, true), Some(Duration::from_millis(10)));
+
+        let backoff = backoff.fail_fast(false).max_attempts(Some(3));
+        assert_eq!(backoff.retry_in(1, false), Some(Duration::from_millis(10)));
+        assert_eq!(backoff.retry_in(2, true), Some(Duration::from_millis(10)));
+        assert_eq!(backoff.retry_in(3, true), None);
+    }
+
+    #[test]
+    fn test_default_stops_on_revoked_and_backs_off_otherwise() {
+        let policy = ReconnectPolicy::default();
+        assert_eq!(
+            policy.action(DisconnectReason::Revoked),
+            ReconnectAction::Stop
+        );
+        assert_eq!(
+            policy.action(DisconnectReason::RekeyRequired),
+            ReconnectAction::Immediate
+        );
+        assert_eq!(
+            policy.action(DisconnectReason::Shutdown),
+            ReconnectAction::Backoff
+        );
+        assert_eq!(
+            policy.action(DisconnectReason::QuotaExceeded),
+            ReconnectAction::Backoff
+        );
+        assert_eq!(
+            policy.action(DisconnectReason::Unknown(99)),
+            ReconnectAction::Backoff
+        );
+    }
+
+    #[test]
+    fn test_overrides_replace_defaults() {
+        let policy = ReconnectPolicy::default()
+            .on(DisconnectReason::Revoked, ReconnectAction::Backoff)
+            .on(DisconnectReason::Kicked, ReconnectAction::Stop)
+            .fallback(ReconnectAction::Immediate);
+        assert_eq!(
+            policy.action(DisconnectReason::Revoked),
+            ReconnectAction::Backoff
+        );
+        assert_eq!(
+            policy.action(DisconnectReason::Kicked),
+            ReconnectAction::Stop
+        );
+        assert_eq!(
+            policy.action(DisconnectReason::Shutdown),
+            ReconnectAction::Immediate
+        );
+    }
+}
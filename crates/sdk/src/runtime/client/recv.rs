@@ -10,11 +10,14 @@
 //!     → noise_decrypt_data_server_into   — decrypts into the next batch buffer
 //!   → network.send_multiple             — one GRO-merged TUN write for the batch
 //! ```
+//!
+//! Rekey responses arrive on the same socket while connected, so they are
+//! handed to the rekey task through `handshake_tx`.
 
 use std::ops::Deref;
 use std::sync::Arc;
 
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 use tracing::{info, warn};
 
 use crate::gateway::{
@@ -22,8 +25,9 @@ use crate::gateway::{
     transport::ClientTransport,
 };
 use crate::protocol::PacketRef;
-use crate::runtime::client::{AWAIT_STATE_DELAY, MAX_PACKET_SIZE};
+use crate::runtime::client::{AWAIT_STATE_DELAY, ClientEvent, MAX_PACKET_SIZE};
 use crate::runtime::crypto::{DataServerActionRef, noise_decrypt_data_server_into};
+use crate::runtime::handshake::HandshakeReply;
 use crate::runtime::state::{ClientSession, RuntimeState};
 use crate::time::{format_duration_millis, micros_since_start};
 
@@ -31,6 +35,7 @@ pub(super) async fn recv_decrypt_forward<T: ClientTransport, N: Network>(
     state_tx: watch::Sender<RuntimeState>,
     transport: Arc<T>,
     network: Arc<N>,
+    handshake_tx: mpsc::Sender<HandshakeReply>,
 ) {
     let mut state_rx = state_tx.subscribe();
     let mut buf = [0u8; MAX_PACKET_SIZE];
@@ -69,7 +74,7 @@ pub(super) async fn recv_decrypt_forward<T: ClientTransport, N: Network>(
             _ = state_rx.changed() => {
                 match state_rx.borrow().deref() {
                     RuntimeState::Error(_) => break,
-                    RuntimeState::Connecting => {
+                    RuntimeState::Connecting | RuntimeState::Disconnected(_) | RuntimeState::Paused => {
                         is_connected = false;
                         transport_state = None;
                     }
@@ -97,7 +102,7 @@ pub(super) async fn recv_decrypt_forward<T: ClientTransport, N: Network>(
         };
 
         let mut batch_len = 0usize;
-        let mut reconnect = false;
+        let mut disconnect = None;
 
         // Drain loop: first datagram, then any others already queued (no wait).
         loop {
@@ -106,56 +111,91 @@ pub(super) async fn recv_decrypt_forward<T: ClientTransport, N: Network>(
             } else {
                 match PacketRef::from_bytes(&buf[..n]) {
                     None => warn!("failed to parse transport packet"),
-                    Some(PacketRef::DataServer { nonce, ciphertext }) => {
-                        let nonce_ok = session.recv_window.lock().unwrap().check_and_update(nonce);
-                        if !nonce_ok {
-                            warn!("replay/stale nonce {} from server", nonce);
-                        } else {
-                            tun_bufs[batch_len].resize(seg, 0);
-                            // Base ptr captured before decrypt (resize never reallocs) to
-                            // locate the IP packet in the frame without re-borrowing.
-                            let base = tun_bufs[batch_len].as_ptr() as usize;
-                            let dec = noise_decrypt_data_server_into(
-                                ciphertext,
-                                &session.noise,
-                                &mut tun_bufs[batch_len][TUN_SEND_OFFSET..],
-                                nonce,
-                            );
-                            match dec {
-                                Err(e) => warn!("decrypt failed: {}", e),
-                                Ok(DataServerActionRef::Forward(packet)) => {
-                                    // `packet` points at the IP packet inside the decrypted
-                                    // frame, past the variant+len header — shift it to
-                                    // TUN_SEND_OFFSET for the single-offset send_multiple.
-                                    let start = packet.as_ptr() as usize - base;
-                                    let len = packet.len();
-                                    tun_bufs[batch_len]
-                                        .copy_within(start..start + len, TUN_SEND_OFFSET);
-                                    tun_bufs[batch_len].truncate(TUN_SEND_OFFSET + len);
-                                    batch_len += 1;
-                                }
-                                Ok(DataServerActionRef::KeepAlive(ts)) => {
-                                    info!(
-                                        "keepalive rtt: {}",
-                                        format_duration_millis(ts, micros_since_start())
+                    Some(PacketRef::DataServer {
+                        epoch,
+                        nonce,
+                        ciphertext,
+                    }) => {
+                        match session.keys.get(epoch) {
+                            None => {
+                                session.stats.unknown_epoch();
+                                warn!("unknown key epoch {} from server", epoch)
+                            }
+                            Some(key) => {
+                                let nonce_ok =
+                                    key.recv_window.lock().unwrap().check_and_update(nonce);
+                                if !nonce_ok {
+                                    session.stats.replay_dropped();
+                                    warn!("replay/stale nonce {} from server", nonce);
+                                } else {
+                                    tun_bufs[batch_len].resize(seg, 0);
+                                    // Base ptr captured before decrypt (resize never reallocs) to
+                                    // locate the IP packet in the frame without re-borrowing.
+                                    let base = tun_bufs[batch_len].as_ptr() as usize;
+                                    let dec = noise_decrypt_data_server_into(
+                                        ciphertext,
+                                        &key.noise,
+                                        &mut tun_bufs[batch_len][TUN_SEND_OFFSET..],
+                                        nonce,
                                     );
-                                }
-                                Ok(DataServerActionRef::Disconnect(code)) => {
-                                    warn!("server disconnect code {}", code);
-                                    reconnect = true;
+                                    if dec.is_ok() {
+                                        session.touch();
+                                    }
+                                    match dec {
+                                        Err(e) => {
+                                            session.stats.decrypt_failed();
+                                            warn!("decrypt failed: {}", e);
+                                        }
+                                        Ok(DataServerActionRef::Forward(packet)) => {
+                                            // `packet` points at the IP packet inside the decrypted
+                                            // frame, past the variant+len header — shift it to
+                                            // TUN_SEND_OFFSET for the single-offset send_multiple.
+                                            let start = packet.as_ptr() as usize - base;
+                                            let len = packet.len();
+                                            tun_bufs[batch_len]
+                                                .copy_within(start..start + len, TUN_SEND_OFFSET);
+                                            tun_bufs[batch_len].truncate(TUN_SEND_OFFSET + len);
+                                            session.stats.received(len);
+                                            batch_len += 1;
+                                        }
+                                        Ok(DataServerActionRef::KeepAlive(ts)) => {
+                                            let now = micros_since_start();
+                                            session.keepalive_echo(now.saturating_sub(ts) as u64);
+                                            info!(
+                                                "keepalive rtt: {}",
+                                                format_duration_millis(ts, now)
+                                            );
+                                        }
+                                        Ok(DataServerActionRef::Disconnect(reason)) => {
+                                            warn!("server disconnected: {}", reason);
+                                            session.emit(ClientEvent::DisconnectReceived(reason));
+                                            disconnect = Some(reason);
+                                        }
+                                    }
                                 }
                             }
                         }
                     }
                     Some(PacketRef::HandshakeResponder(data)) => {
-                        // Connector handles handshake separately; drop it here.
-                        let _ = data;
+                        // Rekey response: hand it to the rekey task.
+                        let reply = HandshakeReply::Response(data.to_vec().into());
+                        if handshake_tx.try_send(reply).is_err() {
+                            warn!("unexpected handshake response, dropping");
+                        }
+                    }
+                    Some(PacketRef::CookieReply(reply)) => {
+                        if handshake_tx
+                            .try_send(HandshakeReply::Cookie(*reply))
+                            .is_err()
+                        {
+                            warn!("unexpected cookie reply, dropping");
+                        }
                     }
                     Some(_) => warn!("unexpected packet variant on client"),
                 }
             }
 
-            if reconnect || batch_len >= TUN_BATCH_SIZE {
+            if disconnect.is_some() || batch_len >= TUN_BATCH_SIZE {
                 break;
             }
             match transport.try_recv(&mut buf) {
@@ -173,7 +213,9 @@ pub(super) async fn recv_decrypt_forward<T: ClientTransport, N: Network>(
             warn!("network send failed: {}", e);
         }
 
-        if reconnect && state_tx.send(RuntimeState::Connecting).is_err() {
+        if let Some(reason) = disconnect
+            && state_tx.send(RuntimeState::Disconnected(reason)).is_err()
+        {
             break;
         }
     }
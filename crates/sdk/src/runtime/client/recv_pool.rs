@@ -20,8 +20,8 @@
 //! module docs). Differences: a connected socket (no per-datagram addr), a single
 //! session taken from [`RuntimeState`] (attached per batch, refreshed on
 //! reconnect), and control frames handled inline — keepalive logs its RTT,
-//! `Disconnect` signals a reconnect. The anti-replay check runs in the single
-//! writer, in order.
+//! `Disconnect` hands its reason to the connector, rekey responses go to the
+//! rekey task. The anti-replay check runs in the single writer, in order.
 
 use std::ops::Deref;
 use std::sync::Arc;
@@ -33,7 +33,10 @@ use tracing::{debug, error, info, warn};
 use crate::gateway::network::{GRO_BUF_CAP, GroState, Network, TUN_BATCH_SIZE, TUN_SEND_OFFSET};
 use crate::gateway::transport::ClientTransport;
 use crate::protocol::PacketRef;
+use crate::runtime::client::ClientEvent;
 use crate::runtime::crypto::{DataServerActionRef, noise_decrypt_data_server_into};
+use crate::runtime::handshake::HandshakeReply;
+use crate::runtime::keys::TransportKey;
 use crate::runtime::state::{ClientSession, RuntimeState};
 use crate::time::{format_duration_millis, micros_since_start};
 
@@ -62,6 +65,8 @@ struct Slot {
     cipher_len: usize,
     /// Set by the worker for `Forward` slots; used by the writer's replay check.
     nonce: u64,
+    /// Key the slot was opened with; its replay window is checked by the writer.
+    key: Option<Arc<TransportKey>>,
     /// Decrypted frame; IP packet lives at `[TUN_SEND_OFFSET..]`, `len()` set by
     /// the worker. Swapped into the writer's TUN batch on `Forward`.
     plain: Vec<u8>,
@@ -74,6 +79,7 @@ impl Slot {
             cipher: vec![0u8; cipher_cap],
             cipher_len: 0,
             nonce: 0,
+            key: None,
             plain: vec![0u8; seg],
             action: SlotAction::Skip,
         }
@@ -112,6 +118,7 @@ pub(super) async fn recv_decrypt_forward_pool<
     state_tx: watch::Sender<RuntimeState>,
     transport: Arc<T>,
     network: Arc<N>,
+    handshake_tx: mpsc::Sender<HandshakeReply>,
     workers: usize,
 ) {
     let mtu = network.mtu() as usize;
@@ -135,7 +142,13 @@ pub(super) async fn recv_decrypt_forward_pool<
         let (dtx, drx) = mpsc::channel::<Box<Batch>>(CHAN_CAP);
         work_tx.push(wtx);
         done_rx.push(drx);
-        set.spawn(worker(wrx, dtx, state_tx.clone(), seg));
+        set.spawn(worker(
+            wrx,
+            dtx,
+            state_tx.clone(),
+            handshake_tx.clone(),
+            seg,
+        ));
     }
 
     set.spawn(reader(
@@ -281,7 +294,7 @@ fn update_state(
     session: &mut Option<ClientSession>,
 ) {
     match state_rx.borrow_and_update().deref() {
-        RuntimeState::Connecting => {
+        RuntimeState::Connecting | RuntimeState::Disconnected(_) | RuntimeState::Paused => {
             *is_connected = false;
             *session = None;
         }
@@ -300,13 +313,20 @@ async fn worker(
     mut work_rx: mpsc::Receiver<Box<Batch>>,
     done_tx: mpsc::Sender<Box<Batch>>,
     state_tx: watch::Sender<RuntimeState>,
+    handshake_tx: mpsc::Sender<HandshakeReply>,
     seg: usize,
 ) {
     while let Some(mut batch) = work_rx.recv().await {
         match batch.session.clone() {
             Some(session) => {
                 for si in 0..batch.len {
-                    decrypt_one(&mut batch.slots[si], &session, seg, &state_tx);
+                    decrypt_one(
+                        &mut batch.slots[si],
+                        &session,
+                        seg,
+                        &state_tx,
+                        &handshake_tx,
+                    );
                 }
             }
             None => {
@@ -330,8 +350,10 @@ fn decrypt_one(
     session: &ClientSession,
     seg: usize,
     state_tx: &watch::Sender<RuntimeState>,
+    handshake_tx: &mpsc::Sender<HandshakeReply>,
 ) {
     slot.action = SlotAction::Skip;
+    slot.key = None;
 
     if slot.cipher_len == 0 || slot.cipher_len >= slot.cipher.len() {
         warn!("dropping transport packet (size {})", slot.cipher_len);
@@ -341,40 +363,69 @@ fn decrypt_one(
     match PacketRef::from_bytes(&slot.cipher[..slot.cipher_len]) {
         None => warn!("failed to parse transport packet"),
 
-        Some(PacketRef::DataServer { nonce, ciphertext }) => {
+        Some(PacketRef::DataServer {
+            epoch,
+            nonce,
+            ciphertext,
+        }) => {
+            let Some(key) = session.keys.get(epoch) else {
+                session.stats.unknown_epoch();
+                warn!("unknown key epoch {} from server", epoch);
+                return;
+            };
             slot.plain.resize(seg, 0);
             let base = slot.plain.as_ptr() as usize;
             let dec = noise_decrypt_data_server_into(
                 ciphertext,
-                &session.noise,
+                &key.noise,
                 &mut slot.plain[TUN_SEND_OFFSET..],
                 nonce,
             );
+            if dec.is_ok() {
+                session.touch();
+            }
             match dec {
-                Err(e) => warn!("decrypt failed: {}", e),
+                Err(e) => {
+                    session.stats.decrypt_failed();
+                    warn!("decrypt failed: {}", e);
+                }
                 Ok(DataServerActionRef::Forward(packet)) => {
                     let start = packet.as_ptr() as usize - base;
                     let len = packet.len();
                     slot.plain.copy_within(start..start + len, TUN_SEND_OFFSET);
                     slot.plain.truncate(TUN_SEND_OFFSET + len);
                     slot.nonce = nonce;
+                    slot.key = Some(key);
                     slot.action = SlotAction::Forward;
                 }
                 Ok(DataServerActionRef::KeepAlive(ts)) => {
-                    info!(
-                        "keepalive rtt: {}",
-                        format_duration_millis(ts, micros_since_start())
-                    );
+                    let now = micros_since_start();
+                    session.keepalive_echo(now.saturating_sub(ts) as u64);
+                    info!("keepalive rtt: {}", format_duration_millis(ts, now));
                 }
-                Ok(DataServerActionRef::Disconnect(code)) => {
-                    warn!("server disconnect code {}", code);
-                    let _ = state_tx.send(RuntimeState::Connecting);
+                Ok(DataServerActionRef::Disconnect(reason)) => {
+                    warn!("server disconnected: {}", reason);
+                    session.emit(ClientEvent::DisconnectReceived(reason));
+                    let _ = state_tx.send(RuntimeState::Disconnected(reason));
                 }
             }
         }
 
-        Some(PacketRef::HandshakeResponder(_)) => {
-            // Connector handles the handshake separately; drop it here.
+        Some(PacketRef::HandshakeResponder(data)) => {
+            // Rekey response: hand it to the rekey task.
+            let reply = HandshakeReply::Response(data.to_vec().into());
+            if handshake_tx.try_send(reply).is_err() {
+                warn!("unexpected handshake response, dropping");
+            }
+        }
+
+        Some(PacketRef::CookieReply(reply)) => {
+            if handshake_tx
+                .try_send(HandshakeReply::Cookie(*reply))
+                .is_err()
+            {
+                warn!("unexpected cookie reply, dropping");
+            }
         }
 
         Some(_) => warn!("unexpected packet variant on client"),
@@ -430,14 +481,22 @@ async fn writer<N: Network>(
 
         for si in 0..batch.len {
             if let SlotAction::Forward = batch.slots[si].action {
-                let ok = match &batch.session {
-                    Some(session) => session
+                let ok = match &batch.slots[si].key {
+                    Some(key) => key
                         .recv_window
                         .lock()
                         .unwrap()
                         .check_and_update(batch.slots[si].nonce),
                     None => false,
                 };
+                if let Some(session) = &batch.session {
+                    match ok {
+                        true => session
+                            .stats
+                            .received(batch.slots[si].plain.len() - TUN_SEND_OFFSET),
+                        false => session.stats.replay_dropped(),
+                    }
+                }
                 if ok {
                     // Copy into the pre-reserved 64 KiB buffer (keeps its capacity,
                     // unlike a swap) so the GRO merge never reallocs.
@@ -453,6 +512,8 @@ async fn writer<N: Network>(
                     warn!("replay/stale nonce {} from server", batch.slots[si].nonce);
                 }
             }
+            // Drop the key Arc so a recycled batch doesn't pin retired keys.
+            batch.slots[si].key = None;
         }
 
         // Drop the session ref so a recycled batch doesn't pin the session.
@@ -488,9 +549,11 @@ mod tests {
     use crate::gateway::transport::TransportSender;
     use crate::gateway::transport::mock::MockTransport;
     use crate::protocol::handshake::HandshakeResponderPayload;
+    use crate::runtime::client::Counters;
     use crate::runtime::crypto::{encode_data_server_packet, make_noise_pair_for_test};
     use std::io;
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use tokio::sync::broadcast;
 
     /// Mock TUN that records every packet handed to it, in order.
     struct RecordingNetwork {
@@ -533,7 +596,9 @@ mod tests {
 
         // Server encrypts DataServer with `resp`; the client decrypts with `init`.
         let (client_state, server_state) = make_noise_pair_for_test();
-        let session = ClientSession::new(client_state);
+        let stats = Arc::new(Counters::default());
+        let session = ClientSession::new(client_state, stats.clone(), broadcast::channel(1).0);
+        let server_key = TransportKey::new(0, server_state);
 
         // client_tp is what the pool receives on; server_tp injects datagrams.
         let (client_tp, server_tp) = MockTransport::create_pair();
@@ -547,11 +612,13 @@ mod tests {
         });
 
         let (state_tx, _state_rx) = watch::channel(RuntimeState::Connecting);
+        let (handshake_tx, _handshake_rx) = mpsc::channel(1);
 
         let pool = tokio::spawn(recv_decrypt_forward_pool(
             state_tx.clone(),
             client_tp.clone(),
             network,
+            handshake_tx,
             WORKERS,
         ));
 
@@ -559,6 +626,8 @@ mod tests {
         let payload = HandshakeResponderPayload {
             sid: 1,
             ipaddr: IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2)),
+            ipv6: None,
+            network: Default::default(),
         };
         state_tx
             .send(RuntimeState::Connected((payload, session)))
@@ -569,7 +638,7 @@ mod tests {
             let mut payload = vec![0u8; 64];
             payload[..8].copy_from_slice(&seq.to_le_bytes());
             let mut frame = vec![0u8; 65600];
-            let n = encode_data_server_packet(&payload, &server_state, seq, &mut frame).unwrap();
+            let n = encode_data_server_packet(&payload, &server_key, seq, &mut frame).unwrap();
             server_tp.send_to(&frame[..n], &peer).await.unwrap();
         }
 
@@ -582,6 +651,9 @@ mod tests {
             assert_eq!(seq, expected, "out-of-order TUN write");
             assert_eq!(got.len(), 64, "payload length mismatch");
         }
+        let stats = stats.snapshot();
+        assert_eq!((stats.rx_packets, stats.rx_bytes), (N, N * 64));
+        assert_eq!(stats.replay_drops, 0);
 
         pool.abort();
     }
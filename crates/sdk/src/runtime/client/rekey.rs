@@ -0,0 +1,97 @@
+//! Periodic rekey task (client side).
+//!
+//! The client is always the initiator. Once the current transport key is older
+//! than `rekey_after_time` or has sealed `rekey_after_messages` packets, it runs
+//! a fresh IKpsk2 handshake carrying [`Rekey`] and seals under the new epoch as
+//! soon as the server answers. The data path never stops: the old key keeps
+//! opening in-flight packets for the overlap window (see `runtime::keys`).
+
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, warn};
+
+use crate::gateway::transport::ClientTransport;
+use crate::protocol::{Alg, Rekey, SessionId};
+use crate::runtime::client::{ClientEvent, Target};
+use crate::runtime::handshake::{HandshakeReply, rekey_step};
+use crate::runtime::state::{ClientSession, RuntimeState};
+
+/// How often the current key is checked against the rekey thresholds.
+const REKEY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn rekey_executor<T: ClientTransport>(
+    state_tx: watch::Sender<RuntimeState>,
+    transport: Arc<T>,
+    mut responses: mpsc::Receiver<HandshakeReply>,
+    target: watch::Receiver<Target>,
+    alg: Alg,
+    after_time: Option<Duration>,
+    after_messages: u64,
+    timeout: Duration,
+) {
+    let mut state_rx = state_tx.subscribe();
+    let mut check_timer = tokio::time::interval(REKEY_CHECK_INTERVAL);
+    let mut connected: Option<(SessionId, ClientSession)> = None;
+
+    loop {
+        tokio::select! {
+            changed = state_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                match state_rx.borrow_and_update().deref() {
+                    RuntimeState::Error(_) => break,
+                    RuntimeState::Connected((payload, session)) => {
+                        connected = Some((payload.sid, session.clone()));
+                    }
+                    _ => connected = None,
+                }
+            }
+            _ = check_timer.tick() => {
+                let Some((sid, session)) = &connected else { continue; };
+                let key = session.keys.current();
+                if !key.is_expired(after_time, after_messages) {
+                    continue;
+                }
+
+                let rekey = Rekey { sid: *sid, epoch: key.epoch.wrapping_add(1) };
+                let target = target.borrow().clone();
+                match rekey_step(&*transport, &mut responses, &target.cred, &alg, &target.cookies, rekey, timeout).await {
+                    Ok((payload, noise)) if payload.sid == rekey.sid => {
+                        session.keys.rotate(rekey.epoch, noise);
+                        session.stats.handshake_completed();
+                        session.emit(ClientEvent::Rekeyed { sid: rekey.sid, epoch: rekey.epoch });
+                        debug!("session {} rekeyed to epoch {}", rekey.sid, rekey.epoch);
+                    }
+                    Ok((payload, noise)) => {
+                        // The server dropped our session and opened a new one.
+                        warn!("session {} expired on server, continuing as {}", rekey.sid, payload.sid);
+                        session.stats.handshake_completed();
+                        let completed = ClientEvent::HandshakeCompleted { payload: payload.clone(), alg: alg.clone() };
+                        let fresh = ClientSession::new(noise, session.stats.clone(), session.events.clone());
+                        // Paused, stopped or reconnected while the rekey ran.
+                        let replaced = state_tx.send_if_modified(|current| {
+                            let ours = matches!(current, RuntimeState::Connected((live, _)) if live.sid == rekey.sid);
+                            if ours {
+                                *current = RuntimeState::Connected((payload, fresh));
+                            }
+                            ours
+                        });
+                        if replaced {
+                            session.emit(completed);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("rekey failed: {}, retrying in {:?}", e, timeout);
+                        tokio::time::sleep(timeout).await;
+                    }
+                }
+            }
+        }
+    }
+    debug!("rekey executor stopped");
+}
@@ -0,0 +1,191 @@
+//! Traffic and link-quality counters of a client.
+//!
+//! Every client task updates one shared [`Counters`] through
+//! [`ClientSession`](crate::runtime::state::ClientSession) with relaxed atomics,
+//! so counting costs nothing measurable on the hot paths. The counters live as
+//! long as the [`Client`](super::Client) and keep counting across reconnects
+//! and rekeys. Readers take a [`ClientStats`] snapshot through a
+//! [`StatsReader`].
+//!
+//! RTT comes from keepalive echoes and is smoothed the way TCP does it
+//! (RFC 6298): `srtt = 7/8 srtt + 1/8 r` and `jitter = 3/4 jitter + 1/4 |srtt - r|`.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Point-in-time copy of the client counters.
+///
+/// Bytes and packets count tunnelled IP packets only, not keepalives,
+/// handshakes or protocol overhead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    /// Smoothed keepalive round-trip time; `None` before the first echo
+    pub rtt: Option<Duration>,
+    /// Mean deviation of the round-trip time
+    pub jitter: Option<Duration>,
+    /// When the last handshake or rekey with the server completed
+    pub last_handshake: Option<SystemTime>,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    /// Packets from the server dropped as replayed or too old
+    pub replay_drops: u64,
+    /// Packets from the server that failed to decrypt
+    pub decrypt_failures: u64,
+    /// Packets from the server sealed under a key epoch that is unknown or
+    /// past its overlap window
+    pub unknown_epoch_drops: u64,
+    /// Sessions established after the first one
+    pub reconnects: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    tx_bytes: AtomicU64,
+    tx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    rx_packets: AtomicU64,
+    replay_drops: AtomicU64,
+    decrypt_failures: AtomicU64,
+    unknown_epoch_drops: AtomicU64,
+    reconnects: AtomicU64,
+    /// Smoothed RTT in microseconds; 0 until the first sample.
+    srtt: AtomicU64,
+    /// RTT mean deviation in microseconds.
+    jitter: AtomicU64,
+    /// Unix time of the last handshake in milliseconds; 0 if none yet.
+    last_handshake: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.tx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        self.rx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn replay_dropped(&self) {
+        self.replay_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn decrypt_failed(&self) {
+        self.decrypt_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn unknown_epoch(&self) {
+        self.unknown_epoch_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handshake_completed(&self) {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.last_handshake.store(millis.max(1), Ordering::Relaxed);
+    }
+
+    /// Fold one keepalive round trip of `micros` into the smoothed RTT.
+    ///
+    /// Samples come from one receive task at a time, so a plain load/store is
+    /// enough; a lost update under the decrypt pool only skews one sample.
+    pub(crate) fn rtt_sample(&self, micros: u64) {
+        let sample = micros.max(1);
+        let srtt = self.srtt.load(Ordering::Relaxed);
+        if srtt == 0 {
+            self.srtt.store(sample, Ordering::Relaxed);
+            self.jitter.store(sample / 2, Ordering::Relaxed);
+            return;
+        }
+        let jitter = self.jitter.load(Ordering::Relaxed);
+        self.jitter
+            .store((3 * jitter + srtt.abs_diff(sample)) / 4, Ordering::Relaxed);
+        self.srtt.store((7 * srtt + sample) / 8, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ClientStats {
+        let srtt = self.srtt.load(Ordering::Relaxed);
+        let last_handshake = self.last_handshake.load(Ordering::Relaxed);
+        ClientStats {
+            rtt: (srtt > 0).then(|| Duration::from_micros(srtt)),
+            jitter: (srtt > 0).then(|| Duration::from_micros(self.jitter.load(Ordering::Relaxed))),
+            last_handshake: (last_handshake > 0)
+                .then(|| UNIX_EPOCH + Duration::from_millis(last_handshake)),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            replay_drops: self.replay_drops.load(Ordering::Relaxed),
+            decrypt_failures: self.decrypt_failures.load(Ordering::Relaxed),
+            unknown_epoch_drops: self.unknown_epoch_drops.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Cheap, cloneable access to the statistics of a running client.
+#[derive(Clone, Debug)]
+pub struct StatsReader(pub(crate) Arc<Counters>);
+
+impl StatsReader {
+    pub fn snapshot(&self) -> ClientStats {
+        self.0.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_snapshot() {
+        assert_eq!(Counters::default().snapshot(), ClientStats::default());
+    }
+
+    #[test]
+    fn test_rtt_is_smoothed() {
+        let counters = Counters::default();
+        counters.rtt_sample(8_000);
+        let stats = counters.snapshot();
+        assert_eq!(stats.rtt, Some(Duration::from_millis(8)));
+        assert_eq!(stats.jitter, Some(Duration::from_millis(4)));
+
+        // A single 16 ms outlier moves the average by an eighth of the gap.
+        counters.rtt_sample(16_000);
+        let stats = counters.snapshot();
+        assert_eq!(stats.rtt, Some(Duration::from_millis(9)));
+        assert_eq!(stats.jitter, Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn test_traffic_counters() {
+        let counters = Counters::default();
+        counters.sent(100);
+        counters.sent(50);
+        counters.received(1400);
+        counters.replay_dropped();
+        counters.decrypt_failed();
+        counters.unknown_epoch();
+        counters.reconnected();
+        counters.handshake_completed();
+
+        let stats = counters.snapshot();
+        assert_eq!((stats.tx_bytes, stats.tx_packets), (150, 2));
+        assert_eq!((stats.rx_bytes, stats.rx_packets), (1400, 1));
+        assert_eq!(
+            (stats.replay_drops, stats.decrypt_failures, stats.reconnects),
+            (1, 1, 1)
+        );
+        assert_eq!(stats.unknown_epoch_drops, 1);
+        assert!(stats.last_handshake.is_some());
+    }
+}
@@ -0,0 +1,291 @@
+//! WireGuard-style cookie MACs for `HandshakeInitial`.
+//!
+//! Every initiation carries a trailer `mac1 | mac2`:
+//! - `mac1` is keyed on the server public key, so the server drops garbage
+//!   with one BLAKE2s call instead of an X25519 Noise read.
+//! - `mac2` is keyed on a cookie the server handed out earlier. It is only
+//!   checked while the handshake queue is under load: initiations without a
+//!   valid `mac2` then get an encrypted [cookie reply](encode_cookie_reply)
+//!   instead of a response, so a source has to prove it can receive on its
+//!   address before the server spends work on it.
+//!
+//! The cookie is a MAC of the source address under a random secret that
+//! rotates every [`COOKIE_SECRET_LIFETIME`].
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use blake2::digest::consts::U16;
+use blake2::digest::{KeyInit, Mac};
+use blake2::{Blake2s256, Blake2sMac, Digest};
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+
+use crate::crypto::PublicKey;
+use crate::protocol::{COOKIE_REPLY_LEN, COOKIE_REPLY_TYPE};
+use crate::runtime::error::RuntimeError;
+
+pub(crate) const MAC_LEN: usize = 16;
+/// Length of the `mac1 | mac2` trailer of a `HandshakeInitial`.
+pub(crate) const MACS_LEN: usize = 2 * MAC_LEN;
+const NONCE_LEN: usize = 24;
+/// Encrypted cookie: the cookie itself plus the AEAD tag.
+const SEALED_COOKIE_LEN: usize = MAC_LEN + 16;
+const _: () = assert!(COOKIE_REPLY_LEN == NONCE_LEN + SEALED_COOKIE_LEN);
+
+/// How long the server keeps one cookie secret.
+pub(crate) const COOKIE_SECRET_LIFETIME: Duration = Duration::from_secs(120);
+/// Clients stop using a cookie a little before the server rotates it away.
+const COOKIE_LIFETIME: Duration = Duration::from_secs(110);
+
+const LABEL_MAC1: &[u8] = b"mac1----";
+const LABEL_COOKIE: &[u8] = b"cookie--";
+
+fn hash(label: &[u8], pk: &PublicKey) -> [u8; 32] {
+    Blake2s256::new()
+        .chain_update(label)
+        .chain_update(pk.as_slice())
+        .finalize()
+        .into()
+}
+
+fn keyed(key: &[u8]) -> Blake2sMac<U16> {
+    <Blake2sMac<U16> as KeyInit>::new_from_slice(key).expect("mac key is at most 32 bytes")
+}
+
+fn mac(key: &[u8], data: &[u8]) -> [u8; MAC_LEN] {
+    let mut mac = keyed(key);
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn verify(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = keyed(key);
+    mac.update(data);
+    mac.verify_slice(tag).is_ok()
+}
+
+fn cipher(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(key.into())
+}
+
+fn addr_bytes(addr: &SocketAddr) -> Vec<u8> {
+    let mut bytes = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    bytes.extend_from_slice(&addr.port().to_be_bytes());
+    bytes
+}
+
+/// Split a stamped initiation into `(body, mac1, mac2)`.
+fn split(msg: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let body_len = msg.len().checked_sub(MACS_LEN)?;
+    let (body, macs) = msg.split_at(body_len);
+    let (mac1, mac2) = macs.split_at(MAC_LEN);
+    Some((body, mac1, mac2))
+}
+
+/// Cookie reply on the wire: `type | nonce | sealed cookie`.
+pub(crate) fn encode_cookie_reply(nonce: &[u8; NONCE_LEN], sealed: &[u8]) -> Vec<u8> {
+    let mut pkt = Vec::with_capacity(1 + COOKIE_REPLY_LEN);
+    pkt.push(COOKIE_REPLY_TYPE);
+    pkt.extend_from_slice(nonce);
+    pkt.extend_from_slice(sealed);
+    pkt
+}
+
+/// Client side: stamps initiations and keeps the latest server cookie.
+pub(crate) struct CookieJar {
+    mac1_key: [u8; 32],
+    cookie_key: [u8; 32],
+    state: Mutex<JarState>,
+}
+
+#[derive(Default)]
+struct JarState {
+    cookie: Option<(Instant, [u8; MAC_LEN])>,
+    /// `mac1` of the last initiation sent; a cookie reply must answer it.
+    last_mac1: Option<[u8; MAC_LEN]>,
+}
+
+impl CookieJar {
+    pub(crate) fn new(server_pk: &PublicKey) -> Self {
+        Self {
+            mac1_key: hash(LABEL_MAC1, server_pk),
+            cookie_key: hash(LABEL_COOKIE, server_pk),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Append `mac1 | mac2` to `handshake`. `mac2` is zero without a fresh cookie.
+    pub(crate) fn stamp(&self, handshake: &[u8]) -> Vec<u8> {
+        let mut msg = Vec::with_capacity(handshake.len() + MACS_LEN);
+        msg.extend_from_slice(handshake);
+        let mac1 = mac(&self.mac1_key, &msg);
+        msg.extend_from_slice(&mac1);
+
+        let mut state = self.state.lock().expect("cookie jar lock");
+        state.last_mac1 = Some(mac1);
+        match state.cookie {
+            Some((received, cookie)) if received.elapsed() < COOKIE_LIFETIME => {
+                let mac2 = mac(&cookie, &msg);
+                msg.extend_from_slice(&mac2);
+            }
+            _ => msg.extend_from_slice(&[0u8; MAC_LEN]),
+        }
+        msg
+    }
+
+    /// Store the cookie from a reply to the last stamped initiation.
+    pub(crate) fn consume(&self, reply: &[u8; COOKIE_REPLY_LEN]) -> Result<(), RuntimeError> {
+        let (nonce, sealed) = reply.split_at(NONCE_LEN);
+        let mut state = self.state.lock().expect("cookie jar lock");
+        let mac1 = state
+            .last_mac1
+            .ok_or_else(|| RuntimeError::Protocol("unsolicited cookie reply".into()))?;
+        let cookie = cipher(&self.cookie_key)
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: &mac1,
+                },
+            )
+            .map_err(|_| RuntimeError::Protocol("invalid cookie reply".into()))?;
+        let cookie = cookie
+            .try_into()
+            .map_err(|_| RuntimeError::Protocol("invalid cookie length".into()))?;
+        state.cookie = Some((Instant::now(), cookie));
+        Ok(())
+    }
+}
+
+/// Server side: checks MACs and issues cookies.
+pub(crate) struct CookieChecker {
+    mac1_key: [u8; 32],
+    cookie_key: [u8; 32],
+    secret: Mutex<(Instant, [u8; 32])>,
+}
+
+impl CookieChecker {
+    pub(crate) fn new(server_pk: &PublicKey) -> Self {
+        Self {
+            mac1_key: hash(LABEL_MAC1, server_pk),
+            cookie_key: hash(LABEL_COOKIE, server_pk),
+            secret: Mutex::new((Instant::now(), random())),
+        }
+    }
+
+    /// Whether `msg` carries a valid `mac1`. Costs one BLAKE2s call.
+    pub(crate) fn check_mac1(&self, msg: &[u8]) -> bool {
+        match split(msg) {
+            Some((body, mac1, _)) => verify(&self.mac1_key, body, mac1),
+            None => false,
+        }
+    }
+
+    /// Whether `msg` carries a `mac2` made with the current cookie for `addr`.
+    pub(crate) fn check_mac2(&self, msg: &[u8], addr: &SocketAddr) -> bool {
+        match split(msg) {
+            Some((_, _, mac2)) => {
+                let stamped = &msg[..msg.len() - MAC_LEN];
+                verify(&self.cookie(addr), stamped, mac2)
+            }
+            None => false,
+        }
+    }
+
+    /// Cookie reply packet answering `msg` from `addr`.
+    pub(crate) fn reply(&self, msg: &[u8], addr: &SocketAddr) -> Vec<u8> {
+        let mac1 = split(msg).map_or(&[][..], |(_, mac1, _)| mac1);
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rng().fill_bytes(&mut nonce);
+        let sealed = cipher(&self.cookie_key)
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &self.cookie(addr),
+                    aad: mac1,
+                },
+            )
+            .expect("cookie encrypts");
+        encode_cookie_reply(&nonce, &sealed)
+    }
+
+    fn cookie(&self, addr: &SocketAddr) -> [u8; MAC_LEN] {
+        let mut secret = self.secret.lock().expect("cookie secret lock");
+        if secret.0.elapsed() >= COOKIE_SECRET_LIFETIME {
+            *secret = (Instant::now(), random());
+        }
+        mac(&secret.1, &addr_bytes(addr))
+    }
+}
+
+fn random() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SecretKey;
+
+    fn pair() -> (CookieJar, CookieChecker) {
+        let pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        (CookieJar::new(&pk), CookieChecker::new(&pk))
+    }
+
+    fn reply_body(pkt: &[u8]) -> [u8; COOKIE_REPLY_LEN] {
+        assert_eq!(pkt[0], COOKIE_REPLY_TYPE);
+        pkt[1..].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_mac1_binds_server_key() {
+        let (jar, checker) = pair();
+        let (_, other) = pair();
+        let msg = jar.stamp(b"handshake");
+        assert!(checker.check_mac1(&msg));
+        assert!(!other.check_mac1(&msg));
+
+        let mut tampered = msg.clone();
+        tampered[0] ^= 1;
+        assert!(!checker.check_mac1(&tampered));
+        assert!(!checker.check_mac1(&msg[..MACS_LEN - 1]));
+    }
+
+    #[test]
+    fn test_cookie_reply_enables_mac2() {
+        let (jar, checker) = pair();
+        let addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+
+        let first = jar.stamp(b"handshake");
+        assert!(!checker.check_mac2(&first, &addr));
+
+        jar.consume(&reply_body(&checker.reply(&first, &addr)))
+            .unwrap();
+        let second = jar.stamp(b"handshake");
+        assert!(checker.check_mac2(&second, &addr));
+
+        let elsewhere: SocketAddr = "192.0.2.2:4000".parse().unwrap();
+        assert!(!checker.check_mac2(&second, &elsewhere));
+    }
+
+    #[test]
+    fn test_cookie_reply_for_other_initiation_is_rejected() {
+        let (jar, checker) = pair();
+        let addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+
+        let old = jar.stamp(b"first");
+        jar.stamp(b"second");
+        assert!(
+            jar.consume(&reply_body(&checker.reply(&old, &addr)))
+                .is_err()
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::crypto::{PublicKey, SecretKey};
 
+#[derive(Clone)]
 pub struct Cred {
     pub sk: SecretKey,
     pub psk: SecretKey,
@@ -23,7 +23,8 @@ use std::sync::Arc;
 use bytes::Bytes;
 use snow::StatelessTransportState;
 
-use crate::protocol::{DataClientBodyRef, DataServerBodyRef, EncryptedData};
+use crate::protocol::{DataClientBodyRef, DataServerBodyRef, DisconnectReason, EncryptedData};
+use crate::runtime::keys::TransportKey;
 
 thread_local! {
     /// Intermediate plaintext buffer: used for bincode encode (encrypt) or
@@ -42,7 +43,7 @@ thread_local! {
 /// Encrypt `body` via bincode/serde then Noise `StatelessTransportState`.
 ///
 /// `nonce` must be a unique, monotonically increasing counter per session.
-/// The caller is responsible for fetching it via `session.send_nonce.fetch_add(1, Relaxed)`.
+/// The caller is responsible for fetching it via `TransportKey::next_nonce`.
 ///
 /// Allocations (after warm-up): **zero** — cipher buffer is reused from the
 /// thread-local pool.
@@ -124,32 +125,39 @@ fn write_ip_packet_plain(plain_buf: &mut [u8], payload: &[u8]) -> usize {
 pub(crate) const TYPE_DATA_SERVER: u8 = 3;
 /// `DataClient` wire type byte.
 pub(crate) const TYPE_DATA_CLIENT: u8 = 2;
-/// `DataServer` header length: `type(1) + nonce(8)`.
-pub(crate) const DATA_SERVER_HDR_LEN: usize = 1 + 8;
-/// `DataClient` header length: `type(1) + sid(4) + nonce(8)`.
-pub(crate) const DATA_CLIENT_HDR_LEN: usize = 1 + 4 + 8;
+/// `DataServer` header length: `type(1) + epoch(1) + nonce(8)`.
+pub(crate) const DATA_SERVER_HDR_LEN: usize = 1 + 1 + 8;
+/// `DataClient` header length: `type(1) + epoch(1) + sid(4) + nonce(8)`.
+pub(crate) const DATA_CLIENT_HDR_LEN: usize = 1 + 1 + 4 + 8;
 
-/// Write the fixed `DataServer` header (`type | nonce`) into `buf`.
+/// Write the fixed `DataServer` header (`type | epoch | nonce`) into `buf`.
 #[inline]
-fn write_data_server_header(buf: &mut [u8], nonce: u64) -> usize {
+fn write_data_server_header(buf: &mut [u8], epoch: u8, nonce: u64) -> usize {
     buf[0] = TYPE_DATA_SERVER;
-    buf[1..9].copy_from_slice(&nonce.to_be_bytes());
+    buf[1] = epoch;
+    buf[2..10].copy_from_slice(&nonce.to_be_bytes());
     DATA_SERVER_HDR_LEN
 }
 
-/// Write the fixed `DataClient` header (`type | sid | nonce`) into `buf`.
+/// Write the fixed `DataClient` header (`type | epoch | sid | nonce`) into `buf`.
 #[inline]
-fn write_data_client_header(buf: &mut [u8], sid: u32, nonce: u64) -> usize {
+fn write_data_client_header(buf: &mut [u8], epoch: u8, sid: u32, nonce: u64) -> usize {
     buf[0] = TYPE_DATA_CLIENT;
-    buf[1..5].copy_from_slice(&sid.to_be_bytes());
-    buf[5..13].copy_from_slice(&nonce.to_be_bytes());
+    buf[1] = epoch;
+    buf[2..6].copy_from_slice(&sid.to_be_bytes());
+    buf[6..14].copy_from_slice(&nonce.to_be_bytes());
     DATA_CLIENT_HDR_LEN
 }
 
 /// Assemble a complete `DataServer` frame from an already-encrypted body
 /// (keepalive path). Returns the total frame length.
-pub(crate) fn encode_data_server_frame(nonce: u64, cipher: &[u8], out: &mut [u8]) -> usize {
-    let h = write_data_server_header(out, nonce);
+pub(crate) fn encode_data_server_frame(
+    epoch: u8,
+    nonce: u64,
+    cipher: &[u8],
+    out: &mut [u8],
+) -> usize {
+    let h = write_data_server_header(out, epoch, nonce);
     out[h..h + cipher.len()].copy_from_slice(cipher);
     h + cipher.len()
 }
@@ -157,12 +165,13 @@ pub(crate) fn encode_data_server_frame(nonce: u64, cipher: &[u8], out: &mut [u8]
 /// Assemble a complete `DataClient` frame from an already-encrypted body
 /// (keepalive path). Returns the total frame length.
 pub(crate) fn encode_data_client_frame(
+    epoch: u8,
     sid: u32,
     nonce: u64,
     cipher: &[u8],
     out: &mut [u8],
 ) -> usize {
-    let h = write_data_client_header(out, sid, nonce);
+    let h = write_data_client_header(out, epoch, sid, nonce);
     out[h..h + cipher.len()].copy_from_slice(cipher);
     h + cipher.len()
 }
@@ -170,7 +179,7 @@ pub(crate) fn encode_data_client_frame(
 /// Encode a raw IP packet as a complete `Packet::DataServer` wire frame into `out`.
 ///
 /// Layout: `[header | noise_ciphertext]`
-/// - header: variant(1B) + nonce(1–9B) + cipher_len(1–3B)
+/// - header: fixed `type | epoch | nonce` (see `DATA_SERVER_HDR_LEN`)
 /// - noise_ciphertext: plain_frame + 16-byte AEAD tag, written directly by Noise
 ///
 /// Hot path: **two memcpy** only — payload→PLAIN_BUF, then AEAD-encrypt→out.
@@ -179,7 +188,7 @@ pub(crate) fn encode_data_client_frame(
 /// Returns the total number of bytes written to `out`.
 pub(crate) fn encode_data_server_packet(
     payload: &[u8],
-    state: &StatelessTransportState,
+    key: &TransportKey,
     nonce: u64,
     out: &mut [u8],
 ) -> anyhow::Result<usize> {
@@ -187,7 +196,8 @@ pub(crate) fn encode_data_server_packet(
     if plain_len > 65536 {
         anyhow::bail!("IP packet too large: {} payload bytes", payload.len());
     }
-    let header_len = write_data_server_header(out, nonce);
+    let header_len = write_data_server_header(out, key.epoch, nonce);
+    let state = &key.noise;
     PLAIN_BUF.with_borrow_mut(|plain| {
         let n = write_ip_packet_plain(plain, payload);
         debug_assert_eq!(n, plain_len);
@@ -200,12 +210,13 @@ pub(crate) fn encode_data_server_packet(
 
 /// Encode a raw IP packet as a complete `Packet::DataClient` wire frame into `out`.
 ///
-/// Same layout as `encode_data_server_packet` but with a session ID prefix.
+/// Same layout as `encode_data_server_packet` but with a session ID after the
+/// epoch byte.
 /// Returns the total number of bytes written to `out`.
 pub(crate) fn encode_data_client_packet(
     payload: &[u8],
     sid: u32,
-    state: &StatelessTransportState,
+    key: &TransportKey,
     nonce: u64,
     out: &mut [u8],
 ) -> anyhow::Result<usize> {
@@ -213,7 +224,8 @@ pub(crate) fn encode_data_client_packet(
     if plain_len > 65536 {
         anyhow::bail!("IP packet too large: {} payload bytes", payload.len());
     }
-    let header_len = write_data_client_header(out, sid, nonce);
+    let header_len = write_data_client_header(out, key.epoch, sid, nonce);
+    let state = &key.noise;
     PLAIN_BUF.with_borrow_mut(|plain| {
         let n = write_ip_packet_plain(plain, payload);
         debug_assert_eq!(n, plain_len);
@@ -234,6 +246,8 @@ pub(crate) enum DataClientActionRef<'p> {
     Forward(&'p [u8]),
     /// Keepalive timestamp (microseconds since client process start).
     KeepAlive(u128),
+    /// The client closed its session.
+    Disconnect,
 }
 
 /// Result of decrypting a DataServerBody (client receives this from server).
@@ -245,8 +259,8 @@ pub(crate) enum DataServerActionRef<'p> {
     Forward(&'p [u8]),
     /// Keepalive echo timestamp.
     KeepAlive(u128),
-    /// Server-initiated disconnect code.
-    Disconnect(u8),
+    /// Server-initiated disconnect with its reason.
+    Disconnect(DisconnectReason),
 }
 
 /// Decrypt a DataClientBody from raw ciphertext directly into `plain`.
@@ -273,6 +287,7 @@ pub(crate) fn noise_decrypt_data_client_into<'p>(
     Ok(match body {
         DataClientBodyRef::Packet(data) => DataClientActionRef::Forward(data),
         DataClientBodyRef::KeepAlive(ts) => DataClientActionRef::KeepAlive(ts),
+        DataClientBodyRef::Disconnect => DataClientActionRef::Disconnect,
     })
 }
 
@@ -293,7 +308,7 @@ pub(crate) fn noise_decrypt_data_server_into<'p>(
     Ok(match body {
         DataServerBodyRef::Packet(data) => DataServerActionRef::Forward(data),
         DataServerBodyRef::KeepAlive(ts) => DataServerActionRef::KeepAlive(ts),
-        DataServerBodyRef::Disconnect(code) => DataServerActionRef::Disconnect(code),
+        DataServerBodyRef::Disconnect(reason) => DataServerActionRef::Disconnect(reason),
     })
 }
 
@@ -440,6 +455,7 @@ mod tests {
         };
 
         let (tx, rx) = make_noise_pair_for_test();
+        let tx = TransportKey::new(5, tx);
         let payload = vec![0xABu8; 1400];
         let mut out = vec![0u8; 65600];
         let nonce = 7u64;
@@ -450,9 +466,11 @@ mod tests {
         // Parse header
         match PacketRef::from_bytes(frame).unwrap() {
             PacketRef::DataServer {
+                epoch,
                 nonce: pkt_nonce,
                 ciphertext,
             } => {
+                assert_eq!(epoch, 5);
                 assert_eq!(pkt_nonce, nonce);
                 // Decrypt
                 let mut plain = [0u8; 65536];
@@ -474,6 +492,7 @@ mod tests {
         };
 
         let (tx, rx) = make_noise_pair_for_test();
+        let tx = TransportKey::new(9, tx);
         let payload = vec![0xDEu8; 512];
         let sid: u32 = 0xDEAD_BEEF;
         let mut out = vec![0u8; 65600];
@@ -484,10 +503,12 @@ mod tests {
 
         match PacketRef::from_bytes(frame).unwrap() {
             PacketRef::DataClient {
+                epoch,
                 sid: pkt_sid,
                 nonce: pkt_nonce,
                 ciphertext,
             } => {
+                assert_eq!(epoch, 9);
                 assert_eq!(pkt_sid, sid);
                 assert_eq!(pkt_nonce, nonce);
                 let mut plain = [0u8; 65536];
@@ -500,17 +521,19 @@ mod tests {
         }
     }
 
-    /// Verify the fixed-size DataServer header layout (`type=3 | nonce BE`).
+    /// Verify the fixed-size DataServer header layout (`type=3 | epoch | nonce BE`).
     #[test]
     fn test_data_server_fixed_header() {
         let (tx, _rx) = make_noise_pair_for_test();
+        let tx = TransportKey::new(0xEE, tx);
         let payload = vec![0x55u8; 64];
         let nonce = 0x0102_0304_0506_0708u64;
 
         let mut out = vec![0u8; 65600];
         let n = encode_data_server_packet(&payload, &tx, nonce, &mut out).unwrap();
         assert_eq!(out[0], TYPE_DATA_SERVER);
-        assert_eq!(&out[1..9], &nonce.to_be_bytes());
+        assert_eq!(out[1], 0xEE);
+        assert_eq!(&out[2..10], &nonce.to_be_bytes());
         // Ciphertext runs from the fixed header to the end — no length field.
         assert_eq!(
             n - DATA_SERVER_HDR_LEN,
@@ -518,10 +541,12 @@ mod tests {
         );
     }
 
-    /// Verify the fixed-size DataClient header layout (`type=2 | sid BE | nonce BE`).
+    /// Verify the fixed-size DataClient header layout
+    /// (`type=2 | epoch | sid BE | nonce BE`).
     #[test]
     fn test_data_client_fixed_header() {
         let (tx, _rx) = make_noise_pair_for_test();
+        let tx = TransportKey::new(0x11, tx);
         let payload = vec![0xAAu8; 128];
         let sid: u32 = 0xDEAD_BEEF;
         let nonce = 0x1122_3344_5566_7788u64;
@@ -529,8 +554,9 @@ mod tests {
         let mut out = vec![0u8; 65600];
         let n = encode_data_client_packet(&payload, sid, &tx, nonce, &mut out).unwrap();
         assert_eq!(out[0], TYPE_DATA_CLIENT);
-        assert_eq!(&out[1..5], &sid.to_be_bytes());
-        assert_eq!(&out[5..13], &nonce.to_be_bytes());
+        assert_eq!(out[1], 0x11);
+        assert_eq!(&out[2..6], &sid.to_be_bytes());
+        assert_eq!(&out[6..14], &nonce.to_be_bytes());
         assert_eq!(
             n - DATA_CLIENT_HDR_LEN,
             ip_packet_plain_len(payload.len()) + 16
@@ -0,0 +1,225 @@
+//! One-time enrollment of a new client.
+//!
+//! The client creates its own key pair and trades an enrollment token for its
+//! PSK, so the server never sees the client's private key. The exchange is a
+//! Noise IK handshake over any byte stream: the client knows the server key
+//! from the token, and the server learns the client key, proven by the
+//! handshake itself. The first message carries the token, the second the
+//! server's answer. Each message is a big-endian `u16` length followed by the
+//! Noise message.
+//!
+//! Servers should keep only [`token_id`] of the tokens they issue.
+
+use std::future::Future;
+use std::io;
+
+use blake2::{Blake2s256, Digest};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use snow::{Builder, HandshakeState};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::crypto::{PublicKey, SecretKey};
+
+const PARAMS: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+const LABEL_TOKEN: &[u8] = b"holynet-enroll-token";
+const MAX_MESSAGE: usize = u16::MAX as usize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EnrollError {
+    #[error("io: {0}")]
+    Io(#[from] io::Error),
+    /// The handshake failed, e.g. because the server key in the token is not
+    /// the one of the server answering.
+    #[error("crypto: {0}")]
+    Crypto(#[from] snow::Error),
+    #[error("malformed message: {0}")]
+    Malformed(String),
+    #[error("refused by server: {0}")]
+    Refused(String),
+}
+
+/// What the client needs besides its own key to connect.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Enrolled {
+    pub psk: SecretKey,
+    /// Port of the VPN listener
+    pub port: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Request {
+    token: SecretKey,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Accepted(Enrolled),
+    Refused(String),
+}
+
+/// Lookup key of `token`; storing it instead of the token keeps a leaked
+/// store from enrolling anyone.
+pub fn token_id(token: &SecretKey) -> [u8; 32] {
+    Blake2s256::new()
+        .chain_update(LABEL_TOKEN)
+        .chain_update(token.as_slice())
+        .finalize()
+        .into()
+}
+
+fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    bincode::serde::encode_to_vec(message, bincode::config::standard())
+        .expect("serialize enrollment message")
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, EnrollError> {
+    bincode::serde::decode_from_slice(bytes, bincode::config::standard())
+        .map(|(message, _)| message)
+        .map_err(|e| EnrollError::Malformed(e.to_string()))
+}
+
+async fn write_message<S: AsyncWrite + Unpin, T: Serialize>(
+    stream: &mut S,
+    noise: &mut HandshakeState,
+    message: &T,
+) -> Result<(), EnrollError> {
+    let mut buf = vec![0u8; MAX_MESSAGE];
+    let len = noise.write_message(&encode(message), &mut buf)?;
+    stream.write_u16(len as u16).await?;
+    stream.write_all(&buf[..len]).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_message<S: AsyncRead + Unpin, T: DeserializeOwned>(
+    stream: &mut S,
+    noise: &mut HandshakeState,
+) -> Result<T, EnrollError> {
+    let len = stream.read_u16().await? as usize;
+    let mut message = vec![0u8; len];
+    stream.read_exact(&mut message).await?;
+    let mut payload = vec![0u8; MAX_MESSAGE];
+    let len = noise.read_message(&message, &mut payload)?;
+    decode(&payload[..len])
+}
+
+/// Enroll the key pair `sk` with the server holding `server_pk`, spending
+/// `token`.
+pub async fn enroll<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    sk: &SecretKey,
+    server_pk: &PublicKey,
+    token: &SecretKey,
+) -> Result<Enrolled, EnrollError> {
+    let mut noise = Builder::new(PARAMS.parse()?)
+        .local_private_key(sk.as_slice())?
+        .remote_public_key(server_pk.as_slice())?
+        .build_initiator()?;
+    let request = Request {
+        token: token.clone(),
+    };
+    write_message(stream, &mut noise, &request).await?;
+    match read_message(stream, &mut noise).await? {
+        Response::Accepted(enrolled) => Ok(enrolled),
+        Response::Refused(reason) => Err(EnrollError::Refused(reason)),
+    }
+}
+
+/// Answer one enrollment as the server with key `sk`. `redeem` gets the
+/// client's proven public key and its token, and either registers the client
+/// or names the reason to refuse it. Returns the enrolled key.
+pub async fn accept<S, F, Fut>(
+    stream: &mut S,
+    sk: &SecretKey,
+    redeem: F,
+) -> Result<PublicKey, EnrollError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnOnce(PublicKey, SecretKey) -> Fut,
+    Fut: Future<Output = Result<Enrolled, String>>,
+{
+    let mut noise = Builder::new(PARAMS.parse()?)
+        .local_private_key(sk.as_slice())?
+        .build_responder()?;
+    let request: Request = read_message(stream, &mut noise).await?;
+    let peer = noise
+        .get_remote_static()
+        .and_then(|key| PublicKey::try_from(key).ok())
+        .ok_or_else(|| EnrollError::Malformed("no client key".into()))?;
+    let (response, result) = match redeem(peer.clone(), request.token).await {
+        Ok(enrolled) => (Response::Accepted(enrolled), Ok(peer)),
+        Err(reason) => (
+            Response::Refused(reason.clone()),
+            Err(EnrollError::Refused(reason)),
+        ),
+    };
+    write_message(stream, &mut noise, &response).await?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enrolled() -> Enrolled {
+        Enrolled {
+            psk: SecretKey::generate_x25519(),
+            port: 26256,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enroll_trades_token_for_psk() {
+        let server_sk = SecretKey::generate_x25519();
+        let server_pk = PublicKey::from_secret(&server_sk);
+        let client_sk = SecretKey::generate_x25519();
+        let token = SecretKey::generate_x25519();
+        let issued = token_id(&token);
+        let reply = enrolled();
+        let psk = reply.psk.clone();
+
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            accept(&mut server, &server_sk, |_, token| async move {
+                match token_id(&token) == issued {
+                    true => Ok(reply),
+                    false => Err("unknown token".into()),
+                }
+            })
+            .await
+        });
+        let got = enroll(&mut client, &client_sk, &server_pk, &token)
+            .await
+            .unwrap();
+        assert_eq!(got.psk.as_slice(), psk.as_slice());
+        assert_eq!(got.port, 26256);
+        let peer = server.await.unwrap().unwrap();
+        assert!(peer == PublicKey::from_secret(&client_sk));
+    }
+
+    #[tokio::test]
+    async fn test_refused_and_wrong_server() {
+        let server_sk = SecretKey::generate_x25519();
+        let server_pk = PublicKey::from_secret(&server_sk);
+        let client_sk = SecretKey::generate_x25519();
+        let token = SecretKey::generate_x25519();
+
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let sk = server_sk.clone();
+        tokio::spawn(async move {
+            let _ = accept(&mut server, &sk, |_, _| async { Err("expired".into()) }).await;
+        });
+        let err = enroll(&mut client, &client_sk, &server_pk, &token).await;
+        assert!(matches!(err, Err(EnrollError::Refused(reason)) if reason == "expired"));
+
+        // A token naming another server key never reaches `redeem`.
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            accept(&mut server, &server_sk, |_, _| async { Ok(enrolled()) }).await
+        });
+        let other = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let _ = enroll(&mut client, &client_sk, &other, &token).await;
+        assert!(matches!(server.await.unwrap(), Err(EnrollError::Crypto(_))));
+    }
+}
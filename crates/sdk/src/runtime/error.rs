@@ -1,55 +1,133 @@
-use std::fmt;
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Debug)]
+use crate::protocol::{DisconnectReason, HandshakeError};
+
+#[derive(Debug, thiserror::Error)]
 pub enum BuildError {
+    #[error("missing required field: {0}")]
     MissingRequiredField(&'static str),
 }
 
-impl fmt::Display for BuildError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            BuildError::MissingRequiredField(field) => {
-                write!(f, "missing required field: {}", field)
-            }
-        }
-    }
-}
-
-impl std::error::Error for BuildError {}
-
-#[derive(Debug, Clone)]
+/// Why a client or server runtime failed.
+///
+/// Errors wrapping an underlying cause expose it through
+/// [`Error::source`]. Causes are shared, so the error stays `Clone` and can be
+/// kept in the runtime state and in events.
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum RuntimeError {
-    IO(String),
-    Handshake(String),
+    /// The server did not answer a handshake in time.
+    #[error("server did not answer within {0:?}")]
+    Timeout(Duration),
+    /// The handshake response did not authenticate: the server holds another
+    /// key than the one configured, or the pre-shared key differs.
+    #[error("server response failed to authenticate, check the server key")]
+    UnknownServerKey,
+    /// The server refused the handshake.
+    #[error("rejected by server: {0}")]
+    Rejected(#[source] HandshakeError),
+    /// Sending or receiving on the transport failed.
+    #[error("transport: {0}")]
+    Transport(#[source] Arc<io::Error>),
+    /// Reading from or writing to the TUN device failed.
+    #[error("tun: {0}")]
+    Tun(#[source] Arc<io::Error>),
+    /// A Noise operation or packet encryption failed.
+    #[error("crypto: {0}")]
+    Crypto(#[source] Arc<dyn Error + Send + Sync>),
+    /// The peer sent a malformed or unsolicited message.
+    #[error("protocol: {0}")]
+    Protocol(String),
+    #[error("unexpected error: {0}")]
     Unexpected(String),
+    #[error("stop signal received")]
     StopSignal,
+    /// The server closed the session and the reconnect policy said to stop.
+    #[error("disconnected by server: {0}")]
+    Disconnected(DisconnectReason),
 }
 
-impl fmt::Display for RuntimeError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl RuntimeError {
+    /// Wrap a failed read or write on the TUN device.
+    pub fn tun(err: io::Error) -> Self {
+        RuntimeError::Tun(Arc::new(err))
+    }
+
+    /// Wrap a failed Noise operation or packet encryption.
+    pub fn crypto(err: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        RuntimeError::Crypto(Arc::from(err.into()))
+    }
+
+    /// Whether trying again later may succeed. Configuration mistakes, local
+    /// failures and explicit stops are not retryable. This decides the first
+    /// connection only; once connected the client retries every handshake
+    /// error.
+    pub fn is_retryable(&self) -> bool {
         match self {
-            RuntimeError::IO(s) => write!(f, "IO error: {}", s),
-            RuntimeError::Handshake(s) => write!(f, "handshake error: {}", s),
-            RuntimeError::Unexpected(s) => write!(f, "unexpected error: {}", s),
-            RuntimeError::StopSignal => write!(f, "stop signal received"),
+            RuntimeError::Timeout(_) | RuntimeError::Transport(_) | RuntimeError::Protocol(_) => {
+                true
+            }
+            RuntimeError::Rejected(err) => matches!(
+                err,
+                HandshakeError::ServerOverloaded
+                    | HandshakeError::MaxConnectedDevices(_)
+                    | HandshakeError::QuotaExceeded
+            ),
+            RuntimeError::UnknownServerKey
+            | RuntimeError::Tun(_)
+            | RuntimeError::Crypto(_)
+            | RuntimeError::Unexpected(_)
+            | RuntimeError::StopSignal
+            | RuntimeError::Disconnected(_) => false,
         }
     }
 }
 
-impl From<std::io::Error> for RuntimeError {
-    fn from(err: std::io::Error) -> Self {
-        RuntimeError::IO(err.to_string())
+impl From<io::Error> for RuntimeError {
+    fn from(err: io::Error) -> Self {
+        RuntimeError::Transport(Arc::new(err))
     }
 }
 
 impl From<snow::Error> for RuntimeError {
     fn from(err: snow::Error) -> Self {
-        RuntimeError::Handshake(format!("snow error: {err}"))
+        RuntimeError::crypto(err)
     }
 }
 
 impl<T> From<tokio::sync::broadcast::error::SendError<T>> for RuntimeError {
     fn from(err: tokio::sync::broadcast::error::SendError<T>) -> Self {
-        RuntimeError::IO(format!("broadcast send: {err}"))
+        RuntimeError::Unexpected(format!("broadcast send: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_chain() {
+        let err = RuntimeError::from(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+        assert!(err.is_retryable());
+        assert_eq!(err.source().unwrap().to_string(), "refused");
+        assert!(
+            matches!(&err, RuntimeError::Transport(e) if e.kind() == io::ErrorKind::ConnectionRefused)
+        );
+
+        let err = RuntimeError::Rejected(HandshakeError::MaxConnectedDevices(2));
+        assert_eq!(
+            err.to_string(),
+            "rejected by server: max connected devices: 2"
+        );
+        assert!(matches!(
+            err.source()
+                .and_then(|e| e.downcast_ref::<HandshakeError>()),
+            Some(HandshakeError::MaxConnectedDevices(2))
+        ));
+
+        assert!(!RuntimeError::from(snow::Error::Decrypt).is_retryable());
+        assert!(!RuntimeError::UnknownServerKey.is_retryable());
     }
 }
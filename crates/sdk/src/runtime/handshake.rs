@@ -1,19 +1,32 @@
 use snow::{Builder, HandshakeState, StatelessTransportState};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::select;
-use tracing::warn;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
 
 use crate::gateway::transport::ClientTransport;
 use crate::protocol::handshake::{alg_hint_byte, params_from_alg};
 use crate::protocol::{
-    Alg, EncryptedHandshake, HandshakeError, HandshakeResponderBody, HandshakeResponderPayload,
-    Packet,
+    Alg, COOKIE_REPLY_LEN, EncryptedHandshake, HandshakeInitiatorPayload, HandshakeResponderBody,
+    HandshakeResponderPayload, Packet, PacketRef, Rekey, SessionId,
 };
+use crate::runtime::cookie::CookieJar;
 use crate::runtime::cred::Cred;
 use crate::runtime::error::RuntimeError;
 
-fn initial(alg: &Alg, cred: &Cred) -> Result<(EncryptedHandshake, HandshakeState), RuntimeError> {
+/// Handshake traffic the receive task hands to the rekey task.
+pub(crate) enum HandshakeReply {
+    Response(EncryptedHandshake),
+    Cookie([u8; COOKIE_REPLY_LEN]),
+}
+
+pub(crate) fn initial(
+    alg: &Alg,
+    cred: &Cred,
+    payload: &HandshakeInitiatorPayload,
+) -> Result<(EncryptedHandshake, HandshakeState), RuntimeError> {
+    let payload = bincode::serde::encode_to_vec(payload, bincode::config::standard())
+        .map_err(|err| RuntimeError::Unexpected(format!("encode handshake payload: {}", err)))?;
     let mut initiator = Builder::new(params_from_alg(alg).clone())
         .local_private_key(cred.sk.as_slice())?
         .remote_public_key(cred.spk.as_slice())?
@@ -21,7 +34,7 @@ fn initial(alg: &Alg, cred: &Cred) -> Result<(EncryptedHandshake, HandshakeState
         .build_initiator()?;
 
     let mut buffer = [0u8; 65536];
-    let len = initiator.write_message(&[], &mut buffer)?;
+    let len = initiator.write_message(&payload, &mut buffer)?;
     // Prepend a 1-byte algorithm hint so the server can select the correct
     // Noise params on first read without a decrypt-then-retry heuristic.
     let mut msg = Vec::with_capacity(1 + len);
@@ -30,70 +43,179 @@ fn initial(alg: &Alg, cred: &Cred) -> Result<(EncryptedHandshake, HandshakeState
     Ok((msg.into(), initiator))
 }
 
-fn complete(
-    handshake: &EncryptedHandshake,
-    mut initiator: HandshakeState,
-) -> Result<(HandshakeResponderBody, StatelessTransportState), RuntimeError> {
+/// Open the server's response to `initiator`'s initiation, or `None` if it
+/// fails to authenticate. That leaves `initiator` as it was, so a late or
+/// forged response does not spoil the real one behind it.
+fn open_response(
+    initiator: &mut HandshakeState,
+    handshake: &[u8],
+) -> Result<Option<HandshakeResponderBody>, RuntimeError> {
     let mut buffer = [0u8; 65536];
-    let len = initiator.read_message(handshake, &mut buffer)?;
+    let len = match initiator.read_message(handshake, &mut buffer) {
+        Ok(len) => len,
+        Err(snow::Error::Decrypt) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
     match bincode::serde::decode_from_slice(&buffer[..len], bincode::config::standard()) {
-        Ok((body, _)) => Ok((body, initiator.into_stateless_transport_mode()?)),
-        Err(err) => Err(RuntimeError::Handshake(format!(
+        Ok((body, _)) => Ok(Some(body)),
+        Err(err) => Err(RuntimeError::Protocol(format!(
             "decode handshake complete packet: {}",
             err
         ))),
     }
 }
 
+/// Finish a handshake from a single response, as tests drive the server.
+#[cfg(test)]
+pub(crate) fn complete(
+    handshake: &EncryptedHandshake,
+    mut initiator: HandshakeState,
+) -> Result<(HandshakeResponderBody, StatelessTransportState), RuntimeError> {
+    let body = open_response(&mut initiator, handshake)?.ok_or(RuntimeError::UnknownServerKey)?;
+    Ok((body, initiator.into_stateless_transport_mode()?))
+}
+
+/// What a handshake that saw no valid response before `timeout` failed with.
+/// Responses that all failed to authenticate most likely mean the server key
+/// is wrong.
+fn timed_out(timeout: Duration, unauthenticated: bool) -> RuntimeError {
+    match unauthenticated {
+        true => RuntimeError::UnknownServerKey,
+        false => RuntimeError::Timeout(timeout),
+    }
+}
+
+/// Send `handshake` with its cookie MACs appended.
+async fn send_initial<T: ClientTransport>(
+    transport: &T,
+    cookies: &CookieJar,
+    handshake: &[u8],
+) -> Result<(), RuntimeError> {
+    transport
+        .send(&Packet::HandshakeInitial(cookies.stamp(handshake).into()).to_bytes())
+        .await?;
+    Ok(())
+}
+
+/// The server is under load and wants a cookie: store it and send `handshake`
+/// once more, now with `mac2`. Returns whether the initiation was resent.
+async fn retry_with_cookie<T: ClientTransport>(
+    transport: &T,
+    cookies: &CookieJar,
+    handshake: &[u8],
+    reply: &[u8; COOKIE_REPLY_LEN],
+) -> Result<bool, RuntimeError> {
+    if let Err(err) = cookies.consume(reply) {
+        warn!("{}", err);
+        return Ok(false);
+    }
+    debug!("server under load, resending handshake with cookie");
+    send_initial(transport, cookies, handshake).await?;
+    Ok(true)
+}
+
+/// Open a new session. `previous` is the session this client held before, so
+/// the server can release it right away instead of waiting for it to time out.
 pub async fn handshake_step<T: ClientTransport>(
     transport: Arc<T>,
     cred: &Cred,
     alg: &Alg,
+    cookies: &CookieJar,
+    previous: Option<SessionId>,
     timeout: Duration,
 ) -> Result<(HandshakeResponderPayload, StatelessTransportState), RuntimeError> {
-    let (handshake, handshake_state) = initial(alg, cred)?;
-    transport
-        .send(&Packet::HandshakeInitial(handshake).to_bytes())
-        .await?;
+    let payload = HandshakeInitiatorPayload::new(None).replacing(previous);
+    let (handshake, mut initiator) = initial(alg, cred, &payload)?;
+    send_initial(&*transport, cookies, &handshake).await?;
 
     let mut buffer = [0u8; 65536];
-    let resp = select! {
-        _ = tokio::time::sleep(timeout) => Err(RuntimeError::Handshake(
-            format!("server timeout ({:?})", timeout)
-        )),
-        handshake = async { loop {
-            let size = transport.recv(&mut buffer).await.map_err(
-                |err| RuntimeError::IO(format!("receive handshake: {}", err))
-            )?;
-            match Packet::try_from(&buffer[..size]) {
-                Ok(Packet::HandshakeResponder(handshake)) => break Ok(handshake),
-                Err(err) => {
-                    warn!("parse handshake packet: {}", err);
-                    continue;
+    let mut cookie_retried = false;
+    let mut unauthenticated = false;
+    let response = async {
+        loop {
+            let size = transport.recv(&mut buffer).await?;
+            match PacketRef::from_bytes(&buffer[..size]) {
+                Some(PacketRef::HandshakeResponder(data)) => {
+                    match open_response(&mut initiator, data)? {
+                        Some(body) => break Ok::<_, RuntimeError>(body),
+                        None => {
+                            unauthenticated = true;
+                            warn!("handshake response failed to authenticate, ignoring");
+                        }
+                    }
                 }
-                _ => {
-                    warn!("unexpected packet during handshake");
-                    continue;
+                Some(PacketRef::CookieReply(reply)) if !cookie_retried => {
+                    cookie_retried =
+                        retry_with_cookie(&*transport, cookies, &handshake, reply).await?;
                 }
+                None => warn!("parse handshake packet: unknown or truncated"),
+                _ => warn!("unexpected packet during handshake"),
             }
-        }} => handshake,
-    }?;
+        }
+    };
+    let body = tokio::time::timeout(timeout, response)
+        .await
+        .map_err(|_| timed_out(timeout, unauthenticated))??;
+    Ok((accepted(body)?, initiator.into_stateless_transport_mode()?))
+}
 
-    let (body, transport_state) = complete(&resp, handshake_state)?;
-    match body {
-        HandshakeResponderBody::Complete(payload) => Ok((payload, transport_state)),
-        HandshakeResponderBody::Disconnect(err) => match err {
-            HandshakeError::MaxConnectedDevices(max) => Err(RuntimeError::Handshake(format!(
-                "max connected devices: {}",
-                max
-            ))),
-            HandshakeError::ServerOverloaded => {
-                Err(RuntimeError::Handshake("server overloaded".into()))
+/// Re-key a live session.
+///
+/// While connected the receive task owns the socket, so the response is read
+/// from `responses` (fed by that task) rather than from the transport. A server
+/// that no longer knows `rekey.sid` answers with a brand-new session instead.
+pub(crate) async fn rekey_step<T: ClientTransport>(
+    transport: &T,
+    responses: &mut mpsc::Receiver<HandshakeReply>,
+    cred: &Cred,
+    alg: &Alg,
+    cookies: &CookieJar,
+    rekey: Rekey,
+    timeout: Duration,
+) -> Result<(HandshakeResponderPayload, StatelessTransportState), RuntimeError> {
+    // Drop responses left over from an attempt that already timed out.
+    while responses.try_recv().is_ok() {}
+
+    let payload = HandshakeInitiatorPayload::new(Some(rekey));
+    let (handshake, mut initiator) = initial(alg, cred, &payload)?;
+    send_initial(transport, cookies, &handshake).await?;
+
+    let mut cookie_retried = false;
+    let mut unauthenticated = false;
+    let response = async {
+        loop {
+            match responses.recv().await {
+                Some(HandshakeReply::Response(resp)) => {
+                    match open_response(&mut initiator, &resp)? {
+                        Some(body) => break Ok(body),
+                        None => {
+                            unauthenticated = true;
+                            warn!("rekey response failed to authenticate, ignoring");
+                        }
+                    }
+                }
+                Some(HandshakeReply::Cookie(reply)) if !cookie_retried => {
+                    cookie_retried =
+                        retry_with_cookie(transport, cookies, &handshake, &reply).await?;
+                }
+                Some(HandshakeReply::Cookie(_)) => warn!("repeated cookie reply, ignoring"),
+                None => {
+                    break Err(RuntimeError::Unexpected(
+                        "handshake response channel closed".into(),
+                    ));
+                }
             }
-            HandshakeError::Unexpected(err) => Err(RuntimeError::Handshake(format!(
-                "unexpected server error: {}",
-                err
-            ))),
-        },
+        }
+    };
+    let body = tokio::time::timeout(timeout, response)
+        .await
+        .map_err(|_| timed_out(timeout, unauthenticated))??;
+    Ok((accepted(body)?, initiator.into_stateless_transport_mode()?))
+}
+
+fn accepted(body: HandshakeResponderBody) -> Result<HandshakeResponderPayload, RuntimeError> {
+    match body {
+        HandshakeResponderBody::Complete(payload) => Ok(payload),
+        HandshakeResponderBody::Disconnect(err) => Err(RuntimeError::Rejected(err)),
     }
 }
@@ -0,0 +1,276 @@
+//! Per-session transport keys with overlapping epochs.
+//!
+//! A session starts on epoch 0 and moves to a fresh Noise transport state every
+//! time the client rekeys (WireGuard's `REKEY_AFTER_TIME` /
+//! `REKEY_AFTER_MESSAGES`). Every data frame carries the 1-byte epoch it was
+//! sealed under, so the receiver picks the matching state directly instead of
+//! trial-decrypting. The superseded state stays usable for [`KEY_OVERLAP`] so
+//! packets already in flight when the keys changed still decrypt.
+//!
+//! Each epoch owns its send nonce counter and anti-replay window: nonces
+//! restart at 0 under a new key, so the window has to restart with them.
+//!
+//! ```text
+//! initiator (client)                      responder (server)
+//!   rotate(e+1) on handshake response       stage(e+1) on handshake initial
+//!   └─ sends under e+1 right away           └─ keeps sending under e until the
+//!                                              first e+1 frame authenticates,
+//!                                              then promotes next → current
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use snow::StatelessTransportState;
+
+use crate::runtime::replay::ReplayWindow;
+
+/// How long a superseded epoch keeps decrypting after it was replaced.
+pub(crate) const KEY_OVERLAP: Duration = Duration::from_secs(10);
+
+/// One epoch's worth of transport keys.
+pub(crate) struct TransportKey {
+    pub(crate) epoch: u8,
+    pub(crate) noise: StatelessTransportState,
+    /// Monotonically increasing nonce for packets sealed under this key.
+    pub(crate) send_nonce: AtomicU64,
+    /// Anti-replay window for packets opened with this key.
+    pub(crate) recv_window: Mutex<ReplayWindow>,
+    pub(crate) created_at: Instant,
+}
+
+impl TransportKey {
+    pub(crate) fn new(epoch: u8, noise: StatelessTransportState) -> Self {
+        Self {
+            epoch,
+            noise,
+            send_nonce: AtomicU64::new(0),
+            recv_window: Mutex::new(ReplayWindow::new()),
+            created_at: Instant::now(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn next_nonce(&self) -> u64 {
+        self.send_nonce.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Whether this key has reached either rekey threshold.
+    pub(crate) fn is_expired(&self, after_time: Option<Duration>, after_messages: u64) -> bool {
+        after_time.is_some_and(|t| self.created_at.elapsed() >= t)
+            || self.send_nonce.load(Ordering::Relaxed) >= after_messages
+    }
+}
+
+struct Slots {
+    current: Arc<TransportKey>,
+    /// Superseded key and the instant it was retired.
+    previous: Option<(Arc<TransportKey>, Instant)>,
+    /// Responder only: negotiated, but not yet confirmed by a peer frame.
+    next: Option<Arc<TransportKey>>,
+}
+
+/// The current, previous and pending transport keys of one session.
+///
+/// Lookups load an immutable snapshot of the slots without locking. Changes
+/// publish a new snapshot under `rotation`, i.e. once per rekey interval.
+pub(crate) struct KeyRing {
+    slots: ArcSwap<Slots>,
+    /// Serializes writers so no rotation is lost between load and store.
+    rotation: Mutex<()>,
+}
+
+impl KeyRing {
+    pub(crate) fn new(noise: StatelessTransportState) -> Self {
+        Self {
+            slots: ArcSwap::from_pointee(Slots {
+                current: Arc::new(TransportKey::new(0, noise)),
+                previous: None,
+                next: None,
+            }),
+            rotation: Mutex::new(()),
+        }
+    }
+
+    /// Key used to seal outgoing packets.
+    #[inline]
+    pub(crate) fn current(&self) -> Arc<TransportKey> {
+        self.slots.load().current.clone()
+    }
+
+    /// Key to open a frame sealed under `epoch`, if it is still valid.
+    ///
+    /// A staged (responder) key is returned as it is; only [`confirm`] makes it
+    /// current, since the epoch travels in the clear.
+    ///
+    /// [`confirm`]: Self::confirm
+    #[inline]
+    pub(crate) fn get(&self, epoch: u8) -> Option<Arc<TransportKey>> {
+        let slots = self.slots.load();
+        if slots.current.epoch == epoch {
+            return Some(slots.current.clone());
+        }
+        if let Some((key, retired_at)) = &slots.previous
+            && key.epoch == epoch
+        {
+            return (retired_at.elapsed() < KEY_OVERLAP).then(|| key.clone());
+        }
+        slots
+            .next
+            .as_ref()
+            .filter(|next| next.epoch == epoch)
+            .cloned()
+    }
+
+    /// A frame sealed under `key` authenticated. If `key` is the staged one,
+    /// that confirms the peer switched over, so it becomes current.
+    #[inline]
+    pub(crate) fn confirm(&self, key: &Arc<TransportKey>) {
+        let is_next = |slots: &Slots| matches!(&slots.next, Some(next) if Arc::ptr_eq(next, key));
+        if !is_next(&self.slots.load()) {
+            return;
+        }
+        let _rotation = self.lock();
+        let slots = self.slots.load_full();
+        // Another task may have promoted it before we took the lock.
+        if is_next(&slots) {
+            self.slots.store(Arc::new(Slots {
+                current: key.clone(),
+                previous: Some((slots.current.clone(), Instant::now())),
+                next: None,
+            }));
+        }
+    }
+
+    /// Initiator side: start sealing under `noise` at once and keep the old key
+    /// around for the overlap window.
+    pub(crate) fn rotate(&self, epoch: u8, noise: StatelessTransportState) {
+        let _rotation = self.lock();
+        let old = self.slots.load().current.clone();
+        self.slots.store(Arc::new(Slots {
+            current: Arc::new(TransportKey::new(epoch, noise)),
+            previous: Some((old, Instant::now())),
+            next: None,
+        }));
+    }
+
+    /// Responder side: hold `noise` back until the peer sends under `epoch`,
+    /// since the peer may not have received the handshake response yet.
+    pub(crate) fn stage(&self, epoch: u8, noise: StatelessTransportState) {
+        let _rotation = self.lock();
+        let slots = self.slots.load();
+        self.slots.store(Arc::new(Slots {
+            current: slots.current.clone(),
+            previous: slots.previous.clone(),
+            next: Some(Arc::new(TransportKey::new(epoch, noise))),
+        }));
+    }
+
+    /// The lock guards no data, so a writer that panicked leaves nothing
+    /// half-done behind and poisoning is ignored.
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.rotation.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyRing")
+            .field("epoch", &self.current().epoch)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::crypto::make_noise_pair_for_test;
+
+    #[test]
+    fn test_starts_on_epoch_zero() {
+        let (state, _) = make_noise_pair_for_test();
+        let ring = KeyRing::new(state);
+        assert_eq!(ring.current().epoch, 0);
+        assert!(ring.get(0).is_some());
+        assert!(ring.get(1).is_none());
+    }
+
+    #[test]
+    fn test_rotate_keeps_previous_for_overlap() {
+        let (s0, _) = make_noise_pair_for_test();
+        let (s1, _) = make_noise_pair_for_test();
+        let ring = KeyRing::new(s0);
+        ring.rotate(1, s1);
+
+        assert_eq!(ring.current().epoch, 1);
+        assert!(ring.get(0).is_some(), "previous epoch must still open");
+        assert!(ring.get(1).is_some());
+    }
+
+    #[test]
+    fn test_previous_expires_after_overlap() {
+        let (s0, _) = make_noise_pair_for_test();
+        let (s1, _) = make_noise_pair_for_test();
+        let ring = KeyRing::new(s0);
+        ring.rotate(1, s1);
+        let slots = ring.slots.load_full();
+        let (key, retired_at) = slots.previous.clone().unwrap();
+        ring.slots.store(Arc::new(Slots {
+            current: slots.current.clone(),
+            previous: Some((key, retired_at - KEY_OVERLAP)),
+            next: None,
+        }));
+        assert!(ring.get(0).is_none());
+    }
+
+    #[test]
+    fn test_staged_key_promoted_once_confirmed() {
+        let (s0, _) = make_noise_pair_for_test();
+        let (s1, _) = make_noise_pair_for_test();
+        let ring = KeyRing::new(s0);
+        ring.stage(1, s1);
+
+        // Still sealing under the confirmed key.
+        assert_eq!(ring.current().epoch, 0);
+
+        let staged = ring.get(1).unwrap();
+        assert_eq!(staged.epoch, 1);
+        assert_eq!(ring.current().epoch, 0, "not before a frame authenticates");
+
+        ring.confirm(&ring.get(0).unwrap());
+        assert_eq!(ring.current().epoch, 0);
+        ring.confirm(&staged);
+        assert_eq!(ring.current().epoch, 1);
+        assert!(ring.get(0).is_some(), "old epoch stays inside the overlap");
+    }
+
+    #[test]
+    fn test_fresh_epoch_has_fresh_nonce_and_window() {
+        let (s0, _) = make_noise_pair_for_test();
+        let (s1, _) = make_noise_pair_for_test();
+        let ring = KeyRing::new(s0);
+        let old = ring.current();
+        old.next_nonce();
+        assert!(old.recv_window.lock().unwrap().check_and_update(0));
+
+        ring.rotate(1, s1);
+        let new = ring.current();
+        assert_eq!(new.next_nonce(), 0);
+        assert!(new.recv_window.lock().unwrap().check_and_update(0));
+    }
+
+    #[test]
+    fn test_expiry_thresholds() {
+        let (s0, _) = make_noise_pair_for_test();
+        let key = TransportKey::new(0, s0);
+        assert!(!key.is_expired(Some(Duration::from_secs(60)), 10));
+        assert!(key.is_expired(Some(Duration::ZERO), 10));
+        for _ in 0..10 {
+            key.next_nonce();
+        }
+        assert!(key.is_expired(None, 10));
+    }
+}
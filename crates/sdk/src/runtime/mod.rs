@@ -1,8 +1,11 @@
 pub mod client;
+pub(crate) mod cookie;
 pub mod cred;
 pub(crate) mod crypto;
+pub mod enroll;
 pub mod error;
 pub(crate) mod handshake;
+pub(crate) mod keys;
 pub(crate) mod replay;
 pub mod server;
 pub mod state;
@@ -1,21 +1,45 @@
+mod auth;
+mod handle;
 mod handshake;
+mod metrics;
 mod network;
 mod recv;
 mod recv_pool;
 pub mod session;
 
-use std::{net::IpAddr, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    sync::Arc,
+    time::Duration,
+};
 
 use dashmap::DashMap;
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 use tokio::task::JoinSet;
-use tracing::info;
+use tracing::{debug, info, warn};
 
-use self::session::Sessions;
-use self::{handshake::handshake_executor, network::encrypt_forward, recv::recv_decrypt_forward};
+pub use self::auth::{ClientAuth, ClientAuthenticator, ClientPolicy};
+pub use self::handle::ServerHandle;
+pub use self::handshake::{DeviceLimit, DeviceLimitPolicy};
+use self::metrics::Metrics;
+pub use self::metrics::{ServerMetrics, render_prometheus};
+pub use self::session::RateLimit;
+pub use self::session::SessionEvent;
+use self::session::{Disconnects, HolyIp, Lease, Session, Sessions};
+use self::{
+    handshake::{
+        HandshakeContext, HandshakeGate, HandshakeQueue, Initiations, NetworkConfigs,
+        handshake_worker,
+    },
+    network::{disconnect_executor, encrypt_forward, send_disconnect},
+    recv::recv_decrypt_forward,
+};
 use crate::crypto::{PublicKey, SecretKey};
 use crate::gateway::network::Network;
 use crate::gateway::transport::Transport;
+use crate::protocol::{DisconnectReason, NetworkConfig};
+use crate::runtime::cookie::CookieChecker;
 use crate::runtime::error::{BuildError, RuntimeError};
 
 pub struct ServerBuilder<T: Transport + 'static, N: Network + 'static> {
@@ -23,12 +47,21 @@ pub struct ServerBuilder<T: Transport + 'static, N: Network + 'static> {
     network: Arc<N>,
     sk: Option<SecretKey>,
     known_clients: Arc<DashMap<PublicKey, SecretKey>>,
+    authenticator: Option<Arc<dyn ClientAuthenticator>>,
     ip: Option<IpAddr>,
     prefix: u8,
+    ipv6: Option<(Ipv6Addr, u8)>,
+    network_configs: NetworkConfigs,
     session_timeout: Option<Duration>,
     session_cleanup_interval: Duration,
     handshake_buf: usize,
+    handshake_workers: Option<usize>,
     decrypt_workers: usize,
+    device_limit: Option<DeviceLimit>,
+    rate_limit: RateLimit,
+    lease_grace: Option<Duration>,
+    pinned: HashMap<PublicKey, HolyIp>,
+    leases: Vec<(PublicKey, Lease)>,
 }
 
 impl<T: Transport + 'static, N: Network + 'static> ServerBuilder<T, N> {
@@ -38,12 +71,21 @@ impl<T: Transport + 'static, N: Network + 'static> ServerBuilder<T, N> {
             network: Arc::new(network),
             sk: None,
             known_clients: Arc::new(DashMap::new()),
+            authenticator: None,
             ip: None,
             prefix: 24,
+            ipv6: None,
+            network_configs: NetworkConfigs::default(),
             session_timeout: Some(Duration::from_secs(60 * 5)),
             session_cleanup_interval: Duration::from_secs(60),
             handshake_buf: 1000,
+            handshake_workers: None,
             decrypt_workers: 0,
+            device_limit: None,
+            rate_limit: RateLimit::default(),
+            lease_grace: None,
+            pinned: HashMap::new(),
+            leases: Vec::new(),
         }
     }
 
@@ -57,6 +99,14 @@ impl<T: Transport + 'static, N: Network + 'static> ServerBuilder<T, N> {
         self
     }
 
+    /// Resolve clients through `authenticator` instead of the built-in table
+    /// of [`known_clients`](Self::known_clients), which
+    /// [`ServerHandle::add_client`] then no longer affects.
+    pub fn authenticator(mut self, authenticator: impl ClientAuthenticator + 'static) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
     /// Set the VPN server IP and subnet prefix used for client session assignment.
     pub fn ip(mut self, ip: IpAddr, prefix: u8) -> Self {
         self.ip = Some(ip);
@@ -64,6 +114,24 @@ impl<T: Transport + 'static, N: Network + 'static> ServerBuilder<T, N> {
         self
     }
 
+    /// Also give every session an address from this IPv6 subnet (dual stack).
+    pub fn ipv6(mut self, ip: Ipv6Addr, prefix: u8) -> Self {
+        self.ipv6 = Some((ip, prefix));
+        self
+    }
+
+    /// Network settings pushed to every client in the handshake response.
+    pub fn network_config(mut self, config: NetworkConfig) -> Self {
+        self.network_configs.default = config;
+        self
+    }
+
+    /// Push `config` to client `pk` instead of the server-wide `network_config`.
+    pub fn client_network_config(mut self, pk: PublicKey, config: NetworkConfig) -> Self {
+        self.network_configs.per_client.insert(pk, config);
+        self
+    }
+
     /// Set session inactivity timeout. `None` disables cleanup.
     pub fn session_timeout(mut self, timeout: Option<Duration>) -> Self {
         self.session_timeout = timeout;
@@ -80,6 +148,52 @@ impl<T: Transport + 'static, N: Network + 'static> ServerBuilder<T, N> {
         self
     }
 
+    /// Number of handshake workers shared by all transports. Defaults to one
+    /// per transport; raise it to spread reconnect storms across cores.
+    pub fn handshake_workers(mut self, count: usize) -> Self {
+        self.handshake_workers = Some(count.max(1));
+        self
+    }
+
+    /// Allow each client key at most `max` concurrent sessions. A handshake
+    /// over the limit is refused or replaces the key's oldest session,
+    /// depending on `policy`. Unlimited by default.
+    pub fn device_limit(mut self, max: u32, policy: DeviceLimitPolicy) -> Self {
+        self.device_limit = Some(DeviceLimit {
+            max: max.max(1),
+            policy,
+        });
+        self
+    }
+
+    /// Limit every session to `limit`, unless the client's
+    /// [`ClientPolicy`] overrides it. Packets over the rate are dropped.
+    /// Unlimited by default.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = limit;
+        self
+    }
+
+    /// Keep a client's tunnel addresses reserved for `grace` after its last
+    /// session ends and give them back when it reconnects. Idle leases are
+    /// swept by the session cleanup worker.
+    pub fn sticky_leases(mut self, grace: Duration) -> Self {
+        self.lease_grace = Some(grace);
+        self
+    }
+
+    /// Always give client `pk` the tunnel address `ip` from the `ip` subnet.
+    pub fn pin_address(mut self, pk: PublicKey, ip: HolyIp) -> Self {
+        self.pinned.insert(pk, ip);
+        self
+    }
+
+    /// Sticky leases saved from [`ServerHandle::leases`] before a restart.
+    pub fn leases(mut self, leases: Vec<(PublicKey, Lease)>) -> Self {
+        self.leases = leases;
+        self
+    }
+
     /// Number of parallel decrypt workers **per receive socket**.
     ///
     /// `0` or `1` keeps the single-task receive path (one core per flow). `>= 2`
@@ -92,6 +206,31 @@ impl<T: Transport + 'static, N: Network + 'static> ServerBuilder<T, N> {
     }
 
     pub fn build(self) -> Result<Server<T, N>, BuildError> {
+        let ip = self.ip.ok_or(BuildError::MissingRequiredField("ip"))?;
+        // Subscribed here, not in `run`, so a shutdown requested before `run`
+        // starts is still seen as a change by every task.
+        let (stop, stop_rx) = watch::channel(false);
+        let (stopped, _) = watch::channel(false);
+        let (disconnects, disconnect_queue) = mpsc::unbounded_channel();
+        let mut sessions = Sessions::new(&ip, self.prefix);
+        if let Some((ip6, prefix6)) = self.ipv6 {
+            sessions = sessions.with_ipv6(&ip6, prefix6);
+        }
+        if let Some(grace) = self.lease_grace {
+            sessions = sessions.with_sticky_leases(grace);
+        }
+        // Pins first: they win over sticky leases for the same address.
+        for (pk, ip) in self.pinned {
+            if !sessions.pin(&pk, ip) {
+                warn!("cannot pin {} to {}: outside the subnet or taken", pk, ip);
+            }
+        }
+        for (pk, lease) in self.leases {
+            if !sessions.restore_lease(pk.clone(), lease) {
+                debug!("dropping saved lease {} of {}", lease.ip, pk);
+            }
+        }
+        let handshake_workers = self.handshake_workers.unwrap_or(self.transports.len());
         Ok(Server {
             transports: if self.transports.is_empty() {
                 return Err(BuildError::MissingRequiredField(
@@ -104,13 +243,25 @@ impl<T: Transport + 'static, N: Network + 'static> ServerBuilder<T, N> {
             sk: self
                 .sk
                 .ok_or(BuildError::MissingRequiredField("secret_key"))?,
+            authenticator: self
+                .authenticator
+                .unwrap_or_else(|| self.known_clients.clone()),
             known_clients: self.known_clients,
-            ip: self.ip.ok_or(BuildError::MissingRequiredField("ip"))?,
-            prefix: self.prefix,
+            network_configs: Arc::new(self.network_configs),
+            sessions,
             session_timeout: self.session_timeout,
             session_cleanup_interval: self.session_cleanup_interval,
             handshake_buf: self.handshake_buf,
+            handshake_workers,
             decrypt_workers: self.decrypt_workers,
+            device_limit: self.device_limit,
+            rate_limit: self.rate_limit,
+            stop,
+            stop_rx,
+            stopped,
+            disconnects,
+            disconnect_queue,
+            metrics: Arc::default(),
         })
     }
 }
@@ -120,24 +271,51 @@ pub struct Server<T: Transport + 'static, N: Network + 'static> {
     network: Arc<N>,
     sk: SecretKey,
     known_clients: Arc<DashMap<PublicKey, SecretKey>>,
-    ip: IpAddr,
-    prefix: u8,
+    authenticator: Arc<dyn ClientAuthenticator>,
+    network_configs: Arc<NetworkConfigs>,
+    sessions: Sessions,
     session_timeout: Option<Duration>,
     session_cleanup_interval: Duration,
     handshake_buf: usize,
+    handshake_workers: usize,
     decrypt_workers: usize,
+    device_limit: Option<DeviceLimit>,
+    rate_limit: RateLimit,
+    stop: watch::Sender<bool>,
+    stop_rx: watch::Receiver<bool>,
+    stopped: watch::Sender<bool>,
+    disconnects: Disconnects,
+    disconnect_queue: mpsc::UnboundedReceiver<(Arc<Session>, DisconnectReason)>,
+    metrics: Arc<Metrics>,
 }
 
 impl<T: Transport + 'static, N: Network + 'static> Server<T, N> {
-    pub async fn run(self) -> Result<std::convert::Infallible, RuntimeError> {
-        let sessions = Sessions::new(&self.ip, self.prefix);
-        let (_stop_tx, stop_rx) = watch::channel::<bool>(false);
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            stop: self.stop.clone(),
+            stopped: self.stopped.subscribe(),
+            known_clients: self.known_clients.clone(),
+            sessions: self.sessions.clone(),
+            disconnects: self.disconnects.clone(),
+            metrics: self.metrics.clone(),
+            rate_limit: self.rate_limit,
+        }
+    }
+
+    /// Run until [`ServerHandle::shutdown`] is called (`Ok`) or every worker
+    /// exits on its own (`Err`).
+    pub async fn run(self) -> Result<(), RuntimeError> {
+        let sessions = self.sessions.clone();
+        let stop_rx = self.stop_rx.clone();
+        let cookies = Arc::new(CookieChecker::new(&PublicKey::from_secret(&self.sk)));
+        let (handshake_tx, handshake_rx) = mpsc::channel(self.handshake_buf);
+        let handshake_queue: HandshakeQueue = Arc::new(tokio::sync::Mutex::new(handshake_rx));
 
         let mut set: JoinSet<()> = JoinSet::new();
 
-        for transport in self.transports {
+        for (index, transport) in self.transports.iter().cloned().enumerate() {
             let network = self.network.clone();
-            let (handshake_tx, handshake_rx) = tokio::sync::mpsc::channel(self.handshake_buf);
+            let handshake = HandshakeGate::new(handshake_tx.clone(), cookies.clone(), index);
             let inf_timeout = self.session_timeout.is_none();
 
             // Hot path 1: UDP → decrypt → network (+ inline keepalive responses).
@@ -150,7 +328,8 @@ impl<T: Transport + 'static, N: Network + 'static> Server<T, N> {
                     transport.clone(),
                     network.clone(),
                     sessions.clone(),
-                    handshake_tx,
+                    handshake,
+                    self.metrics.clone(),
                     inf_timeout,
                     self.decrypt_workers,
                 ));
@@ -160,7 +339,8 @@ impl<T: Transport + 'static, N: Network + 'static> Server<T, N> {
                     transport.clone(),
                     network.clone(),
                     sessions.clone(),
-                    handshake_tx,
+                    handshake,
+                    self.metrics.clone(),
                     inf_timeout,
                 ));
             }
@@ -169,21 +349,41 @@ impl<T: Transport + 'static, N: Network + 'static> Server<T, N> {
             set.spawn(encrypt_forward(
                 stop_rx.clone(),
                 network,
-                transport.clone(),
+                transport,
                 sessions.clone(),
+                self.metrics.clone(),
             ));
+        }
+        drop(handshake_tx);
 
-            // Rare path: handshake completion
-            set.spawn(handshake_executor(
+        // Rare path: handshake completion, one pool for every transport
+        let handshake_ctx = Arc::new(HandshakeContext {
+            sk: self.sk.clone(),
+            auth: self.authenticator.clone(),
+            sessions: sessions.clone(),
+            initiations: Initiations::new(),
+            network: self.network_configs.clone(),
+            device_limit: self.device_limit,
+            rate_limit: self.rate_limit,
+            disconnects: self.disconnects.clone(),
+            metrics: self.metrics.clone(),
+        });
+        for _ in 0..self.handshake_workers {
+            set.spawn(handshake_worker(
                 stop_rx.clone(),
-                handshake_rx,
-                transport,
-                self.known_clients.clone(),
-                sessions.clone(),
-                self.sk.clone(),
+                handshake_queue.clone(),
+                self.transports.clone(),
+                handshake_ctx.clone(),
             ));
         }
 
+        // Disconnects for sessions closed through the handle
+        set.spawn(disconnect_executor(
+            stop_rx.clone(),
+            self.disconnect_queue,
+            self.transports[0].clone(),
+        ));
+
         if let Some(timeout) = self.session_timeout {
             info!("session cleanup worker started (timeout: {:?})", timeout);
             set.spawn(session::worker::run(
@@ -202,8 +402,398 @@ impl<T: Transport + 'static, N: Network + 'static> Server<T, N> {
             }
         }
 
-        Err(RuntimeError::Unexpected(
-            "all workers exited unexpectedly".into(),
-        ))
+        if !*stop_rx.borrow() {
+            return Err(RuntimeError::Unexpected(
+                "all workers exited unexpectedly".into(),
+            ));
+        }
+
+        // Every reuseport socket shares the listen address, so any of them
+        // reaches any client.
+        let transport = &self.transports[0];
+        for session in sessions.all() {
+            if let Err(e) =
+                send_disconnect(&**transport, &session, DisconnectReason::Shutdown).await
+            {
+                warn!("[{}] failed to send disconnect: {}", session.sock_addr(), e);
+            }
+            sessions.closed(&session, DisconnectReason::Shutdown);
+        }
+        info!("server stopped");
+        self.stopped.send_replace(true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::gateway::network::{NetworkReceiver, NetworkSender};
+    use crate::gateway::transport::TransportReceiver;
+    use crate::gateway::transport::mock::MockTransport;
+    use crate::protocol::{Alg, PacketRef};
+    use crate::runtime::client::{ClientBuilder, ClientEvent, ReconnectAction, ReconnectPolicy};
+    use crate::runtime::cred::Cred;
+    use crate::runtime::crypto::{
+        DataServerActionRef, make_noise_pair_for_test, noise_decrypt_data_server_into,
+    };
+    use crate::runtime::state::RuntimeState;
+
+    /// TUN stand-in that never yields a packet.
+    struct IdleNetwork;
+
+    impl NetworkSender for IdleNetwork {
+        async fn send_to(&self, data: &[u8], _addr: &SocketAddr) -> io::Result<usize> {
+            Ok(data.len())
+        }
+        async fn send(&self, data: &[u8]) -> io::Result<usize> {
+            Ok(data.len())
+        }
+    }
+
+    impl NetworkReceiver for IdleNetwork {
+        async fn recv_from(&self, _buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            std::future::pending().await
+        }
+        async fn recv(&self, _buffer: &mut [u8]) -> io::Result<usize> {
+            std::future::pending().await
+        }
+    }
+
+    impl Network for IdleNetwork {
+        fn mtu(&self) -> u16 {
+            1420
+        }
+    }
+
+    /// Read one frame from `client_tp` and decode it as a `Disconnect`.
+    async fn recv_disconnect(
+        client_tp: &MockTransport,
+        client_state: &snow::StatelessTransportState,
+    ) -> DisconnectReason {
+        let mut buf = [0u8; 256];
+        let n = client_tp.recv(&mut buf).await.unwrap();
+        let Some(PacketRef::DataServer {
+            epoch,
+            nonce,
+            ciphertext,
+        }) = PacketRef::from_bytes(&buf[..n])
+        else {
+            panic!("expected a DataServer frame");
+        };
+        assert_eq!(epoch, 0);
+        let mut plain = [0u8; 256];
+        match noise_decrypt_data_server_into(ciphertext, client_state, &mut plain, nonce) {
+            Ok(DataServerActionRef::Disconnect(reason)) => reason,
+            _ => panic!("expected a disconnect"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remove_client_closes_its_sessions() {
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let psk = SecretKey::generate_x25519();
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let server = ServerBuilder::new(vec![server_tp], IdleNetwork)
+            .secret_key(SecretKey::generate_x25519())
+            .known_clients(vec![(peer_pk.clone(), psk)])
+            .ip("10.0.0.0".parse().unwrap(), 24)
+            .build()
+            .unwrap();
+
+        let (client_state, server_state) = make_noise_pair_for_test();
+        let sid = server.sessions.next_session_id().unwrap();
+        let ip = server.sessions.next_holy_ip().unwrap();
+        server.sessions.add(
+            sid,
+            ip,
+            None,
+            client_tp.local_addr(),
+            peer_pk.clone(),
+            Alg::ChaCha20Poly1305,
+            server_state,
+        );
+
+        let handle = server.handle();
+        let run = tokio::spawn(server.run());
+        assert_eq!(handle.remove_client(&peer_pk), 1);
+        assert!(!handle.known_clients.contains_key(&peer_pk));
+        assert!(handle.sessions.get_by_sid(&sid).is_none());
+        assert_eq!(
+            recv_disconnect(&client_tp, &client_state).await,
+            DisconnectReason::Revoked
+        );
+
+        handle.shutdown().await.unwrap();
+        assert!(run.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_psk_rotation_keeps_pin() {
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let pinned: HolyIp = "10.0.0.42".parse().unwrap();
+        let server = ServerBuilder::new(vec![server_tp], IdleNetwork)
+            .secret_key(SecretKey::generate_x25519())
+            .known_clients(vec![(peer_pk.clone(), SecretKey::generate_x25519())])
+            .ip("10.0.0.0".parse().unwrap(), 24)
+            .pin_address(peer_pk.clone(), pinned)
+            .build()
+            .unwrap();
+
+        let (client_state, server_state) = make_noise_pair_for_test();
+        let sid = server.sessions.next_session_id().unwrap();
+        let (ip, ip6) = server.sessions.take_lease(&peer_pk).unwrap();
+        assert_eq!(ip, pinned);
+        server.sessions.add(
+            sid,
+            ip,
+            ip6,
+            client_tp.local_addr(),
+            peer_pk.clone(),
+            Alg::ChaCha20Poly1305,
+            server_state,
+        );
+
+        let handle = server.handle();
+        let run = tokio::spawn(server.run());
+        // What `server start` does when a user's PSK changes.
+        handle.add_client(peer_pk.clone(), SecretKey::generate_x25519());
+        assert_eq!(
+            handle.disconnect_peer(&peer_pk, DisconnectReason::Revoked),
+            1
+        );
+        assert_eq!(
+            recv_disconnect(&client_tp, &client_state).await,
+            DisconnectReason::Revoked
+        );
+        assert_eq!(handle.sessions.pinned(&peer_pk), Some(pinned));
+        assert_eq!(handle.sessions.take_lease(&peer_pk).unwrap().0, pinned);
+
+        handle.shutdown().await.unwrap();
+        assert!(run.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_closes_one_session() {
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let server = ServerBuilder::new(vec![server_tp], IdleNetwork)
+            .secret_key(SecretKey::generate_x25519())
+            .known_clients(vec![(peer_pk.clone(), SecretKey::generate_x25519())])
+            .ip("10.0.0.0".parse().unwrap(), 24)
+            .build()
+            .unwrap();
+
+        let mut sids = Vec::new();
+        let mut states = Vec::new();
+        for _ in 0..2 {
+            let (client_state, server_state) = make_noise_pair_for_test();
+            let sid = server.sessions.next_session_id().unwrap();
+            let ip = server.sessions.next_holy_ip().unwrap();
+            server.sessions.add(
+                sid,
+                ip,
+                None,
+                client_tp.local_addr(),
+                peer_pk.clone(),
+                Alg::ChaCha20Poly1305,
+                server_state,
+            );
+            sids.push(sid);
+            states.push(client_state);
+        }
+
+        let handle = server.handle();
+        let mut events = handle.events();
+        let run = tokio::spawn(server.run());
+        assert!(handle.disconnect(sids[0], DisconnectReason::Kicked));
+        assert!(!handle.disconnect(sids[0], DisconnectReason::Kicked));
+        match events.try_recv().unwrap() {
+            SessionEvent::Closed { sid, reason, .. } => {
+                assert_eq!((sid, reason), (sids[0], DisconnectReason::Kicked))
+            }
+            _ => panic!("expected Closed"),
+        }
+        assert!(handle.session(sids[0]).is_none());
+        assert_eq!(handle.sessions_of(&peer_pk)[0].id, sids[1]);
+        assert_eq!(
+            recv_disconnect(&client_tp, &states[0]).await,
+            DisconnectReason::Kicked
+        );
+        assert_eq!(handle.sessions().len(), 1);
+        assert!(handle.known_clients.contains_key(&peer_pk));
+
+        handle.shutdown().await.unwrap();
+        assert!(run.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_client_pause_and_stop_free_the_session() {
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let server_sk = SecretKey::generate_x25519();
+        let client_sk = SecretKey::generate_x25519();
+        let psk = SecretKey::generate_x25519();
+        let server = ServerBuilder::new(vec![server_tp], IdleNetwork)
+            .secret_key(server_sk.clone())
+            .known_clients(vec![(PublicKey::from_secret(&client_sk), psk.clone())])
+            .ip("10.0.0.0".parse().unwrap(), 24)
+            .build()
+            .unwrap();
+        let handle = server.handle();
+        let mut events = handle.events();
+        let run = tokio::spawn(server.run());
+
+        let client = ClientBuilder::new(client_tp, IdleNetwork)
+            .keepalive(None)
+            .cred(Cred {
+                sk: client_sk,
+                psk,
+                spk: PublicKey::from_secret(&server_sk),
+            })
+            .build()
+            .unwrap();
+        let client_handle = client.handle();
+        let mut state = client.subscribe();
+        let client_run = tokio::spawn(client.run());
+
+        let steps = async {
+            for _ in 0..2 {
+                state
+                    .wait_for(|state| matches!(state, RuntimeState::Connected(_)))
+                    .await
+                    .unwrap();
+                assert!(matches!(
+                    events.recv().await.unwrap(),
+                    SessionEvent::Created { .. }
+                ));
+                assert!(client_handle.pause().await);
+                assert!(matches!(
+                    events.recv().await.unwrap(),
+                    SessionEvent::Left { .. }
+                ));
+                assert!(handle.sessions().is_empty());
+                assert!(client_handle.resume());
+            }
+            state
+                .wait_for(|state| matches!(state, RuntimeState::Connected(_)))
+                .await
+                .unwrap();
+            client_handle.stop().await;
+            assert!(matches!(
+                events.recv().await.unwrap(),
+                SessionEvent::Created { .. }
+            ));
+            assert!(matches!(
+                events.recv().await.unwrap(),
+                SessionEvent::Left { .. }
+            ));
+            client_run.await.unwrap()
+        };
+        let result = tokio::time::timeout(Duration::from_secs(10), steps)
+            .await
+            .expect("client did not stop");
+        assert!(matches!(result, Err(RuntimeError::StopSignal)));
+        assert!(!client_handle.resume());
+
+        handle.shutdown().await.unwrap();
+        assert!(run.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_client_events_follow_the_session() {
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let server_sk = SecretKey::generate_x25519();
+        let client_sk = SecretKey::generate_x25519();
+        let psk = SecretKey::generate_x25519();
+        let server = ServerBuilder::new(vec![server_tp], IdleNetwork)
+            .secret_key(server_sk.clone())
+            .known_clients(vec![(PublicKey::from_secret(&client_sk), psk.clone())])
+            .ip("10.0.0.0".parse().unwrap(), 24)
+            .build()
+            .unwrap();
+        let handle = server.handle();
+        let run = tokio::spawn(server.run());
+
+        let client = ClientBuilder::new(client_tp, IdleNetwork)
+            .alg(Alg::ChaCha20Poly1305)
+            .keepalive(None)
+            .reconnect_policy(
+                ReconnectPolicy::default().on(DisconnectReason::Kicked, ReconnectAction::Stop),
+            )
+            .cred(Cred {
+                sk: client_sk,
+                psk,
+                spk: PublicKey::from_secret(&server_sk),
+            })
+            .build()
+            .unwrap();
+        let mut events = client.events();
+        let client_run = tokio::spawn(client.run());
+
+        let steps = async {
+            assert!(matches!(
+                events.recv().await.unwrap(),
+                ClientEvent::HandshakeStarted
+            ));
+            let sid = match events.recv().await.unwrap() {
+                ClientEvent::HandshakeCompleted { payload, alg } => {
+                    assert_eq!(alg, Alg::ChaCha20Poly1305);
+                    payload.sid
+                }
+                _ => panic!("expected HandshakeCompleted"),
+            };
+            assert!(handle.disconnect(sid, DisconnectReason::Kicked));
+            assert!(matches!(
+                events.recv().await.unwrap(),
+                ClientEvent::DisconnectReceived(DisconnectReason::Kicked)
+            ));
+            client_run.await.unwrap()
+        };
+        let result = tokio::time::timeout(Duration::from_secs(10), steps)
+            .await
+            .expect("client did not stop");
+        assert!(matches!(
+            result,
+            Err(RuntimeError::Disconnected(DisconnectReason::Kicked))
+        ));
+
+        handle.shutdown().await.unwrap();
+        assert!(run.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_disconnects_sessions_and_returns_ok() {
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let server = ServerBuilder::new(vec![server_tp], IdleNetwork)
+            .secret_key(SecretKey::generate_x25519())
+            .ip("10.0.0.0".parse().unwrap(), 24)
+            .build()
+            .unwrap();
+
+        let (client_state, server_state) = make_noise_pair_for_test();
+        let sid = server.sessions.next_session_id().unwrap();
+        let ip = server.sessions.next_holy_ip().unwrap();
+        server.sessions.add(
+            sid,
+            ip,
+            None,
+            client_tp.local_addr(),
+            PublicKey::from_secret(&SecretKey::generate_x25519()),
+            Alg::ChaCha20Poly1305,
+            server_state,
+        );
+
+        let handle = server.handle();
+        let run = tokio::spawn(server.run());
+        handle.shutdown().await.unwrap();
+        assert!(run.await.unwrap().is_ok());
+        assert_eq!(
+            recv_disconnect(&client_tp, &client_state).await,
+            DisconnectReason::Shutdown
+        );
     }
 }
@@ -0,0 +1,125 @@
+//! Resolving client keys to their PSK and per-user policy.
+//!
+//! Every handshake asks the server's [`ClientAuthenticator`] about the key the
+//! initiation was signed with. The default is the in-memory table filled by
+//! [`ServerBuilder::known_clients`](super::ServerBuilder::known_clients) and
+//! [`ServerHandle::add_client`](super::ServerHandle::add_client); account
+//! systems plug in through
+//! [`ServerBuilder::authenticator`](super::ServerBuilder::authenticator).
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+
+use super::session::HolyIp;
+use crate::crypto::{PublicKey, SecretKey};
+
+/// What a known client may do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientPolicy {
+    /// `false` refuses every handshake and closes the live sessions.
+    pub allowed: bool,
+    /// Pin the client to this tunnel address.
+    pub address: Option<HolyIp>,
+    /// Concurrent sessions, overriding the server's device limit; `Some(0)`
+    /// lifts the limit for this client.
+    pub max_devices: Option<u32>,
+    /// Handshakes are refused from this moment on.
+    pub expires_at: Option<SystemTime>,
+    /// Bytes per second from the client, overriding the server's
+    /// [`RateLimit`](super::RateLimit); `Some(0)` lifts the limit.
+    pub upload_limit: Option<u64>,
+    /// Bytes per second to the client, like `upload_limit`.
+    pub download_limit: Option<u64>,
+    /// The client's traffic quota is used up: handshakes are refused with
+    /// [`HandshakeError::QuotaExceeded`](crate::protocol::HandshakeError::QuotaExceeded)
+    /// and live sessions closed until it is cleared.
+    pub quota_exceeded: bool,
+}
+
+impl Default for ClientPolicy {
+    fn default() -> Self {
+        Self {
+            allowed: true,
+            address: None,
+            max_devices: None,
+            expires_at: None,
+            upload_limit: None,
+            download_limit: None,
+            quota_exceeded: false,
+        }
+    }
+}
+
+impl ClientPolicy {
+    /// Whether a handshake at `now` is refused.
+    pub fn denies(&self, now: SystemTime) -> bool {
+        !self.allowed || self.expires_at.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// A resolved client: the PSK its handshakes are keyed with, and its policy.
+#[derive(Clone)]
+pub struct ClientAuth {
+    pub psk: SecretKey,
+    pub policy: ClientPolicy,
+}
+
+impl ClientAuth {
+    /// A client with the default policy.
+    pub fn new(psk: SecretKey) -> Self {
+        Self {
+            psk,
+            policy: ClientPolicy::default(),
+        }
+    }
+}
+
+/// Looks up clients by their static public key.
+///
+/// Called from the handshake workers once per initiation, after the Noise
+/// message proved the sender holds the key. Slow backends delay only other
+/// handshakes, never the data path; put a cache in front of remote services.
+pub trait ClientAuthenticator: Send + Sync {
+    /// Resolve `pk`. `Ok(None)` marks an unknown key and the initiation goes
+    /// unanswered; `Err` means the backend failed and the initiation is
+    /// dropped, so the client retries.
+    fn authenticate<'a>(
+        &'a self,
+        pk: &'a PublicKey,
+    ) -> BoxFuture<'a, anyhow::Result<Option<ClientAuth>>>;
+}
+
+/// The built-in table: known keys and their PSKs, all on the default policy.
+impl ClientAuthenticator for DashMap<PublicKey, SecretKey> {
+    fn authenticate<'a>(
+        &'a self,
+        pk: &'a PublicKey,
+    ) -> BoxFuture<'a, anyhow::Result<Option<ClientAuth>>> {
+        let auth = self.get(pk).map(|psk| ClientAuth::new(psk.clone()));
+        Box::pin(async move { Ok(auth) })
+    }
+}
+
+/// A table of resolved clients, for servers that keep policies in memory and
+/// update them while running; share it through an [`Arc`].
+impl ClientAuthenticator for DashMap<PublicKey, ClientAuth> {
+    fn authenticate<'a>(
+        &'a self,
+        pk: &'a PublicKey,
+    ) -> BoxFuture<'a, anyhow::Result<Option<ClientAuth>>> {
+        let auth = self.get(pk).map(|auth| auth.clone());
+        Box::pin(async move { Ok(auth) })
+    }
+}
+
+impl<A: ClientAuthenticator + ?Sized> ClientAuthenticator for Arc<A> {
+    fn authenticate<'a>(
+        &'a self,
+        pk: &'a PublicKey,
+    ) -> BoxFuture<'a, anyhow::Result<Option<ClientAuth>>> {
+        (**self).authenticate(pk)
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::sync::{broadcast, watch};
+use tracing::info;
+
+use super::ClientPolicy;
+use super::metrics::{Metrics, ServerMetrics, render_prometheus};
+use super::session::{Disconnects, HolyIp, Lease, RateLimit, Session, SessionEvent, Sessions};
+use crate::crypto::{PublicKey, SecretKey};
+use crate::protocol::{DisconnectReason, SessionId};
+use crate::runtime::error::RuntimeError;
+
+/// Cloneable control handle for a [`Server`](super::Server).
+///
+/// Obtained via [`Server::handle`](super::Server::handle) before calling
+/// `run`, and stays valid for the whole lifetime of the server.
+#[derive(Clone)]
+pub struct ServerHandle {
+    pub(super) stop: watch::Sender<bool>,
+    pub(super) stopped: watch::Receiver<bool>,
+    pub(super) known_clients: Arc<DashMap<PublicKey, SecretKey>>,
+    pub(super) sessions: Sessions,
+    pub(super) disconnects: Disconnects,
+    pub(super) metrics: Arc<Metrics>,
+    pub(super) rate_limit: RateLimit,
+}
+
+impl ServerHandle {
+    /// Allow `pk` to handshake with `psk`. Replaces the PSK of a known client;
+    /// its live sessions keep running until they rekey. Only the built-in
+    /// table; a custom [`ClientAuthenticator`](super::ClientAuthenticator)
+    /// is not consulted.
+    pub fn add_client(&self, pk: PublicKey, psk: SecretKey) {
+        info!("client {} added", pk);
+        self.known_clients.insert(pk, psk);
+    }
+
+    /// Forget `pk`, its address leases and close its live sessions with
+    /// [`DisconnectReason::Revoked`]. Returns the number of closed sessions.
+    /// This drops a pinned address too; to close the sessions of a client
+    /// whose PSK changed, use [`disconnect_peer`](Self::disconnect_peer).
+    pub fn remove_client(&self, pk: &PublicKey) -> usize {
+        self.known_clients.remove(pk);
+        let count = self.disconnect_peer(pk, DisconnectReason::Revoked);
+        self.sessions.drop_lease(pk);
+        info!("client {} removed, {} session(s) closed", pk, count);
+        count
+    }
+
+    /// Every live session.
+    pub fn sessions(&self) -> Vec<Arc<Session>> {
+        self.sessions.all()
+    }
+
+    /// Live session `sid`.
+    pub fn session(&self, sid: SessionId) -> Option<Arc<Session>> {
+        self.sessions.get_by_sid(&sid)
+    }
+
+    /// Live session holding tunnel address `ip`.
+    pub fn session_by_ip(&self, ip: &HolyIp) -> Option<Arc<Session>> {
+        self.sessions.get_by_holy_ip(ip)
+    }
+
+    /// Live sessions of client `pk`, oldest first.
+    pub fn sessions_of(&self, pk: &PublicKey) -> Vec<Arc<Session>> {
+        self.sessions.by_peer(pk)
+    }
+
+    /// Subscribe to the [`SessionEvent`]s of every session: created, roamed,
+    /// expired, left, or closed by the server with a reason. Sessions already
+    /// live when subscribing are not replayed; list them with
+    /// [`sessions`](Self::sessions).
+    pub fn events(&self) -> broadcast::Receiver<SessionEvent> {
+        self.sessions.subscribe()
+    }
+
+    /// Close session `sid` and tell its client why. `false` when no such
+    /// session is live.
+    pub fn disconnect(&self, sid: SessionId, reason: DisconnectReason) -> bool {
+        let closed = self
+            .sessions
+            .get_by_sid(&sid)
+            .is_some_and(|session| self.sessions.close(session, reason, &self.disconnects));
+        if closed {
+            info!("session {} closed: {}", sid, reason);
+        }
+        closed
+    }
+
+    /// Close every session of client `pk` and tell it why. The client stays
+    /// known and may connect again. Returns the number of closed sessions.
+    pub fn disconnect_peer(&self, pk: &PublicKey, reason: DisconnectReason) -> usize {
+        self.sessions
+            .by_peer(pk)
+            .into_iter()
+            .filter(|session| {
+                self.sessions
+                    .close(session.clone(), reason, &self.disconnects)
+            })
+            .count()
+    }
+
+    /// Apply the server's rate limit with the overrides of `policy` to the
+    /// live sessions of `pk`; new sessions get them from the authenticator.
+    /// Returns the number of sessions.
+    pub fn set_rate_limit(&self, pk: &PublicKey, policy: &ClientPolicy) -> usize {
+        let limit = self.rate_limit.for_client(policy);
+        let sessions = self.sessions.by_peer(pk);
+        for session in &sessions {
+            session.shaper.set(limit);
+        }
+        sessions.len()
+    }
+
+    /// Pin client `pk` to `ip`, or drop its pin with `None`. Takes effect on
+    /// its next new session. `false` when `ip` is outside the subnet or
+    /// another client holds it.
+    pub fn pin_address(&self, pk: &PublicKey, ip: Option<HolyIp>) -> bool {
+        match ip {
+            Some(ip) => self.sessions.pin(pk, ip),
+            None => {
+                self.sessions.unpin(pk);
+                true
+            }
+        }
+    }
+
+    /// Snapshot of the sticky leases, to hand to
+    /// [`ServerBuilder::leases`](super::ServerBuilder::leases) after a restart.
+    pub fn leases(&self) -> Vec<(PublicKey, Lease)> {
+        self.sessions.leases()
+    }
+
+    /// Snapshot of the server-wide counters.
+    pub fn metrics(&self) -> ServerMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Server-wide counters and per-peer traffic in the Prometheus text
+    /// format, plus per-session traffic with `per_session`; see
+    /// [`render_prometheus`].
+    pub fn prometheus(&self, per_session: bool) -> String {
+        render_prometheus(&self.metrics.snapshot(), &self.sessions.all(), per_session)
+    }
+
+    /// Gracefully stop the server.
+    ///
+    /// Signals every task to stop, waits for the handshake, receive and encrypt
+    /// tasks to drain, then sends `DataServerBody::Disconnect` to every live
+    /// session. Returns once `Server::run` has finished.
+    pub async fn shutdown(&self) -> Result<(), RuntimeError> {
+        self.stop.send_replace(true);
+        let mut stopped = self.stopped.clone();
+        // A dropped sender means the server is already gone.
+        let _ = stopped.wait_for(|stopped| *stopped).await;
+        Ok(())
+    }
+}
@@ -1,167 +1,1136 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{Ipv6Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use dashmap::DashMap;
-use snow::Builder;
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+use snow::{Builder, HandshakeState};
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::watch;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
-use super::session::Sessions;
+use super::auth::{ClientAuth, ClientAuthenticator, ClientPolicy};
+use super::metrics::Metrics;
+use super::session::{Disconnects, HolyIp, RateLimit, Session, Sessions};
 use crate::crypto::{PublicKey, SecretKey};
 use crate::gateway::transport::Transport;
 use crate::protocol::handshake::{alg_from_hint_byte, params_from_alg};
 use crate::protocol::{
-    Alg, EncryptedHandshake, HandshakeError, HandshakeResponderBody, HandshakeResponderPayload,
-    Packet,
+    Alg, DisconnectReason, EncryptedHandshake, HandshakeError, HandshakeInitiatorPayload,
+    HandshakeResponderBody, HandshakeResponderPayload, NetworkConfig, Packet, SessionId, Tai64N,
 };
-use crate::runtime::cred::ServerCredential;
-
-fn decode_handshake_params(
-    handshake: &EncryptedHandshake,
-    sk: &SecretKey,
-) -> anyhow::Result<(PublicKey, Alg)> {
-    let (hint, noise_msg) = handshake
-        .split_first()
-        .ok_or_else(|| anyhow::anyhow!("empty handshake"))?;
-    let alg = alg_from_hint_byte(*hint)
-        .ok_or_else(|| anyhow::anyhow!("unknown algorithm hint byte: 0x{:02x}", hint))?;
-
-    let mut buffer = [0u8; 65536];
-    let mut responder = Builder::new(params_from_alg(&alg).clone())
-        .local_private_key(sk.as_slice())?
-        .build_responder()?;
-    responder.read_message(noise_msg, &mut buffer)?;
-
-    match responder
-        .get_remote_static()
-        .map(|bytes: &[u8]| PublicKey::try_from(bytes))
-    {
-        Some(Ok(key)) => Ok((key, alg)),
-        Some(Err(e)) => Err(anyhow::anyhow!("invalid remote static key: {}", e)),
-        None => Err(anyhow::anyhow!("invalid handshake: missing remote static")),
+use crate::runtime::cookie::{CookieChecker, MACS_LEN};
+
+/// The handshake queue counts as under load once this fraction (`1/N`) of it
+/// is occupied; from then on initiations need a valid cookie.
+const UNDER_LOAD_DIVISOR: usize = 8;
+
+/// Entry to the handshake queue, shared by the receive tasks.
+///
+/// Drops initiations with a bad `mac1` before any Noise work. While the queue
+/// is under load, sources without a valid `mac2` get a cookie reply instead of
+/// a queue slot.
+#[derive(Clone)]
+pub(super) struct HandshakeGate {
+    queue: mpsc::Sender<(EncryptedHandshake, SocketAddr, usize)>,
+    cookies: Arc<CookieChecker>,
+    /// Index of the transport this gate's receive task reads from
+    transport: usize,
+}
+
+impl HandshakeGate {
+    pub(super) fn new(
+        queue: mpsc::Sender<(EncryptedHandshake, SocketAddr, usize)>,
+        cookies: Arc<CookieChecker>,
+        transport: usize,
+    ) -> Self {
+        Self {
+            queue,
+            cookies,
+            transport,
+        }
+    }
+
+    fn under_load(&self) -> bool {
+        let queued = self.queue.max_capacity() - self.queue.capacity();
+        queued >= (self.queue.max_capacity() / UNDER_LOAD_DIVISOR).max(1)
+    }
+
+    /// Check the MACs of `msg` (a `HandshakeInitial` body from `addr`) and queue
+    /// it for the handshake executor, or answer with a cookie reply.
+    pub(super) async fn admit<T: Transport>(&self, transport: &T, msg: &[u8], addr: SocketAddr) {
+        if !self.cookies.check_mac1(msg) {
+            debug!("[{}] handshake with invalid mac1, dropping", addr);
+            return;
+        }
+        if self.under_load() && !self.cookies.check_mac2(msg, &addr) {
+            debug!("[{}] handshake queue under load, sending cookie", addr);
+            if let Err(e) = transport
+                .send_to(&self.cookies.reply(msg, &addr), &addr)
+                .await
+            {
+                warn!("[{}] failed to send cookie reply: {}", addr, e);
+            }
+            return;
+        }
+        let handshake = msg[..msg.len() - MACS_LEN].to_vec().into();
+        match self.queue.try_send((handshake, addr, self.transport)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("[{}] handshake queue full, dropping", addr),
+            Err(TrySendError::Closed(_)) => error!("handshake queue closed"),
+        }
     }
 }
 
-/// `noise_msg` must be the handshake payload with the leading algorithm-hint
-/// byte already stripped (i.e. `&handshake[1..]`).
-async fn complete(
-    noise_msg: &[u8],
-    cred: &ServerCredential,
-    alg: Alg,
-    addr: &SocketAddr,
+/// Network settings pushed in handshake responses, with per-client overrides.
+#[derive(Default)]
+pub(super) struct NetworkConfigs {
+    pub(super) default: NetworkConfig,
+    pub(super) per_client: HashMap<PublicKey, NetworkConfig>,
+}
+
+impl NetworkConfigs {
+    fn for_client(&self, pk: &PublicKey) -> &NetworkConfig {
+        self.per_client.get(pk).unwrap_or(&self.default)
+    }
+}
+
+/// Newest initiation timestamp accepted from each client key.
+pub(super) type Initiations = DashMap<PublicKey, Tai64N>;
+
+/// Record `timestamp` for `pk` unless an equal or newer one was already
+/// accepted, which makes a captured `HandshakeInitial` useless to replay.
+fn fresh_initiation(initiations: &Initiations, pk: &PublicKey, timestamp: Tai64N) -> bool {
+    match initiations.entry(pk.clone()) {
+        Entry::Occupied(last) if *last.get() >= timestamp => false,
+        Entry::Occupied(mut last) => {
+            last.insert(timestamp);
+            true
+        }
+        Entry::Vacant(last) => {
+            last.insert(timestamp);
+            true
+        }
+    }
+}
+
+/// What to do with a handshake from a user already at the device limit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceLimitPolicy {
+    /// Close the user's oldest session with [`DisconnectReason::Replaced`]
+    /// once the new one has its addresses.
+    #[default]
+    ReplaceOldest,
+    /// Refuse the handshake with [`HandshakeError::MaxConnectedDevices`].
+    RejectNew,
+}
+
+/// Upper bound on concurrent sessions per client key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceLimit {
+    pub max: u32,
+    pub policy: DeviceLimitPolicy,
+}
+
+/// Reserve a session id and tunnel addresses for a new session of `peer_pk`,
+/// preferring its pinned or sticky lease, and hand back whatever was already
+/// taken when a pool is exhausted.
+fn addresses(
     sessions: &Sessions,
-) -> anyhow::Result<EncryptedHandshake> {
-    let mut responder = Builder::new(params_from_alg(&alg).clone())
-        .local_private_key(cred.sk.as_slice())?
-        .remote_public_key(cred.peer_pk.as_slice())?
-        .psk(2, cred.psk.as_bytes())?
-        .build_responder()?;
-
-    let mut buffer = [0u8; 65536];
-    let _len = responder.read_message(noise_msg, &mut buffer)?;
-
-    let (body, keys) = match sessions.next_session_id() {
-        Some(sid) => match sessions.next_holy_ip() {
-            Some(ipaddr) => {
-                info!("[{}] session created with sid: {}", addr, sid);
-                (
-                    HandshakeResponderBody::Complete(HandshakeResponderPayload { sid, ipaddr }),
-                    Some((sid, ipaddr)),
-                )
-            }
+    peer_pk: &PublicKey,
+) -> Result<(HolyIp, Option<Ipv6Addr>), &'static str> {
+    if let Some(addresses) = sessions.take_lease(peer_pk) {
+        return Ok(addresses);
+    }
+    let ip = sessions.next_holy_ip().ok_or("no holy ip available")?;
+    let ip6 = if sessions.has_ipv6() {
+        match sessions.next_holy_ip6() {
+            Some(ip6) => Some(ip6),
             None => {
-                warn!("[{}] failed to create session: no holy ip available", addr);
-                sessions.release_session_id(&sid);
-                (
-                    HandshakeResponderBody::Disconnect(HandshakeError::ServerOverloaded),
-                    None,
-                )
+                sessions.release_holy_ip(&ip);
+                return Err("no holy ipv6 available");
             }
-        },
-        None => {
-            warn!(
-                "[{}] failed to create session: no session id available",
-                addr
-            );
-            (
-                HandshakeResponderBody::Disconnect(HandshakeError::ServerOverloaded),
-                None,
-            )
         }
+    } else {
+        None
     };
+    sessions.record_lease(peer_pk, ip, ip6);
+    Ok((ip, ip6))
+}
+
+/// An initiation read without the PSK, waiting for its client to be resolved.
+struct Initiation {
+    alg: Alg,
+    responder: HandshakeState,
+    peer_pk: PublicKey,
+    payload: HandshakeInitiatorPayload,
+}
+
+/// Initiations waiting for a worker, with the index of the transport they
+/// arrived on. The receiver is shared by every worker.
+pub(super) type HandshakeQueue =
+    Arc<tokio::sync::Mutex<mpsc::Receiver<(EncryptedHandshake, SocketAddr, usize)>>>;
+
+/// State shared by all handshake workers.
+pub(super) struct HandshakeContext {
+    pub(super) sk: SecretKey,
+    pub(super) auth: Arc<dyn ClientAuthenticator>,
+    pub(super) sessions: Sessions,
+    pub(super) initiations: Initiations,
+    pub(super) network: Arc<NetworkConfigs>,
+    pub(super) device_limit: Option<DeviceLimit>,
+    /// Rates of sessions whose policy does not override them
+    pub(super) rate_limit: RateLimit,
+    /// Sessions pushed out by [`DeviceLimitPolicy::ReplaceOldest`]
+    pub(super) disconnects: Disconnects,
+    pub(super) metrics: Arc<Metrics>,
+}
 
-    let len = responder.write_message(
-        &bincode::serde::encode_to_vec(&body, bincode::config::standard())?,
-        &mut buffer,
-    )?;
+impl HandshakeContext {
+    /// Make room for a new session of `peer_pk`: release the session the
+    /// client says it held before, then apply the device limit, or the
+    /// client's own `max_devices`. Returns the sessions to replace once the
+    /// new one is allocated; `Err` carries the limit when the handshake must
+    /// be refused.
+    fn make_room(
+        &self,
+        peer_pk: &PublicKey,
+        policy: &ClientPolicy,
+        previous: Option<SessionId>,
+        addr: &SocketAddr,
+    ) -> Result<Vec<Arc<Session>>, u32> {
+        if let Some(previous) = previous
+            && let Some(session) = self.sessions.release_owned(previous, peer_pk)
+        {
+            info!("[{}] released previous session {}", addr, previous);
+            self.sessions.closed(&session, DisconnectReason::Replaced);
+        }
+        let limit = match policy.max_devices {
+            Some(0) => None,
+            Some(max) => Some(DeviceLimit {
+                max,
+                policy: self.device_limit.map(|l| l.policy).unwrap_or_default(),
+            }),
+            None => self.device_limit,
+        };
+        let Some(limit) = limit else {
+            return Ok(Vec::new());
+        };
+        let owned = self.sessions.by_peer(peer_pk);
+        let excess = (owned.len() + 1).saturating_sub(limit.max as usize);
+        if excess == 0 {
+            return Ok(Vec::new());
+        }
+        match limit.policy {
+            DeviceLimitPolicy::RejectNew => Err(limit.max),
+            DeviceLimitPolicy::ReplaceOldest => Ok(owned.into_iter().take(excess).collect()),
+        }
+    }
 
-    if let Some((sid, holy_ip)) = keys {
-        sessions.add(
-            sid,
-            holy_ip,
-            *addr,
+    /// Allocate a session id and addresses for `peer_pk`, then close the
+    /// sessions it replaces. A replaced session holding the client's lease
+    /// hands its addresses over, so it is closed first; the others are left
+    /// alone when allocation fails.
+    fn allocate(
+        &self,
+        peer_pk: &PublicKey,
+        replaced: Vec<Arc<Session>>,
+        addr: &SocketAddr,
+    ) -> Result<(SessionId, HolyIp, Option<Ipv6Addr>), &'static str> {
+        let sid = self
+            .sessions
+            .next_session_id()
+            .ok_or("no session id available")?;
+        let (handover, rest): (Vec<_>, Vec<_>) = replaced
+            .into_iter()
+            .partition(|session| self.sessions.holds_lease(session));
+        for session in handover {
+            self.replace(session, addr);
+        }
+        match addresses(&self.sessions, peer_pk) {
+            Ok((ip, ip6)) => {
+                for session in rest {
+                    self.replace(session, addr);
+                }
+                Ok((sid, ip, ip6))
+            }
+            Err(reason) => {
+                self.sessions.release_session_id(&sid);
+                Err(reason)
+            }
+        }
+    }
+
+    /// Close `session` to keep its client within the device limit.
+    fn replace(&self, session: Arc<Session>, addr: &SocketAddr) {
+        let sid = session.id;
+        if self
+            .sessions
+            .close(session, DisconnectReason::Replaced, &self.disconnects)
+        {
+            info!("[{}] device limit reached, replaced session {}", addr, sid);
+        }
+    }
+
+    /// Close every live session of `peer_pk`, which may no longer connect,
+    /// telling the clients `reason`.
+    fn revoke(&self, peer_pk: &PublicKey, reason: DisconnectReason) {
+        for session in self.sessions.by_peer(peer_pk) {
+            self.sessions.close(session, reason, &self.disconnects);
+        }
+    }
+
+    /// Answer one initiation (algorithm hint byte included) in a single Noise
+    /// pass.
+    pub(super) async fn complete(
+        &self,
+        handshake: &[u8],
+        addr: &SocketAddr,
+    ) -> anyhow::Result<EncryptedHandshake> {
+        let initiation = self.read(handshake)?;
+        let auth = self
+            .auth
+            .authenticate(&initiation.peer_pk)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("unknown client: {}", initiation.peer_pk))?;
+        self.respond(initiation, auth, addr)
+    }
+
+    /// Read an initiation up to the point where the client's PSK is needed.
+    fn read(&self, handshake: &[u8]) -> anyhow::Result<Initiation> {
+        let (hint, noise_msg) = handshake
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("empty handshake"))?;
+        let alg = alg_from_hint_byte(*hint)
+            .ok_or_else(|| anyhow::anyhow!("unknown algorithm hint byte: 0x{:02x}", hint))?;
+
+        let mut responder = Builder::new(params_from_alg(&alg).clone())
+            .local_private_key(self.sk.as_slice())?
+            .build_responder()?;
+
+        // IKpsk2 only mixes the PSK into the response, so the initiation can be
+        // read before we know which client sent it.
+        let mut buffer = [0u8; 65536];
+        let len = responder.read_message(noise_msg, &mut buffer)?;
+        let peer_pk = match responder
+            .get_remote_static()
+            .map(|bytes: &[u8]| PublicKey::try_from(bytes))
+        {
+            Some(Ok(key)) => key,
+            Some(Err(e)) => anyhow::bail!("invalid remote static key: {}", e),
+            None => anyhow::bail!("invalid handshake: missing remote static"),
+        };
+        let (payload, _) =
+            bincode::serde::decode_from_slice(&buffer[..len], bincode::config::standard())?;
+        Ok(Initiation {
             alg,
-            responder.into_stateless_transport_mode()?,
-        );
+            responder,
+            peer_pk,
+            payload,
+        })
     }
 
-    Ok(buffer[..len].to_vec().into())
+    /// Finish the handshake of a resolved client.
+    fn respond(
+        &self,
+        initiation: Initiation,
+        auth: ClientAuth,
+        addr: &SocketAddr,
+    ) -> anyhow::Result<EncryptedHandshake> {
+        let Initiation {
+            alg,
+            mut responder,
+            peer_pk,
+            payload: initiator,
+        } = initiation;
+        responder.set_psk(2, auth.psk.as_bytes())?;
+        let mut buffer = [0u8; 65536];
+
+        if !fresh_initiation(&self.initiations, &peer_pk, initiator.timestamp) {
+            anyhow::bail!("replayed or stale initiation from {}", peer_pk);
+        }
+        let refusal = if auth.policy.denies(SystemTime::now()) {
+            Some((HandshakeError::Denied, DisconnectReason::Revoked))
+        } else if auth.policy.quota_exceeded {
+            Some((
+                HandshakeError::QuotaExceeded,
+                DisconnectReason::QuotaExceeded,
+            ))
+        } else {
+            None
+        };
+        if let Some((error, reason)) = refusal {
+            warn!("[{}] {} refused by policy: {}", addr, peer_pk, error);
+            self.revoke(&peer_pk, reason);
+            let body = HandshakeResponderBody::Disconnect(error);
+            let len = responder.write_message(
+                &bincode::serde::encode_to_vec(&body, bincode::config::standard())?,
+                &mut buffer,
+            )?;
+            self.metrics.handshake_rejected();
+            return Ok(buffer[..len].to_vec().into());
+        }
+        if let Some(ip) = auth.policy.address
+            && self.sessions.pinned(&peer_pk) != Some(ip)
+            && !self.sessions.pin(&peer_pk, ip)
+        {
+            warn!(
+                "[{}] cannot pin {} to {}: outside the subnet or taken",
+                addr, peer_pk, ip
+            );
+        }
+        let network = self.network.for_client(&peer_pk);
+
+        // Rekey: same client, same session — only the transport keys change.
+        if let Some(rekey) = initiator.rekey {
+            match self.sessions.get_by_sid(&rekey.sid) {
+                Some(session) if session.peer_pk == peer_pk => {
+                    let body = HandshakeResponderBody::Complete(HandshakeResponderPayload {
+                        sid: session.id,
+                        ipaddr: session.holy_ip,
+                        ipv6: session.holy_ip6,
+                        network: network.clone(),
+                    });
+                    let len = responder.write_message(
+                        &bincode::serde::encode_to_vec(&body, bincode::config::standard())?,
+                        &mut buffer,
+                    )?;
+                    session
+                        .keys
+                        .stage(rekey.epoch, responder.into_stateless_transport_mode()?);
+                    info!(
+                        "[{}] session {} rekeyed to epoch {}",
+                        addr, session.id, rekey.epoch
+                    );
+                    self.metrics.handshake_accepted();
+                    return Ok(buffer[..len].to_vec().into());
+                }
+                _ => warn!(
+                    "[{}] rekey for unknown session {}, creating a new one",
+                    addr, rekey.sid
+                ),
+            }
+        }
+
+        let allocated = match self.make_room(&peer_pk, &auth.policy, initiator.previous, addr) {
+            Ok(replaced) => self.allocate(&peer_pk, replaced, addr).map_err(|reason| {
+                warn!("[{}] failed to create session: {}", addr, reason);
+                HandshakeError::ServerOverloaded
+            }),
+            Err(max) => {
+                warn!("[{}] {} is at its device limit ({})", addr, peer_pk, max);
+                Err(HandshakeError::MaxConnectedDevices(max))
+            }
+        };
+        let (body, keys) = match allocated {
+            Ok((sid, ipaddr, ipv6)) => {
+                info!("[{}] session created with sid: {}", addr, sid);
+                (
+                    HandshakeResponderBody::Complete(HandshakeResponderPayload {
+                        sid,
+                        ipaddr,
+                        ipv6,
+                        network: network.clone(),
+                    }),
+                    Some((sid, ipaddr, ipv6)),
+                )
+            }
+            Err(err) => (HandshakeResponderBody::Disconnect(err), None),
+        };
+
+        let len = responder.write_message(
+            &bincode::serde::encode_to_vec(&body, bincode::config::standard())?,
+            &mut buffer,
+        )?;
+
+        match keys {
+            Some(_) => self.metrics.handshake_accepted(),
+            None => self.metrics.handshake_rejected(),
+        }
+        if let Some((sid, holy_ip, holy_ip6)) = keys {
+            let session = self.sessions.add(
+                sid,
+                holy_ip,
+                holy_ip6,
+                *addr,
+                peer_pk,
+                alg,
+                responder.into_stateless_transport_mode()?,
+            );
+            session.shaper.set(self.rate_limit.for_client(&auth.policy));
+        }
+
+        Ok(buffer[..len].to_vec().into())
+    }
 }
 
-pub(super) async fn handshake_executor<T: Transport>(
+/// One handshake worker. Any number of them drain the shared `queue` and answer
+/// on the transport each initiation arrived on.
+pub(super) async fn handshake_worker<T: Transport>(
     mut stop: watch::Receiver<bool>,
-    mut queue: mpsc::Receiver<(EncryptedHandshake, SocketAddr)>,
-    transport: Arc<T>,
-    known_clients: Arc<DashMap<PublicKey, SecretKey>>,
-    sessions: Sessions,
-    sk: SecretKey,
+    queue: HandshakeQueue,
+    transports: Vec<Arc<T>>,
+    ctx: Arc<HandshakeContext>,
 ) {
     // Encode buffer for handshake responses (handshakes are rare, but we still
     // avoid per-call allocation by reusing this buffer across iterations).
     let mut encode_buf = [0u8; 4096];
 
     loop {
-        tokio::select! {
+        let next = tokio::select! {
             _ = stop.changed() => break,
-            data = queue.recv() => match data {
-                Some((handshake, addr)) => match decode_handshake_params(&handshake, &sk) {
-                    Ok((peer_pk, alg)) => match known_clients.get(&peer_pk) {
-                        Some(psk) => {
-                            let cred = ServerCredential {
-                                sk: sk.clone(),
-                                psk: psk.clone(),
-                                peer_pk,
-                            };
-                            match complete(&handshake[1..], &cred, alg, &addr, &sessions).await {
-                                Ok(response) => {
-                                    let pkt = Packet::HandshakeResponder(response);
-                                    match bincode::encode_into_slice(
-                                        &pkt,
-                                        &mut encode_buf,
-                                        bincode::config::standard(),
-                                    ) {
-                                        Ok(n) => match transport.send_to(&encode_buf[..n], &addr).await {
-                                            Ok(_) => info!("[{}] handshake complete", addr),
-                                            Err(e) => warn!("[{}] failed to send handshake response: {}", addr, e),
-                                        },
-                                        Err(e) => warn!("[{}] failed to encode handshake response: {}", addr, e),
-                                    }
-                                }
-                                Err(err) => warn!("[{}] failed to complete handshake: {}", addr, err),
-                            }
-                        }
-                        None => {
-                            warn!("[{}] received handshake from unknown client: {}", addr, peer_pk);
-                        }
-                    },
-                    Err(e) => warn!("[{}] failed to decode handshake params: {}", addr, e),
-                },
-                None => {
-                    debug!("handshake_executor channel closed");
-                    break;
+            next = async { queue.lock().await.recv().await } => next,
+        };
+        let Some((handshake, addr, transport)) = next else {
+            debug!("handshake queue closed");
+            break;
+        };
+        let response = match ctx.complete(&handshake, &addr).await {
+            Ok(response) => response,
+            Err(err) => {
+                ctx.metrics.handshake_rejected();
+                warn!("[{}] failed to complete handshake: {}", addr, err);
+                continue;
+            }
+        };
+        let pkt = Packet::HandshakeResponder(response);
+        match bincode::encode_into_slice(&pkt, &mut encode_buf, bincode::config::standard()) {
+            Ok(n) => match transports[transport].send_to(&encode_buf[..n], &addr).await {
+                Ok(_) => info!("[{}] handshake complete", addr),
+                Err(e) => warn!("[{}] failed to send handshake response: {}", addr, e),
+            },
+            Err(e) => warn!("[{}] failed to encode handshake response: {}", addr, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::transport::mock::MockTransport;
+    use crate::gateway::transport::{TransportReceiver, TransportSender};
+    use crate::protocol::{Alg, COOKIE_REPLY_LEN, COOKIE_REPLY_TYPE, PacketRef, Rekey};
+    use crate::runtime::cookie::{CookieJar, MACS_LEN};
+    use crate::runtime::cred::Cred;
+    use crate::runtime::error::RuntimeError;
+    use crate::runtime::handshake::{complete as client_complete, handshake_step, initial};
+    use std::time::Duration;
+
+    /// Handshake context plus the client table its PSK lookup reads.
+    struct TestServer {
+        ctx: HandshakeContext,
+        known: Arc<DashMap<PublicKey, SecretKey>>,
+        disconnects: mpsc::UnboundedReceiver<(Arc<Session>, DisconnectReason)>,
+    }
+
+    fn server(sessions: Sessions) -> TestServer {
+        let known: Arc<DashMap<PublicKey, SecretKey>> = Arc::default();
+        let network = NetworkConfigs {
+            default: NetworkConfig {
+                mtu: Some(1380),
+                ..Default::default()
+            },
+            per_client: HashMap::new(),
+        };
+        let (disconnects_tx, disconnects) = mpsc::unbounded_channel();
+        TestServer {
+            ctx: HandshakeContext {
+                sk: SecretKey::generate_x25519(),
+                auth: known.clone(),
+                sessions,
+                initiations: Initiations::new(),
+                network: Arc::new(network),
+                device_limit: None,
+                rate_limit: RateLimit::default(),
+                disconnects: disconnects_tx,
+                metrics: Arc::default(),
+            },
+            known,
+            disconnects,
+        }
+    }
+
+    impl TestServer {
+        fn sessions(&self) -> &Sessions {
+            &self.ctx.sessions
+        }
+
+        /// Register a new client and return its credentials.
+        fn client(&self) -> Cred {
+            let sk = SecretKey::generate_x25519();
+            let psk = SecretKey::generate_x25519();
+            self.known.insert(PublicKey::from_secret(&sk), psk.clone());
+            Cred {
+                sk,
+                psk,
+                spk: PublicKey::from_secret(&self.ctx.sk),
+            }
+        }
+
+        async fn respond(&self, msg: &EncryptedHandshake) -> anyhow::Result<EncryptedHandshake> {
+            self.ctx
+                .complete(msg, &"127.0.0.1:5000".parse().unwrap())
+                .await
+        }
+
+        /// Run one handshake and return the server's answer.
+        async fn try_handshake(
+            &self,
+            client: &Cred,
+            payload: HandshakeInitiatorPayload,
+        ) -> HandshakeResponderBody {
+            let (msg, state) = initial(&Alg::ChaCha20Poly1305, client, &payload).unwrap();
+            let resp = self.respond(&msg).await.unwrap();
+            client_complete(&resp, state).unwrap().0
+        }
+
+        /// Run one handshake and return the accepted payload.
+        async fn handshake(
+            &self,
+            client: &Cred,
+            payload: HandshakeInitiatorPayload,
+        ) -> HandshakeResponderPayload {
+            match self.try_handshake(client, payload).await {
+                HandshakeResponderBody::Complete(payload) => payload,
+                HandshakeResponderBody::Disconnect(_) => panic!("handshake rejected"),
+            }
+        }
+    }
+
+    fn v4_only() -> Sessions {
+        Sessions::new(&"10.0.0.0".parse().unwrap(), 8)
+    }
+
+    #[tokio::test]
+    async fn test_rekey_keeps_session_and_stages_epoch() {
+        let server = server(v4_only());
+        let client = server.client();
+
+        let first = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        let rekey = Rekey {
+            sid: first.sid,
+            epoch: 1,
+        };
+        let second = server
+            .handshake(&client, HandshakeInitiatorPayload::new(Some(rekey)))
+            .await;
+
+        assert_eq!(second.sid, first.sid);
+        assert_eq!(second.ipaddr, first.ipaddr);
+        assert_eq!(second.network.mtu, Some(1380));
+        assert_eq!(server.sessions().len(), 1);
+        let session = server.sessions().get_by_sid(&first.sid).unwrap();
+        assert_eq!(session.keys.current().epoch, 0, "staged until first use");
+        assert!(session.keys.get(1).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rekey_of_foreign_session_creates_new_one() {
+        let server = server(v4_only());
+        let owner = server.client();
+        let other = server.client();
+
+        let first = server
+            .handshake(&owner, HandshakeInitiatorPayload::new(None))
+            .await;
+        let rekey = Rekey {
+            sid: first.sid,
+            epoch: 1,
+        };
+        let second = server
+            .handshake(&other, HandshakeInitiatorPayload::new(Some(rekey)))
+            .await;
+
+        assert_ne!(second.sid, first.sid);
+        assert_eq!(server.sessions().len(), 2);
+        let session = server.sessions().get_by_sid(&first.sid).unwrap();
+        assert!(session.keys.get(1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_session_gets_both_addresses() {
+        let server = server(v4_only().with_ipv6(&"fd00::".parse().unwrap(), 64));
+        let client = server.client();
+
+        let payload = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        let ipv6 = payload
+            .ipv6
+            .expect("dual-stack server must hand out an IPv6 address");
+
+        let sessions = server.sessions();
+        let by_v4 = sessions.get_by_holy_ip(&payload.ipaddr).unwrap();
+        let by_v6 = sessions.get_by_holy_ip(&ipv6.into()).unwrap();
+        assert_eq!(by_v4.id, payload.sid);
+        assert_eq!(by_v6.id, payload.sid);
+
+        sessions.release_by_sid(payload.sid);
+        assert!(!sessions.is_holy_ip_allocated(&ipv6.into()));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_releases_previous_session() {
+        let server = server(v4_only());
+        let client = server.client();
+        let other = server.client();
+
+        let foreign = server
+            .handshake(&other, HandshakeInitiatorPayload::new(None))
+            .await;
+        let first = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        // Naming someone else's session must not release it.
+        let payload = HandshakeInitiatorPayload::new(None).replacing(Some(foreign.sid));
+        let second = server.handshake(&client, payload).await;
+        assert_eq!(server.sessions().len(), 3);
+
+        let payload = HandshakeInitiatorPayload::new(None).replacing(Some(first.sid));
+        server.handshake(&client, payload).await;
+        assert_eq!(server.sessions().len(), 3);
+        assert!(!server.sessions().is_sid_allocated(first.sid));
+        assert!(server.sessions().is_sid_allocated(second.sid));
+        assert!(server.sessions().is_sid_allocated(foreign.sid));
+    }
+
+    #[tokio::test]
+    async fn test_device_limit_rejects_new_sessions() {
+        let mut server = server(v4_only());
+        server.ctx.device_limit = Some(DeviceLimit {
+            max: 2,
+            policy: DeviceLimitPolicy::RejectNew,
+        });
+        let client = server.client();
+        let other = server.client();
+
+        server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        assert!(matches!(
+            server
+                .try_handshake(&client, HandshakeInitiatorPayload::new(None))
+                .await,
+            HandshakeResponderBody::Disconnect(HandshakeError::MaxConnectedDevices(2))
+        ));
+        server
+            .handshake(&other, HandshakeInitiatorPayload::new(None))
+            .await;
+        assert_eq!(server.sessions().len(), 3);
+        let metrics = server.ctx.metrics.snapshot();
+        assert_eq!(
+            (metrics.handshakes_accepted, metrics.handshakes_rejected),
+            (3, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_device_limit_replaces_oldest_session() {
+        let mut server = server(v4_only());
+        server.ctx.device_limit = Some(DeviceLimit {
+            max: 2,
+            policy: DeviceLimitPolicy::ReplaceOldest,
+        });
+        let client = server.client();
+
+        let oldest = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        let kept = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        let newest = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+
+        let owned: Vec<_> = server
+            .sessions()
+            .by_peer(&PublicKey::from_secret(&client.sk))
+            .iter()
+            .map(|session| session.id)
+            .collect();
+        assert_eq!(owned, [kept.sid, newest.sid]);
+        let (replaced, reason) = server.disconnects.try_recv().unwrap();
+        assert_eq!(replaced.id, oldest.sid);
+        assert_eq!(reason, DisconnectReason::Replaced);
+        assert!(server.disconnects.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replace_oldest_keeps_session_when_pool_is_full() {
+        let mut server = server(Sessions::new(&"10.0.0.0".parse().unwrap(), 28));
+        server.ctx.device_limit = Some(DeviceLimit {
+            max: 1,
+            policy: DeviceLimitPolicy::ReplaceOldest,
+        });
+        let client = server.client();
+        let first = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        while server.sessions().next_holy_ip().is_some() {}
+
+        assert!(matches!(
+            server
+                .try_handshake(&client, HandshakeInitiatorPayload::new(None))
+                .await,
+            HandshakeResponderBody::Disconnect(HandshakeError::ServerOverloaded)
+        ));
+        assert!(server.sessions().get_by_sid(&first.sid).is_some());
+        assert!(server.disconnects.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replace_oldest_hands_over_pinned_address() {
+        let mut server = server(v4_only());
+        server.ctx.device_limit = Some(DeviceLimit {
+            max: 1,
+            policy: DeviceLimitPolicy::ReplaceOldest,
+        });
+        let client = server.client();
+        let pinned: HolyIp = "10.0.0.42".parse().unwrap();
+        assert!(
+            server
+                .sessions()
+                .pin(&PublicKey::from_secret(&client.sk), pinned)
+        );
+
+        let first = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        let second = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+
+        assert_eq!((first.ipaddr, second.ipaddr), (pinned, pinned));
+        assert!(server.sessions().get_by_sid(&first.sid).is_none());
+        let (replaced, reason) = server.disconnects.try_recv().unwrap();
+        assert_eq!(
+            (replaced.id, reason),
+            (first.sid, DisconnectReason::Replaced)
+        );
+    }
+
+    /// Stand-in for an account service: per-key PSK and policy, or a failing
+    /// backend.
+    #[derive(Default)]
+    struct Directory {
+        accounts: DashMap<PublicKey, ClientAuth>,
+        down: std::sync::atomic::AtomicBool,
+    }
+
+    impl ClientAuthenticator for Directory {
+        fn authenticate<'a>(
+            &'a self,
+            pk: &'a PublicKey,
+        ) -> futures::future::BoxFuture<'a, anyhow::Result<Option<ClientAuth>>> {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                if self.down.load(std::sync::atomic::Ordering::Relaxed) {
+                    anyhow::bail!("directory unreachable");
                 }
+                Ok(self.accounts.get(pk).map(|auth| auth.clone()))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticator_policy_applies() {
+        let mut server = server(v4_only());
+        let directory = Arc::new(Directory::default());
+        server.ctx.auth = directory.clone();
+        server.ctx.device_limit = Some(DeviceLimit {
+            max: 1,
+            policy: DeviceLimitPolicy::RejectNew,
+        });
+        let client = server.client();
+        let pk = PublicKey::from_secret(&client.sk);
+        let mut auth = ClientAuth::new(client.psk.clone());
+        auth.policy.address = Some("10.0.0.77".parse().unwrap());
+        auth.policy.max_devices = Some(2);
+        auth.policy.download_limit = Some(0);
+        directory.accounts.insert(pk.clone(), auth.clone());
+        server.ctx.rate_limit = RateLimit {
+            upload: 1_000,
+            download: 2_000,
+        };
+
+        let first = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        assert_eq!(first.ipaddr, "10.0.0.77".parse::<HolyIp>().unwrap());
+        let session = server.ctx.sessions.get_by_sid(&first.sid).unwrap();
+        assert_eq!(
+            session.shaper.limit(),
+            RateLimit {
+                upload: 1_000,
+                download: 0
             }
+        );
+        server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+        assert!(matches!(
+            server
+                .try_handshake(&client, HandshakeInitiatorPayload::new(None))
+                .await,
+            HandshakeResponderBody::Disconnect(HandshakeError::MaxConnectedDevices(2))
+        ));
+
+        // Disabling the account refuses it and closes what it had.
+        auth.policy.allowed = false;
+        directory.accounts.insert(pk.clone(), auth.clone());
+        assert!(matches!(
+            server
+                .try_handshake(&client, HandshakeInitiatorPayload::new(None))
+                .await,
+            HandshakeResponderBody::Disconnect(HandshakeError::Denied)
+        ));
+        assert_eq!(server.sessions().len(), 0);
+        assert_eq!(
+            server.disconnects.try_recv().unwrap().1,
+            DisconnectReason::Revoked
+        );
+
+        auth.policy.allowed = true;
+        auth.policy.expires_at = Some(SystemTime::now());
+        directory.accounts.insert(pk, auth);
+        assert!(matches!(
+            server
+                .try_handshake(&client, HandshakeInitiatorPayload::new(None))
+                .await,
+            HandshakeResponderBody::Disconnect(HandshakeError::Denied)
+        ));
+
+        directory
+            .down
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let payload = HandshakeInitiatorPayload::new(None);
+        let (msg, _) = initial(&Alg::ChaCha20Poly1305, &client, &payload).unwrap();
+        assert!(server.respond(&msg).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quota_refusal_tells_its_reason() {
+        let mut server = server(v4_only());
+        let directory = Arc::new(Directory::default());
+        server.ctx.auth = directory.clone();
+        let client = server.client();
+        let pk = PublicKey::from_secret(&client.sk);
+        let mut auth = ClientAuth::new(client.psk.clone());
+        directory.accounts.insert(pk.clone(), auth.clone());
+        let live = server
+            .handshake(&client, HandshakeInitiatorPayload::new(None))
+            .await;
+
+        auth.policy.quota_exceeded = true;
+        directory.accounts.insert(pk, auth);
+        assert!(matches!(
+            server
+                .try_handshake(&client, HandshakeInitiatorPayload::new(None))
+                .await,
+            HandshakeResponderBody::Disconnect(HandshakeError::QuotaExceeded)
+        ));
+        let (closed, reason) = server.disconnects.try_recv().unwrap();
+        assert_eq!(
+            (closed.id, reason),
+            (live.sid, DisconnectReason::QuotaExceeded)
+        );
+        assert_eq!(server.sessions().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_client_is_rejected() {
+        let server = server(v4_only());
+        let stranger = Cred {
+            sk: SecretKey::generate_x25519(),
+            psk: SecretKey::generate_x25519(),
+            spk: PublicKey::from_secret(&server.ctx.sk),
+        };
+
+        let payload = HandshakeInitiatorPayload::new(None);
+        let (msg, _) = initial(&Alg::ChaCha20Poly1305, &stranger, &payload).unwrap();
+        assert!(server.respond(&msg).await.is_err());
+        assert_eq!(server.sessions().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_initiation_is_dropped() {
+        let server = server(v4_only());
+        let client = server.client();
+
+        let payload = HandshakeInitiatorPayload::new(None);
+        let (msg, _) = initial(&Alg::ChaCha20Poly1305, &client, &payload).unwrap();
+        server.respond(&msg).await.unwrap();
+        assert!(server.respond(&msg).await.is_err());
+        assert_eq!(server.sessions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_older_initiation_is_dropped() {
+        let server = server(v4_only());
+        let client = server.client();
+
+        let older = HandshakeInitiatorPayload::new(None);
+        let newer = HandshakeInitiatorPayload::new(None);
+        let (older, _) = initial(&Alg::ChaCha20Poly1305, &client, &older).unwrap();
+        let (newer, _) = initial(&Alg::ChaCha20Poly1305, &client, &newer).unwrap();
+        server.respond(&newer).await.unwrap();
+        assert!(server.respond(&older).await.is_err());
+        assert_eq!(server.sessions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_workers_answer_on_the_receiving_transport() {
+        let server = server(v4_only());
+        let client = server.client();
+        let (first, first_peer) = MockTransport::create_pair();
+        let (second, second_peer) = MockTransport::create_pair();
+
+        let (queue_tx, queue_rx) = mpsc::channel(8);
+        let queue: HandshakeQueue = Arc::new(tokio::sync::Mutex::new(queue_rx));
+        let transports = vec![Arc::new(first), Arc::new(second)];
+        let ctx = Arc::new(server.ctx);
+        let (_stop_tx, stop_rx) = watch::channel(false);
+        for _ in 0..2 {
+            tokio::spawn(handshake_worker(
+                stop_rx.clone(),
+                queue.clone(),
+                transports.clone(),
+                ctx.clone(),
+            ));
+        }
+
+        let addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        for transport in [1, 0] {
+            let payload = HandshakeInitiatorPayload::new(None);
+            let (msg, _) = initial(&Alg::ChaCha20Poly1305, &client, &payload).unwrap();
+            queue_tx.send((msg, addr, transport)).await.unwrap();
+        }
+
+        let mut buf = [0u8; 1024];
+        for peer in [&second_peer, &first_peer] {
+            let n = peer.recv(&mut buf).await.unwrap();
+            assert!(matches!(
+                Packet::try_from(&buf[..n]),
+                Ok(Packet::HandshakeResponder(_))
+            ));
         }
+        assert_eq!(ctx.sessions.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_gate_requires_cookie_under_load() {
+        let server_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let (queue_tx, mut queue_rx) = mpsc::channel(8);
+        let gate = HandshakeGate::new(queue_tx, Arc::new(CookieChecker::new(&server_pk)), 0);
+        let jar = CookieJar::new(&server_pk);
+        let (server_tp, client_tp) = MockTransport::create_pair();
+        let addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+
+        gate.admit(&server_tp, &[0u8; 64], addr).await;
+        assert!(queue_rx.try_recv().is_err(), "bad mac1 must be dropped");
+
+        // One queued initiation out of eight puts the queue under load.
+        gate.admit(&server_tp, &jar.stamp(b"first"), addr).await;
+        gate.admit(&server_tp, &jar.stamp(b"second"), addr).await;
+
+        let mut buf = [0u8; 128];
+        let n = client_tp.recv(&mut buf).await.unwrap();
+        assert_eq!(buf[0], COOKIE_REPLY_TYPE);
+        let reply: [u8; COOKIE_REPLY_LEN] = buf[1..n].try_into().unwrap();
+        jar.consume(&reply).unwrap();
+        gate.admit(&server_tp, &jar.stamp(b"second"), addr).await;
+
+        assert_eq!(&*queue_rx.try_recv().unwrap().0, b"first");
+        assert_eq!(&*queue_rx.try_recv().unwrap().0, b"second");
+        assert!(queue_rx.try_recv().is_err());
+    }
+
+    /// Answer the initiation arriving on `server_tp` with the response to
+    /// another initiation first, then with its own if `answer`.
+    async fn answer_late(
+        server: &TestServer,
+        client: &Cred,
+        server_tp: &MockTransport,
+        answer: bool,
+    ) {
+        let mut buf = [0u8; 2048];
+        let n = server_tp.recv(&mut buf).await.unwrap();
+        let Some(PacketRef::HandshakeInitial(stamped)) = PacketRef::from_bytes(&buf[..n]) else {
+            panic!("expected an initiation");
+        };
+        let msg: EncryptedHandshake = stamped[..stamped.len() - MACS_LEN].to_vec().into();
+        let (other, _) = initial(
+            &Alg::ChaCha20Poly1305,
+            client,
+            &HandshakeInitiatorPayload::new(None),
+        )
+        .unwrap();
+        // Answered in the order they were made, or the server takes the
+        // client's initiation for a replay.
+        let real = match answer {
+            true => Some(server.respond(&msg).await.unwrap()),
+            false => None,
+        };
+        let mut responses = vec![server.respond(&other).await.unwrap()];
+        responses.extend(real);
+        for resp in responses {
+            server_tp
+                .send(&Packet::HandshakeResponder(resp).to_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_skips_unauthenticated_response() {
+        let server = server(v4_only());
+        let client = server.client();
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let cookies = CookieJar::new(&client.spk);
+        let (result, ()) = tokio::join!(
+            handshake_step(
+                Arc::new(client_tp),
+                &client,
+                &Alg::ChaCha20Poly1305,
+                &cookies,
+                None,
+                Duration::from_secs(5),
+            ),
+            answer_late(&server, &client, &server_tp, true),
+        );
+        assert!(
+            result.is_ok(),
+            "the real response behind a stale one is read"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_only_unauthenticated_responses_mean_unknown_server_key() {
+        let server = server(v4_only());
+        let client = server.client();
+        let (client_tp, server_tp) = MockTransport::create_pair();
+        let cookies = CookieJar::new(&client.spk);
+        let (result, ()) = tokio::join!(
+            handshake_step(
+                Arc::new(client_tp),
+                &client,
+                &Alg::ChaCha20Poly1305,
+                &cookies,
+                None,
+                Duration::from_millis(200),
+            ),
+            answer_late(&server, &client, &server_tp, false),
+        );
+        assert!(matches!(result, Err(RuntimeError::UnknownServerKey)));
     }
 }
@@ -0,0 +1,326 @@
+//! Server-wide counters and their Prometheus text exposition.
+//!
+//! The hot paths bump [`Metrics`] with relaxed atomics; per-session traffic
+//! lives on each [`Session`](super::session::Session). Readers take a
+//! [`ServerMetrics`] snapshot or the rendered text through the
+//! [`ServerHandle`](super::ServerHandle).
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::session::Session;
+use crate::crypto::PublicKey;
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    handshakes_accepted: AtomicU64,
+    handshakes_rejected: AtomicU64,
+    unknown_sid_drops: AtomicU64,
+    unknown_epoch_drops: AtomicU64,
+    replay_drops: AtomicU64,
+    rate_limit_drops: AtomicU64,
+    parse_failures: AtomicU64,
+    tun_write_errors: AtomicU64,
+}
+
+#[inline]
+fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Metrics {
+    pub(crate) fn handshake_accepted(&self) {
+        bump(&self.handshakes_accepted);
+    }
+
+    pub(crate) fn handshake_rejected(&self) {
+        bump(&self.handshakes_rejected);
+    }
+
+    pub(crate) fn unknown_sid(&self) {
+        bump(&self.unknown_sid_drops);
+    }
+
+    pub(crate) fn unknown_epoch(&self) {
+        bump(&self.unknown_epoch_drops);
+    }
+
+    pub(crate) fn replay_dropped(&self) {
+        bump(&self.replay_drops);
+    }
+
+    pub(crate) fn rate_limited(&self) {
+        bump(&self.rate_limit_drops);
+    }
+
+    pub(crate) fn parse_failed(&self) {
+        bump(&self.parse_failures);
+    }
+
+    pub(crate) fn tun_write_failed(&self) {
+        bump(&self.tun_write_errors);
+    }
+
+    pub(crate) fn snapshot(&self) -> ServerMetrics {
+        ServerMetrics {
+            handshakes_accepted: self.handshakes_accepted.load(Ordering::Relaxed),
+            handshakes_rejected: self.handshakes_rejected.load(Ordering::Relaxed),
+            unknown_sid_drops: self.unknown_sid_drops.load(Ordering::Relaxed),
+            unknown_epoch_drops: self.unknown_epoch_drops.load(Ordering::Relaxed),
+            replay_drops: self.replay_drops.load(Ordering::Relaxed),
+            rate_limit_drops: self.rate_limit_drops.load(Ordering::Relaxed),
+            parse_failures: self.parse_failures.load(Ordering::Relaxed),
+            tun_write_errors: self.tun_write_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point-in-time copy of the server-wide counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServerMetrics {
+    /// New sessions and rekeys
+    pub handshakes_accepted: u64,
+    /// Initiations that failed or were refused after the Noise read
+    pub handshakes_rejected: u64,
+    /// Data packets for a session id the server does not know
+    pub unknown_sid_drops: u64,
+    /// Data packets sealed under a key epoch that is unknown or past its
+    /// overlap window
+    pub unknown_epoch_drops: u64,
+    /// Data packets dropped as replayed or too old
+    pub replay_drops: u64,
+    /// Data packets over their session's rate limit
+    pub rate_limit_drops: u64,
+    /// Datagrams and TUN packets that could not be parsed
+    pub parse_failures: u64,
+    /// Failed batched writes to the TUN
+    pub tun_write_errors: u64,
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// A traffic counter of [`Session`], rendered per peer and per session.
+struct TrafficCounter {
+    name: &'static str,
+    help: &'static str,
+    field: fn(&Session) -> &AtomicU64,
+}
+
+impl TrafficCounter {
+    fn value(&self, session: &Session) -> u64 {
+        (self.field)(session).load(Ordering::Relaxed)
+    }
+}
+
+const TRAFFIC: [TrafficCounter; 4] = [
+    TrafficCounter {
+        name: "rx_bytes",
+        help: "Bytes received from the client.",
+        field: |s| &s.traffic.rx_bytes,
+    },
+    TrafficCounter {
+        name: "rx_packets",
+        help: "Packets received from the client.",
+        field: |s| &s.traffic.rx_packets,
+    },
+    TrafficCounter {
+        name: "tx_bytes",
+        help: "Bytes sent to the client.",
+        field: |s| &s.traffic.tx_bytes,
+    },
+    TrafficCounter {
+        name: "tx_packets",
+        help: "Packets sent to the client.",
+        field: |s| &s.traffic.tx_packets,
+    },
+];
+
+/// Render `metrics` and the traffic of `sessions` in the Prometheus text
+/// format (version 0.0.4).
+///
+/// Traffic is summed per peer, over its live sessions. `per_session` adds a
+/// series per session labelled with its id and tunnel address; that grows with
+/// every connection and names users' addresses, so leave it off unless the
+/// scraper is trusted and the churn is small.
+pub fn render_prometheus(
+    metrics: &ServerMetrics,
+    sessions: &[Arc<Session>],
+    per_session: bool,
+) -> String {
+    let mut out = String::new();
+
+    family(
+        &mut out,
+        "holynet_handshakes_total",
+        "counter",
+        "Handshakes by result.",
+    );
+    let _ = writeln!(
+        out,
+        "holynet_handshakes_total{{result=\"accepted\"}} {}",
+        metrics.handshakes_accepted
+    );
+    let _ = writeln!(
+        out,
+        "holynet_handshakes_total{{result=\"rejected\"}} {}",
+        metrics.handshakes_rejected
+    );
+
+    family(
+        &mut out,
+        "holynet_dropped_packets_total",
+        "counter",
+        "Packets dropped on the data path, by reason.",
+    );
+    for (reason, value) in [
+        ("unknown_session", metrics.unknown_sid_drops),
+        ("unknown_epoch", metrics.unknown_epoch_drops),
+        ("replay", metrics.replay_drops),
+        ("rate_limit", metrics.rate_limit_drops),
+        ("parse", metrics.parse_failures),
+    ] {
+        let _ = writeln!(
+            out,
+            "holynet_dropped_packets_total{{reason=\"{}\"}} {}",
+            reason, value
+        );
+    }
+
+    family(
+        &mut out,
+        "holynet_tun_write_errors_total",
+        "counter",
+        "Failed writes to the TUN device.",
+    );
+    let _ = writeln!(
+        out,
+        "holynet_tun_write_errors_total {}",
+        metrics.tun_write_errors
+    );
+
+    family(&mut out, "holynet_sessions", "gauge", "Live sessions.");
+    let _ = writeln!(out, "holynet_sessions {}", sessions.len());
+
+    let mut peers: HashMap<&PublicKey, [u64; 4]> = HashMap::new();
+    for session in sessions {
+        let sums = peers.entry(&session.peer_pk).or_default();
+        for (sum, counter) in sums.iter_mut().zip(TRAFFIC) {
+            *sum += counter.value(session);
+        }
+    }
+    for (i, counter) in TRAFFIC.iter().enumerate() {
+        let name = format!("holynet_peer_{}_total", counter.name);
+        family(&mut out, &name, "counter", counter.help);
+        for (peer, sums) in &peers {
+            let _ = writeln!(out, "{}{{peer=\"{}\"}} {}", name, peer, sums[i]);
+        }
+    }
+
+    if per_session {
+        for counter in TRAFFIC {
+            let name = format!("holynet_session_{}_total", counter.name);
+            family(&mut out, &name, "counter", counter.help);
+            for session in sessions {
+                let _ = writeln!(
+                    out,
+                    "{}{{sid=\"{}\",peer=\"{}\",ip=\"{}\"}} {}",
+                    name,
+                    session.id,
+                    session.peer_pk,
+                    session.holy_ip,
+                    counter.value(session)
+                );
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SecretKey;
+    use crate::protocol::Alg;
+    use crate::runtime::crypto::make_noise_pair_for_test;
+    use crate::runtime::server::session::Sessions;
+
+    fn add_session(sessions: &Sessions, sid: u32, ip: &str, peer_pk: &PublicKey) -> Arc<Session> {
+        let (_, state) = make_noise_pair_for_test();
+        sessions.add(
+            sid,
+            ip.parse().unwrap(),
+            None,
+            "127.0.0.1:1000".parse().unwrap(),
+            peer_pk.clone(),
+            Alg::ChaCha20Poly1305,
+            state,
+        );
+        sessions.get_by_sid(&sid).unwrap()
+    }
+
+    #[test]
+    fn test_render_counters_and_peers() {
+        let metrics = Metrics::default();
+        metrics.handshake_accepted();
+        metrics.replay_dropped();
+        metrics.replay_dropped();
+        metrics.unknown_epoch();
+
+        let sessions = Sessions::new(&"10.0.0.0".parse().unwrap(), 24);
+        let alice = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let bob = PublicKey::from_secret(&SecretKey::generate_x25519());
+        add_session(&sessions, 7, "10.0.0.2", &alice)
+            .traffic
+            .received(1400);
+        add_session(&sessions, 8, "10.0.0.3", &alice)
+            .traffic
+            .received(600);
+        add_session(&sessions, 9, "10.0.0.4", &bob).traffic.sent(60);
+
+        let text = render_prometheus(&metrics.snapshot(), &sessions.all(), false);
+        assert!(text.contains("holynet_handshakes_total{result=\"accepted\"} 1\n"));
+        assert!(text.contains("holynet_dropped_packets_total{reason=\"replay\"} 2\n"));
+        assert!(text.contains("holynet_dropped_packets_total{reason=\"unknown_epoch\"} 1\n"));
+        assert!(text.contains("holynet_sessions 3\n"));
+        assert!(text.contains(&format!(
+            "holynet_peer_rx_bytes_total{{peer=\"{}\"}} 2000\n",
+            alice
+        )));
+        assert!(text.contains(&format!(
+            "holynet_peer_tx_bytes_total{{peer=\"{}\"}} 60\n",
+            bob
+        )));
+        assert!(text.contains("# TYPE holynet_peer_tx_packets_total counter\n"));
+        assert!(
+            !text.contains("holynet_session_"),
+            "no per-session series by default"
+        );
+        assert!(!text.contains("10.0.0."), "no tunnel addresses by default");
+    }
+
+    #[test]
+    fn test_render_per_session() {
+        let sessions = Sessions::new(&"10.0.0.0".parse().unwrap(), 24);
+        let peer = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let session = add_session(&sessions, 7, "10.0.0.2", &peer);
+        session.traffic.received(1400);
+        session.traffic.sent(60);
+
+        let text = render_prometheus(&ServerMetrics::default(), &sessions.all(), true);
+        let rx = format!(
+            "holynet_session_rx_bytes_total{{sid=\"7\",peer=\"{}\",ip=\"10.0.0.2\"}} 1400\n",
+            peer
+        );
+        assert!(text.contains(&rx));
+        assert!(text.contains("# TYPE holynet_session_tx_packets_total counter\n"));
+        assert!(text.contains(&format!(
+            "holynet_peer_rx_bytes_total{{peer=\"{}\"}} 1400\n",
+            peer
+        )));
+    }
+}
@@ -15,17 +15,18 @@
 //! 1-entry session cache turns the per-packet DashMap lookup into a pointer
 //! compare.
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
 
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 use tracing::{debug, error, warn};
 
+use super::metrics::Metrics;
 use super::session::{HolyIp, Session, Sessions};
 use crate::gateway::network::{Network, TUN_BATCH_SIZE};
 use crate::gateway::transport::Transport;
-use crate::runtime::crypto::encode_data_server_packet;
+use crate::protocol::{DataServerBody, DisconnectReason};
+use crate::runtime::crypto::{encode_data_server_frame, encode_data_server_packet, noise_encrypt};
 
 /// Send a batch of encrypted frames laid out contiguously in `gso_buf`.
 ///
@@ -63,10 +64,14 @@ async fn send_batch<T: Transport>(
 }
 
 /// Extract the destination IP address from a raw IP packet without parsing
-/// all layers. Only IPv4 is supported; IPv6 returns an error.
+/// all layers.
 ///
 /// IPv4 layout: version nibble at byte 0, destination addr at bytes 16–19.
 /// Minimum valid IPv4 header is 20 bytes.
+///
+/// IPv6 layout: destination addr at bytes 24–39 of the 40-byte fixed header.
+/// Extension headers follow the fixed header and never move it, so they need
+/// no walking here.
 #[inline]
 pub(super) fn parse_destination(packet: &[u8]) -> anyhow::Result<IpAddr> {
     let version = packet.first().map(|b| b >> 4);
@@ -80,12 +85,70 @@ pub(super) fn parse_destination(packet: &[u8]) -> anyhow::Result<IpAddr> {
             }
             Ok(Ipv4Addr::from([packet[16], packet[17], packet[18], packet[19]]).into())
         }
-        Some(6) => Err(anyhow::anyhow!("IPv6 is not supported")),
+        Some(6) => {
+            let Some(dst) = packet.get(24..40) else {
+                return Err(anyhow::anyhow!(
+                    "IPv6 packet too short: {} bytes",
+                    packet.len()
+                ));
+            };
+            let dst: [u8; 16] = dst.try_into().expect("slice is 16 bytes");
+            Ok(Ipv6Addr::from(dst).into())
+        }
         Some(v) => Err(anyhow::anyhow!("unknown IP version: {}", v)),
         None => Err(anyhow::anyhow!("empty packet")),
     }
 }
 
+/// Send an encrypted `DataServerBody::Disconnect(reason)` to the client behind
+/// `session` so it stops or reconnects at once instead of timing out.
+pub(super) async fn send_disconnect<T: Transport>(
+    transport: &T,
+    session: &Session,
+    reason: DisconnectReason,
+) -> anyhow::Result<()> {
+    let key = session.keys.current();
+    let nonce = key.next_nonce();
+    let encrypted = noise_encrypt(&DataServerBody::Disconnect(reason), &key.noise, nonce)?;
+    let mut buf = [0u8; 128];
+    let n = encode_data_server_frame(key.epoch, nonce, &encrypted, &mut buf);
+    transport.send_to(&buf[..n], &session.sock_addr()).await?;
+    Ok(())
+}
+
+/// Sends the disconnects queued by [`ServerHandle`](super::ServerHandle) for
+/// sessions it closed, e.g. when their client is removed. Disconnects still
+/// queued when the server stops are sent before returning.
+pub(super) async fn disconnect_executor<T: Transport>(
+    mut stop: watch::Receiver<bool>,
+    mut queue: mpsc::UnboundedReceiver<(Arc<Session>, DisconnectReason)>,
+    transport: Arc<T>,
+) {
+    loop {
+        tokio::select! {
+            _ = stop.changed() => break,
+            next = queue.recv() => match next {
+                Some((session, reason)) => disconnect_or_warn(&*transport, &session, reason).await,
+                None => break,
+            },
+        }
+    }
+    while let Ok((session, reason)) = queue.try_recv() {
+        disconnect_or_warn(&*transport, &session, reason).await;
+    }
+    debug!("disconnect_executor stopped");
+}
+
+async fn disconnect_or_warn<T: Transport>(
+    transport: &T,
+    session: &Session,
+    reason: DisconnectReason,
+) {
+    if let Err(e) = send_disconnect(transport, session, reason).await {
+        warn!("[{}] failed to send disconnect: {}", session.sock_addr(), e);
+    }
+}
+
 fn ip_to_holy(ip: IpAddr) -> HolyIp {
     match ip {
         IpAddr::V4(v4) => HolyIp::V4(v4),
@@ -103,6 +166,7 @@ pub(super) async fn encrypt_forward<T: Transport, N: Network>(
     network: Arc<N>,
     transport: Arc<T>,
     sessions: Sessions,
+    metrics: Arc<Metrics>,
 ) {
     // Batched TUN read buffers (reused each iteration — zero alloc in steady state).
     let mut orig = vec![0u8; 10 + 65535]; // raw GSO super-frame + virtio hdr
@@ -129,6 +193,7 @@ pub(super) async fn encrypt_forward<T: Transport, N: Network>(
                         let pkt = &bufs[i][..sizes[i]];
                         let ip = match parse_destination(pkt) {
                             Err(e) => {
+                                metrics.parse_failed();
                                 warn!("failed to parse network packet destination: {}", e);
                                 continue;
                             }
@@ -146,10 +211,16 @@ pub(super) async fn encrypt_forward<T: Transport, N: Network>(
                                 s
                             }
                         };
-                        let send_nonce = session.send_nonce.fetch_add(1, Ordering::Relaxed);
-                        match encode_data_server_packet(pkt, &session.state, send_nonce, &mut gso_buf[off..]) {
+                        if !session.shaper.download.take(pkt.len()) {
+                            metrics.rate_limited();
+                            continue;
+                        }
+                        let key = session.keys.current();
+                        let send_nonce = key.next_nonce();
+                        match encode_data_server_packet(pkt, &key, send_nonce, &mut gso_buf[off..]) {
                             Err(e) => warn!("[{}] encrypt failed (sid {}): {}", ip, session.id, e),
                             Ok(n) => {
+                                session.traffic.sent(pkt.len());
                                 frames.push((off, n, session.sock_addr()));
                                 off += n;
                             }
@@ -166,6 +237,11 @@ pub(super) async fn encrypt_forward<T: Transport, N: Network>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::{PublicKey, SecretKey};
+    use crate::gateway::transport::TransportReceiver;
+    use crate::gateway::transport::mock::MockTransport;
+    use crate::protocol::Alg;
+    use crate::runtime::crypto::make_noise_pair_for_test;
 
     fn ipv4_packet(dst: [u8; 4]) -> Vec<u8> {
         // Minimal IPv4 header: 20 bytes. Version=4, IHL=5.
@@ -197,9 +273,18 @@ mod tests {
     }
 
     #[test]
-    fn test_ipv6_returns_error() {
+    fn test_ipv6_dst_parsed() {
+        let dst: Ipv6Addr = "fd00::2".parse().unwrap();
         let mut pkt = vec![0u8; 40]; // IPv6 header is 40 bytes
         pkt[0] = 0x60; // version=6
+        pkt[24..40].copy_from_slice(&dst.octets());
+        assert_eq!(parse_destination(&pkt).unwrap(), IpAddr::V6(dst));
+    }
+
+    #[test]
+    fn test_ipv6_too_short_errors() {
+        let mut pkt = vec![0u8; 39];
+        pkt[0] = 0x60;
         assert!(parse_destination(&pkt).is_err());
     }
 
@@ -213,4 +298,35 @@ mod tests {
         let pkt = vec![0x50u8; 20]; // version=5 (reserved)
         assert!(parse_destination(&pkt).is_err());
     }
+
+    #[tokio::test]
+    async fn test_disconnects_queued_at_stop_are_sent() {
+        let (server_tp, client_tp) = MockTransport::create_pair();
+        let sessions = Sessions::new(&"10.0.0.0".parse().unwrap(), 24);
+        let (_, state) = make_noise_pair_for_test();
+        sessions.add(
+            7,
+            "10.0.0.2".parse().unwrap(),
+            None,
+            "127.0.0.1:1000".parse().unwrap(),
+            PublicKey::from_secret(&SecretKey::generate_x25519()),
+            Alg::ChaCha20Poly1305,
+            state,
+        );
+        let session = sessions.get_by_sid(&7).unwrap();
+
+        let (queue_tx, queue) = mpsc::unbounded_channel();
+        queue_tx
+            .send((session.clone(), DisconnectReason::Revoked))
+            .unwrap();
+        queue_tx.send((session, DisconnectReason::Kicked)).unwrap();
+        let (stop_tx, stop) = watch::channel(false);
+        stop_tx.send(true).unwrap();
+        disconnect_executor(stop, queue, Arc::new(server_tp)).await;
+
+        let mut buf = [0u8; 128];
+        for _ in 0..2 {
+            assert!(client_tp.recv(&mut buf).await.unwrap() > 0);
+        }
+    }
 }
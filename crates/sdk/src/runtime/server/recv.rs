@@ -16,17 +16,18 @@
 //! so a single-packet flow adds zero latency, while a bulk stream coalesces many
 //! packets into one TUN write. Keepalives and handshakes are handled inline.
 
-use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
-use tokio::sync::{mpsc, watch};
+use tokio::sync::watch;
 use tracing::{debug, error, info, warn};
 
+use super::handshake::HandshakeGate;
+use super::metrics::Metrics;
 use super::session::{Session, Sessions};
 use crate::gateway::network::{GRO_BUF_CAP, GroState, Network, TUN_BATCH_SIZE, TUN_SEND_OFFSET};
 use crate::gateway::transport::Transport;
-use crate::protocol::{DataServerBody, EncryptedHandshake, PacketRef, SessionId};
+use crate::protocol::{DataServerBody, PacketRef, SessionId};
 use crate::runtime::crypto::{
     DataClientActionRef, encode_data_server_frame, noise_decrypt_data_client_into, noise_encrypt,
 };
@@ -37,13 +38,14 @@ use crate::time::sec_since_start;
 /// Reads encrypted UDP datagrams, decrypts them, and:
 /// - **Data packets** → batched and written to `network` via `send_multiple`.
 /// - **Keepalive** → response encrypted and sent back inline.
-/// - **Handshakes** → forwarded to `handshake_tx` (rare, may allocate).
+/// - **Handshakes** → admitted through the [`HandshakeGate`] (rare, may allocate).
 pub(super) async fn recv_decrypt_forward<T: Transport, N: Network>(
     mut stop: watch::Receiver<bool>,
     transport: Arc<T>,
     network: Arc<N>,
     sessions: Sessions,
-    handshake_tx: mpsc::Sender<(EncryptedHandshake, SocketAddr)>,
+    handshake: HandshakeGate,
+    metrics: Arc<Metrics>,
     inf_sessions_timeout: bool,
 ) {
     let mut udp_buf = [0u8; 65536];
@@ -84,16 +86,17 @@ pub(super) async fn recv_decrypt_forward<T: Transport, N: Network>(
                 warn!("dropping packet from {} (size {})", addr, n);
             } else {
                 match PacketRef::from_bytes(&udp_buf[..n]) {
-                    None => warn!("failed to parse packet from {}", addr),
+                    None => {
+                        metrics.parse_failed();
+                        warn!("failed to parse packet from {}", addr);
+                    }
 
                     Some(PacketRef::HandshakeInitial(hs_data)) => {
-                        let hs = hs_data.to_vec().into();
-                        if let Err(e) = handshake_tx.send((hs, addr)).await {
-                            error!("handshake_tx closed: {}", e);
-                        }
+                        handshake.admit(&*transport, hs_data, addr).await;
                     }
 
                     Some(PacketRef::DataClient {
+                        epoch,
                         sid,
                         nonce,
                         ciphertext,
@@ -115,17 +118,28 @@ pub(super) async fn recv_decrypt_forward<T: Transport, N: Network>(
                                     Some(s)
                                 }
                                 None => {
+                                    metrics.unknown_sid();
                                     warn!("[{}] data for unknown session {}", addr, sid);
                                     None
                                 }
                             },
                         };
 
-                        if let Some(session) = session {
+                        // Pick the transport key the frame was sealed under.
+                        let session = session.and_then(|s| match s.keys.get(epoch) {
+                            Some(key) => Some((s, key)),
+                            None => {
+                                metrics.unknown_epoch();
+                                warn!("[{}] unknown key epoch {} for sid {}", addr, epoch, sid);
+                                None
+                            }
+                        });
+
+                        if let Some((session, key)) = session {
                             // Replay window check under lock, before decryption.
-                            let nonce_ok =
-                                session.recv_window.lock().unwrap().check_and_update(nonce);
+                            let nonce_ok = key.recv_window.lock().unwrap().check_and_update(nonce);
                             if !nonce_ok {
+                                metrics.replay_dropped();
                                 warn!("[{}] replay/stale nonce {} for sid {}", addr, nonce, sid);
                             } else {
                                 // Decrypt straight into the next batch buffer, at the
@@ -138,41 +152,49 @@ pub(super) async fn recv_decrypt_forward<T: Transport, N: Network>(
                                 let base = tun_bufs[batch_len].as_ptr() as usize;
                                 let dec = noise_decrypt_data_client_into(
                                     ciphertext,
-                                    &session.state,
+                                    &key.noise,
                                     &mut tun_bufs[batch_len][TUN_SEND_OFFSET..],
                                     nonce,
                                 );
+                                // Authenticated, so a staged key may take over.
+                                if dec.is_ok() {
+                                    session.keys.confirm(&key);
+                                }
                                 match dec {
                                     Err(e) => {
                                         warn!("[{}] decrypt failed (sid {}): {}", addr, sid, e)
                                     }
                                     Ok(DataClientActionRef::Forward(packet)) => {
-                                        // `packet` points at the IP packet inside the
-                                        // decrypted frame (past the variant+len header),
-                                        // so it does not start at TUN_SEND_OFFSET. Shift
-                                        // it there — send_multiple uses one global offset.
-                                        let start = packet.as_ptr() as usize - base;
                                         let len = packet.len();
-                                        tun_bufs[batch_len]
-                                            .copy_within(start..start + len, TUN_SEND_OFFSET);
-                                        tun_bufs[batch_len].truncate(TUN_SEND_OFFSET + len);
-                                        if session.sock_addr() != addr {
-                                            debug!("[{}] addr changed for sid {}", addr, sid);
-                                            session.set_sock_addr(addr);
+                                        sessions.roam(&session, addr);
+                                        if !session.shaper.upload.take(len) {
+                                            metrics.rate_limited();
+                                        } else {
+                                            // `packet` points at the IP packet inside the
+                                            // decrypted frame (past the variant+len header),
+                                            // so it does not start at TUN_SEND_OFFSET. Shift
+                                            // it there — send_multiple uses one global offset.
+                                            let start = packet.as_ptr() as usize - base;
+                                            tun_bufs[batch_len]
+                                                .copy_within(start..start + len, TUN_SEND_OFFSET);
+                                            tun_bufs[batch_len].truncate(TUN_SEND_OFFSET + len);
+                                            session.traffic.received(len);
+                                            batch_len += 1;
                                         }
-                                        batch_len += 1;
+                                    }
+                                    Ok(DataClientActionRef::Disconnect) => {
+                                        info!("[{}] sid {} disconnected", addr, sid);
+                                        sessions.leave(&session);
+                                        cached_session = None;
                                     }
                                     Ok(DataClientActionRef::KeepAlive(client_ts)) => {
                                         info!("[{}] keepalive from sid {}", addr, sid);
-                                        if session.sock_addr() != addr {
-                                            debug!("[{}] addr changed for sid {}", addr, sid);
-                                            session.set_sock_addr(addr);
-                                        }
-                                        let send_nonce =
-                                            session.send_nonce.fetch_add(1, Ordering::Relaxed);
+                                        sessions.roam(&session, addr);
+                                        let out_key = session.keys.current();
+                                        let send_nonce = out_key.next_nonce();
                                         match noise_encrypt(
                                             &DataServerBody::KeepAlive(client_ts),
-                                            &session.state,
+                                            &out_key.noise,
                                             send_nonce,
                                         ) {
                                             Err(e) => {
@@ -180,6 +202,7 @@ pub(super) async fn recv_decrypt_forward<T: Transport, N: Network>(
                                             }
                                             Ok(encrypted) => {
                                                 let m = encode_data_server_frame(
+                                                    out_key.epoch,
                                                     send_nonce,
                                                     &encrypted,
                                                     &mut encode_buf,
@@ -224,6 +247,7 @@ pub(super) async fn recv_decrypt_forward<T: Transport, N: Network>(
                 .send_multiple(&mut gro, &mut tun_bufs[..batch_len], TUN_SEND_OFFSET)
                 .await
         {
+            metrics.tun_write_failed();
             error!("network send_multiple error: {}", e);
         }
     }
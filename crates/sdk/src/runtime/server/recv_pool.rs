@@ -60,13 +60,16 @@ use tokio::sync::{mpsc, watch};
 use tokio::task::JoinSet;
 use tracing::{debug, error, warn};
 
+use super::handshake::HandshakeGate;
+use super::metrics::Metrics;
 use super::session::{Session, Sessions};
 use crate::gateway::network::{GRO_BUF_CAP, GroState, Network, TUN_BATCH_SIZE, TUN_SEND_OFFSET};
 use crate::gateway::transport::Transport;
-use crate::protocol::{DataServerBody, EncryptedHandshake, PacketRef, SessionId};
+use crate::protocol::{DataServerBody, PacketRef, SessionId};
 use crate::runtime::crypto::{
     DataClientActionRef, encode_data_server_frame, noise_decrypt_data_client_into, noise_encrypt,
 };
+use crate::runtime::keys::TransportKey;
 use crate::time::sec_since_start;
 
 /// Datagrams the reader gathers per `recvmmsg` call and carries as one [`Batch`]
@@ -95,6 +98,10 @@ struct Slot {
     cipher_len: usize,
     /// Set by the worker for `Forward` slots; used by the writer's replay check.
     nonce: u64,
+    /// Key the slot was opened with; its replay window is checked by the writer.
+    key: Option<Arc<TransportKey>>,
+    /// Session the slot belongs to; the writer counts its traffic after the
+    /// replay check.
     session: Option<Arc<Session>>,
     /// Decrypted frame; IP packet lives at `[TUN_SEND_OFFSET..]`, `len()` set by
     /// the worker. Swapped into the writer's TUN batch on `Forward`.
@@ -109,6 +116,7 @@ impl Slot {
             cipher: vec![0u8; cipher_cap],
             cipher_len: 0,
             nonce: 0,
+            key: None,
             session: None,
             plain: vec![0u8; seg],
             action: SlotAction::Skip,
@@ -140,12 +148,14 @@ impl Batch {
 /// Spawn the reader + `workers` decrypt tasks + writer and run until stop.
 ///
 /// `workers` must be >= 2 (the caller uses the single-task path otherwise).
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn recv_decrypt_forward_pool<T: Transport + 'static, N: Network + 'static>(
     stop: watch::Receiver<bool>,
     transport: Arc<T>,
     network: Arc<N>,
     sessions: Sessions,
-    handshake_tx: mpsc::Sender<(EncryptedHandshake, SocketAddr)>,
+    handshake: HandshakeGate,
+    metrics: Arc<Metrics>,
     inf_sessions_timeout: bool,
     workers: usize,
 ) {
@@ -178,7 +188,8 @@ pub(super) async fn recv_decrypt_forward_pool<T: Transport + 'static, N: Network
             dtx,
             transport.clone(),
             sessions.clone(),
-            handshake_tx.clone(),
+            handshake.clone(),
+            metrics.clone(),
             inf_sessions_timeout,
             seg,
         ));
@@ -195,6 +206,7 @@ pub(super) async fn recv_decrypt_forward_pool<T: Transport + 'static, N: Network
     set.spawn(writer(
         stop.clone(),
         network.clone(),
+        metrics,
         workers,
         done_rx,
         free_tx.clone(),
@@ -273,12 +285,14 @@ async fn reader<T: Transport>(
 /// the writer (skipped slots included, so the writer's rotation stays in lockstep
 /// with the batch `seq`). Data packets decrypt straight into the slot's `plain`
 /// buffer; keepalives are answered inline; handshakes go out of band.
+#[allow(clippy::too_many_arguments)]
 async fn worker<T: Transport>(
     mut work_rx: mpsc::Receiver<Box<Batch>>,
     done_tx: mpsc::Sender<Box<Batch>>,
     transport: Arc<T>,
     sessions: Sessions,
-    handshake_tx: mpsc::Sender<(EncryptedHandshake, SocketAddr)>,
+    handshake: HandshakeGate,
+    metrics: Arc<Metrics>,
     inf_sessions_timeout: bool,
     seg: usize,
 ) {
@@ -293,7 +307,8 @@ async fn worker<T: Transport>(
                 &mut batch.slots[si],
                 &transport,
                 &sessions,
-                &handshake_tx,
+                &handshake,
+                &metrics,
                 inf_sessions_timeout,
                 seg,
                 &mut cached,
@@ -314,13 +329,15 @@ async fn decrypt_one<T: Transport>(
     slot: &mut Slot,
     transport: &Arc<T>,
     sessions: &Sessions,
-    handshake_tx: &mpsc::Sender<(EncryptedHandshake, SocketAddr)>,
+    handshake: &HandshakeGate,
+    metrics: &Metrics,
     inf_sessions_timeout: bool,
     seg: usize,
     cached: &mut Option<(SessionId, Arc<Session>)>,
     encode_buf: &mut [u8],
 ) {
     slot.action = SlotAction::Skip;
+    slot.key = None;
     slot.session = None;
 
     if slot.cipher_len == 0 || slot.cipher_len >= slot.cipher.len() {
@@ -332,9 +349,13 @@ async fn decrypt_one<T: Transport>(
     }
 
     match PacketRef::from_bytes(&slot.cipher[..slot.cipher_len]) {
-        None => warn!("failed to parse packet from {}", slot.addr),
+        None => {
+            metrics.parse_failed();
+            warn!("failed to parse packet from {}", slot.addr);
+        }
 
         Some(PacketRef::DataClient {
+            epoch,
             sid,
             nonce,
             ciphertext,
@@ -347,13 +368,26 @@ async fn decrypt_one<T: Transport>(
                         Some(s)
                     }
                     None => {
+                        metrics.unknown_sid();
                         warn!("[{}] data for unknown session {}", slot.addr, sid);
                         None
                     }
                 },
             };
 
-            if let Some(session) = session {
+            let session = session.and_then(|s| match s.keys.get(epoch) {
+                Some(key) => Some((s, key)),
+                None => {
+                    metrics.unknown_epoch();
+                    warn!(
+                        "[{}] unknown key epoch {} for sid {}",
+                        slot.addr, epoch, sid
+                    );
+                    None
+                }
+            });
+
+            if let Some((session, key)) = session {
                 if !inf_sessions_timeout {
                     session
                         .last_seen
@@ -364,10 +398,13 @@ async fn decrypt_one<T: Transport>(
                 let base = slot.plain.as_ptr() as usize;
                 let dec = noise_decrypt_data_client_into(
                     ciphertext,
-                    &session.state,
+                    &key.noise,
                     &mut slot.plain[TUN_SEND_OFFSET..],
                     nonce,
                 );
+                if dec.is_ok() {
+                    session.keys.confirm(&key);
+                }
                 match dec {
                     Err(e) => warn!("[{}] decrypt failed (sid {}): {}", slot.addr, sid, e),
                     Ok(DataClientActionRef::Forward(packet)) => {
@@ -375,28 +412,36 @@ async fn decrypt_one<T: Transport>(
                         let len = packet.len();
                         slot.plain.copy_within(start..start + len, TUN_SEND_OFFSET);
                         slot.plain.truncate(TUN_SEND_OFFSET + len);
-                        if session.sock_addr() != slot.addr {
-                            debug!("[{}] addr changed for sid {}", slot.addr, sid);
-                            session.set_sock_addr(slot.addr);
-                        }
+                        sessions.roam(&session, slot.addr);
                         slot.nonce = nonce;
+                        slot.key = Some(key);
                         slot.session = Some(session);
                         slot.action = SlotAction::Forward;
                     }
+                    // Handled here rather than by the writer: only the
+                    // session's own keys open it, and those die with it.
+                    Ok(DataClientActionRef::Disconnect) => {
+                        debug!("[{}] sid {} disconnected", slot.addr, sid);
+                        sessions.leave(&session);
+                        *cached = None;
+                    }
                     Ok(DataClientActionRef::KeepAlive(client_ts)) => {
-                        if session.sock_addr() != slot.addr {
-                            session.set_sock_addr(slot.addr);
-                        }
-                        let send_nonce = session.send_nonce.fetch_add(1, Ordering::Relaxed);
+                        sessions.roam(&session, slot.addr);
+                        let out_key = session.keys.current();
+                        let send_nonce = out_key.next_nonce();
                         match noise_encrypt(
                             &DataServerBody::KeepAlive(client_ts),
-                            &session.state,
+                            &out_key.noise,
                             send_nonce,
                         ) {
                             Err(e) => error!("[{}] keepalive encrypt failed: {}", slot.addr, e),
                             Ok(encrypted) => {
-                                let m =
-                                    encode_data_server_frame(send_nonce, &encrypted, encode_buf);
+                                let m = encode_data_server_frame(
+                                    out_key.epoch,
+                                    send_nonce,
+                                    &encrypted,
+                                    encode_buf,
+                                );
                                 if let Err(e) =
                                     transport.send_to(&encode_buf[..m], &slot.addr).await
                                 {
@@ -410,10 +455,7 @@ async fn decrypt_one<T: Transport>(
         }
 
         Some(PacketRef::HandshakeInitial(hs_data)) => {
-            let hs = hs_data.to_vec().into();
-            if let Err(e) = handshake_tx.send((hs, slot.addr)).await {
-                error!("handshake_tx closed: {}", e);
-            }
+            handshake.admit(&**transport, hs_data, slot.addr).await;
         }
 
         Some(_) => warn!("[{}] unexpected packet variant", slot.addr),
@@ -427,6 +469,7 @@ async fn decrypt_one<T: Transport>(
 async fn writer<N: Network>(
     mut stop: watch::Receiver<bool>,
     network: Arc<N>,
+    metrics: Arc<Metrics>,
     workers: usize,
     mut done_rx: Vec<mpsc::Receiver<Box<Batch>>>,
     free_tx: mpsc::Sender<Box<Batch>>,
@@ -456,7 +499,7 @@ async fn writer<N: Network>(
             Err(mpsc::error::TryRecvError::Empty) => {
                 // Nothing ready in order: flush what we have, then wait for it.
                 if tun_len > 0 {
-                    flush(&network, &mut gro, &mut tun_batch, tun_len).await;
+                    flush(&network, &metrics, &mut gro, &mut tun_batch, tun_len).await;
                     tun_len = 0;
                 }
                 tokio::select! {
@@ -470,15 +513,25 @@ async fn writer<N: Network>(
         for si in 0..batch.len {
             match batch.slots[si].action {
                 SlotAction::Forward => {
-                    let ok = match &batch.slots[si].session {
-                        Some(session) => session
+                    let ok = match &batch.slots[si].key {
+                        Some(key) => key
                             .recv_window
                             .lock()
                             .unwrap()
                             .check_and_update(batch.slots[si].nonce),
                         None => false,
                     };
-                    if ok {
+                    let len = batch.slots[si].plain.len() - TUN_SEND_OFFSET;
+                    let session = &batch.slots[si].session;
+                    if !ok {
+                        metrics.replay_dropped();
+                        warn!("replay/stale nonce {} dropped", batch.slots[si].nonce);
+                    } else if session.as_ref().is_some_and(|s| !s.shaper.upload.take(len)) {
+                        metrics.rate_limited();
+                    } else {
+                        if let Some(session) = session {
+                            session.traffic.received(len);
+                        }
                         // Copy into the pre-reserved 64 KiB buffer (keeps its
                         // capacity, unlike a swap) so the GRO merge never reallocs.
                         let dst = &mut tun_batch[tun_len];
@@ -486,16 +539,16 @@ async fn writer<N: Network>(
                         dst.extend_from_slice(&batch.slots[si].plain);
                         tun_len += 1;
                         if tun_len == TUN_BATCH_SIZE {
-                            flush(&network, &mut gro, &mut tun_batch, tun_len).await;
+                            flush(&network, &metrics, &mut gro, &mut tun_batch, tun_len).await;
                             tun_len = 0;
                         }
-                    } else {
-                        warn!("replay/stale nonce {} dropped", batch.slots[si].nonce);
                     }
                 }
                 SlotAction::Skip => {}
             }
-            // Drop the session Arc so a recycled batch doesn't pin sessions.
+            // Drop the key and session Arcs so a recycled batch doesn't pin
+            // retired keys or closed sessions.
+            batch.slots[si].key = None;
             batch.slots[si].session = None;
         }
 
@@ -503,7 +556,7 @@ async fn writer<N: Network>(
     }
 
     if tun_len > 0 {
-        flush(&network, &mut gro, &mut tun_batch, tun_len).await;
+        flush(&network, &metrics, &mut gro, &mut tun_batch, tun_len).await;
     }
     debug!("decrypt pool writer stopped");
 }
@@ -511,6 +564,7 @@ async fn writer<N: Network>(
 /// Write `tun_batch[..len]` to the TUN in one GRO-merged `send_multiple`.
 async fn flush<N: Network>(
     network: &Arc<N>,
+    metrics: &Metrics,
     gro: &mut GroState,
     tun_batch: &mut [Vec<u8>],
     len: usize,
@@ -519,6 +573,7 @@ async fn flush<N: Network>(
         .send_multiple(gro, &mut tun_batch[..len], TUN_SEND_OFFSET)
         .await
     {
+        metrics.tun_write_failed();
         error!("network send_multiple error: {}", e);
     }
 }
@@ -526,11 +581,14 @@ async fn flush<N: Network>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::{PublicKey, SecretKey};
     use crate::gateway::network::{NetworkReceiver, NetworkSender};
     use crate::gateway::transport::TransportSender;
     use crate::gateway::transport::mock::MockTransport;
     use crate::protocol::Alg;
+    use crate::runtime::cookie::CookieChecker;
     use crate::runtime::crypto::{encode_data_client_packet, make_noise_pair_for_test};
+    use crate::runtime::server::metrics::ServerMetrics;
     use std::io;
 
     /// Mock TUN that records every packet handed to it, in order. Only the send
@@ -579,7 +637,17 @@ mod tests {
         let addr: SocketAddr = "127.0.0.1:10001".parse().unwrap();
         let sid = sessions.next_session_id().unwrap();
         let ip = sessions.next_holy_ip().unwrap();
-        sessions.add(sid, ip, addr, Alg::ChaCha20Poly1305, server_state);
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        sessions.add(
+            sid,
+            ip,
+            None,
+            addr,
+            peer_pk,
+            Alg::ChaCha20Poly1305,
+            server_state,
+        );
+        let client_key = TransportKey::new(0, client_state);
 
         let (client_tp, server_tp) = MockTransport::create_pair();
         let server_tp = Arc::new(server_tp);
@@ -591,14 +659,19 @@ mod tests {
         });
 
         let (handshake_tx, _handshake_rx) = mpsc::channel(16);
+        let cookies = CookieChecker::new(&PublicKey::from_secret(&SecretKey::generate_x25519()));
+        let handshake = HandshakeGate::new(handshake_tx, Arc::new(cookies), 0);
         let (_stop_tx, stop_rx) = watch::channel(false);
 
+        let metrics = Arc::new(Metrics::default());
+
         let pool = tokio::spawn(recv_decrypt_forward_pool(
             stop_rx,
             server_tp.clone(),
             network,
-            sessions,
-            handshake_tx,
+            sessions.clone(),
+            handshake,
+            metrics.clone(),
             true,
             WORKERS,
         ));
@@ -609,8 +682,7 @@ mod tests {
             let mut payload = vec![0u8; 64];
             payload[..8].copy_from_slice(&seq.to_le_bytes());
             let mut frame = vec![0u8; 65600];
-            let n =
-                encode_data_client_packet(&payload, sid, &client_state, seq, &mut frame).unwrap();
+            let n = encode_data_client_packet(&payload, sid, &client_key, seq, &mut frame).unwrap();
             client_tp.send_to(&frame[..n], &addr).await.unwrap();
         }
 
@@ -623,6 +695,10 @@ mod tests {
             assert_eq!(seq, expected, "out-of-order TUN write");
             assert_eq!(got.len(), 64, "payload length mismatch");
         }
+        let session = sessions.get_by_sid(&sid).unwrap();
+        assert_eq!(session.traffic.rx_packets.load(Ordering::Relaxed), N);
+        assert_eq!(session.traffic.rx_bytes.load(Ordering::Relaxed), N * 64);
+        assert_eq!(metrics.snapshot(), ServerMetrics::default());
 
         pool.abort();
     }
@@ -0,0 +1,48 @@
+use std::net::{Ipv6Addr, SocketAddr};
+
+use super::HolyIp;
+use crate::crypto::PublicKey;
+use crate::protocol::{DisconnectReason, SessionId};
+
+/// Lifecycle of a session, published to every
+/// [`ServerHandle::events`](crate::runtime::server::ServerHandle::events)
+/// subscriber.
+#[derive(Clone)]
+pub enum SessionEvent {
+    /// A handshake created a new session.
+    Created {
+        sid: SessionId,
+        peer: PublicKey,
+        ip: HolyIp,
+        ip6: Option<Ipv6Addr>,
+        endpoint: SocketAddr,
+    },
+    /// The client started sending from another address.
+    Roamed {
+        sid: SessionId,
+        from: SocketAddr,
+        to: SocketAddr,
+    },
+    /// The session was dropped after a period without traffic.
+    Expired { sid: SessionId, peer: PublicKey },
+    /// The client closed the session itself.
+    Left { sid: SessionId, peer: PublicKey },
+    /// The server closed the session and told the client why.
+    Closed {
+        sid: SessionId,
+        peer: PublicKey,
+        reason: DisconnectReason,
+    },
+}
+
+impl SessionEvent {
+    pub fn sid(&self) -> SessionId {
+        match self {
+            SessionEvent::Created { sid, .. }
+            | SessionEvent::Roamed { sid, .. }
+            | SessionEvent::Expired { sid, .. }
+            | SessionEvent::Left { sid, .. }
+            | SessionEvent::Closed { sid, .. } => *sid,
+        }
+    }
+}
@@ -56,6 +56,13 @@ impl IpAddressGenerator {
         None
     }
 
+    /// Claim `address` itself. `false` when it is outside the subnet or
+    /// already taken.
+    pub fn reserve(&self, address: &IpAddr) -> bool {
+        self.ip_to_offset(address)
+            .is_some_and(|offset| self.borrowed.insert(offset))
+    }
+
     pub fn release(&self, address: &IpAddr) {
         if let Some(offset) = self.ip_to_offset(address) {
             self.borrowed.remove(&offset);
@@ -143,6 +150,19 @@ mod tests {
         assert_eq!(generator.next(), Some(ips[0]));
     }
 
+    #[test]
+    fn test_reserve_skips_address() {
+        let generator = IpAddressGenerator::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 30);
+        let pinned = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(generator.reserve(&pinned));
+        assert!(!generator.reserve(&pinned));
+        assert!(!generator.reserve(&IpAddr::V4(Ipv4Addr::new(10, 0, 1, 0))));
+
+        let ips: Vec<_> = (0..3).map(|_| generator.next().unwrap()).collect();
+        assert!(!ips.contains(&pinned));
+        assert!(generator.next().is_none());
+    }
+
     #[tokio::test]
     async fn test_concurrent_no_duplicates() {
         use std::sync::Arc;
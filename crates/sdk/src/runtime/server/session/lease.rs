@@ -0,0 +1,337 @@
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::{Duration, SystemTime};
+
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+
+use super::{HolyIp, Session, Sessions};
+use crate::crypto::PublicKey;
+
+/// Tunnel addresses kept for one client key between its sessions.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub ip: HolyIp,
+    pub ip6: Option<Ipv6Addr>,
+    /// When the addresses go back to the pool; `None` while a session uses them
+    pub expires: Option<SystemTime>,
+}
+
+pub(super) struct Held {
+    lease: Lease,
+    /// Pinned leases never expire and are not part of [`Sessions::leases`].
+    pinned: bool,
+    /// A session of the key currently uses the lease.
+    in_use: bool,
+}
+
+/// Which addresses of an ending session stay reserved for its key.
+pub(super) struct Kept {
+    pub(super) ip: bool,
+    pub(super) ip6: bool,
+}
+
+impl Sessions {
+    /// Keep the addresses of a key's last session reserved for `grace` after
+    /// it ends, and hand them back when the same key reconnects in time.
+    pub fn with_sticky_leases(mut self, grace: Duration) -> Self {
+        self.lease_grace = Some(grace);
+        self
+    }
+
+    /// Always give `peer_pk` the tunnel address `ip`. Fails when `ip` lies
+    /// outside the pool or another key holds it.
+    pub fn pin(&self, peer_pk: &PublicKey, ip: HolyIp) -> bool {
+        if let Some(mut held) = self.leases.get_mut(peer_pk)
+            && held.lease.ip == ip
+        {
+            held.pinned = true;
+            held.lease.expires = None;
+            return true;
+        }
+        let in_use = self
+            .get_by_holy_ip(&ip)
+            .is_some_and(|session| session.peer_pk == *peer_pk);
+        if !in_use && !self.holy_ip_gen.reserve(&ip) {
+            return false;
+        }
+        let held = Held {
+            lease: Lease {
+                ip,
+                ip6: None,
+                expires: None,
+            },
+            pinned: true,
+            in_use,
+        };
+        if let Some(old) = self.leases.insert(peer_pk.clone(), held) {
+            self.free_idle(&old);
+        }
+        true
+    }
+
+    /// Drop the pinned address of `peer_pk`, if any.
+    pub fn unpin(&self, peer_pk: &PublicKey) {
+        if let Some((_, held)) = self.leases.remove_if(peer_pk, |_, held| held.pinned) {
+            self.free_idle(&held);
+        }
+    }
+
+    /// The address pinned to `peer_pk`.
+    pub fn pinned(&self, peer_pk: &PublicKey) -> Option<HolyIp> {
+        self.leases
+            .get(peer_pk)
+            .filter(|held| held.pinned)
+            .map(|held| held.lease.ip)
+    }
+
+    /// Re-reserve a sticky lease saved before a restart. Leases that were in
+    /// use get a fresh grace period. Fails when the key already has a lease or
+    /// the address is taken.
+    pub fn restore_lease(&self, peer_pk: PublicKey, mut lease: Lease) -> bool {
+        let Entry::Vacant(entry) = self.leases.entry(peer_pk) else {
+            return false;
+        };
+        if !self.holy_ip_gen.reserve(&lease.ip) {
+            return false;
+        }
+        lease.ip6 = lease.ip6.filter(|ip6| {
+            self.holy_ip6_gen
+                .as_ref()
+                .is_some_and(|generator| generator.reserve(&IpAddr::V6(*ip6)))
+        });
+        lease.expires = lease.expires.or_else(|| self.lease_deadline());
+        entry.insert(Held {
+            lease,
+            pinned: false,
+            in_use: false,
+        });
+        true
+    }
+
+    /// Snapshot of every sticky lease, for saving across restarts.
+    pub fn leases(&self) -> Vec<(PublicKey, Lease)> {
+        self.leases
+            .iter()
+            .filter(|held| !held.pinned)
+            .map(|held| (held.key().clone(), held.lease))
+            .collect()
+    }
+
+    /// Forget every lease of `peer_pk`, pinned or sticky.
+    pub fn drop_lease(&self, peer_pk: &PublicKey) {
+        if let Some((_, held)) = self.leases.remove(peer_pk) {
+            self.free_idle(&held);
+        }
+    }
+
+    /// Return expired sticky leases to the pool.
+    pub fn expire_leases(&self) {
+        let now = SystemTime::now();
+        self.leases.retain(|_, held| {
+            let expired = !held.pinned
+                && !held.in_use
+                && held.lease.expires.is_some_and(|expires| expires <= now);
+            if expired {
+                self.free_idle(held);
+            }
+            !expired
+        });
+    }
+
+    /// Claim the lease of `peer_pk` for a new session. `None` when the key
+    /// has no lease or another of its sessions already uses it.
+    pub(crate) fn take_lease(&self, peer_pk: &PublicKey) -> Option<(HolyIp, Option<Ipv6Addr>)> {
+        let mut held = self.leases.get_mut(peer_pk)?;
+        if held.in_use {
+            return None;
+        }
+        // Pinned leases get their IPv6 address on first use.
+        if held.lease.ip6.is_none() && self.has_ipv6() {
+            held.lease.ip6 = self.next_holy_ip6();
+        }
+        held.in_use = true;
+        held.lease.expires = None;
+        Some((held.lease.ip, held.lease.ip6))
+    }
+
+    /// Whether the addresses of `session` are the lease of its client, which
+    /// a new session of the client gets once `session` ends.
+    pub(crate) fn holds_lease(&self, session: &Session) -> bool {
+        self.leases
+            .get(&session.peer_pk)
+            .is_some_and(|held| held.in_use && held.lease.ip == session.holy_ip)
+    }
+
+    /// Remember freshly allocated addresses as the sticky lease of `peer_pk`
+    /// unless sticky leases are off or the key already has one.
+    pub(crate) fn record_lease(&self, peer_pk: &PublicKey, ip: HolyIp, ip6: Option<Ipv6Addr>) {
+        if self.lease_grace.is_none() {
+            return;
+        }
+        self.leases.entry(peer_pk.clone()).or_insert(Held {
+            lease: Lease {
+                ip,
+                ip6,
+                expires: None,
+            },
+            pinned: false,
+            in_use: true,
+        });
+    }
+
+    /// Called when `session` ends: mark its lease idle and tell which of its
+    /// addresses the lease keeps.
+    pub(super) fn keep_lease(&self, session: &Session) -> Kept {
+        let nothing = Kept {
+            ip: false,
+            ip6: false,
+        };
+        let Some(mut held) = self.leases.get_mut(&session.peer_pk) else {
+            return nothing;
+        };
+        if !held.in_use || held.lease.ip != session.holy_ip {
+            return nothing;
+        }
+        held.in_use = false;
+        if !held.pinned {
+            held.lease.expires = self.lease_deadline();
+        }
+        if held.lease.ip6.is_none() {
+            held.lease.ip6 = session.holy_ip6;
+        }
+        Kept {
+            ip: true,
+            ip6: held.lease.ip6 == session.holy_ip6,
+        }
+    }
+
+    fn lease_deadline(&self) -> Option<SystemTime> {
+        Some(SystemTime::now() + self.lease_grace.unwrap_or_default())
+    }
+
+    /// Return the addresses of a lease no session uses to the pool.
+    fn free_idle(&self, held: &Held) {
+        if held.in_use {
+            return;
+        }
+        self.holy_ip_gen.release(&held.lease.ip);
+        if let Some(ip6) = held.lease.ip6 {
+            self.release_holy_ip6(&ip6);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SecretKey;
+    use crate::protocol::Alg;
+    use crate::runtime::crypto::make_noise_pair_for_test;
+
+    fn peer() -> PublicKey {
+        PublicKey::from_secret(&SecretKey::generate_x25519())
+    }
+
+    /// Open a session the way the handshake does: lease first, then the pool.
+    fn connect(sessions: &Sessions, peer_pk: &PublicKey) -> (u32, HolyIp) {
+        let sid = sessions.next_session_id().unwrap();
+        let ip = match sessions.take_lease(peer_pk) {
+            Some((ip, _)) => ip,
+            None => {
+                let ip = sessions.next_holy_ip().unwrap();
+                sessions.record_lease(peer_pk, ip, None);
+                ip
+            }
+        };
+        let (state, _) = make_noise_pair_for_test();
+        sessions.add(
+            sid,
+            ip,
+            None,
+            "127.0.0.1:1000".parse().unwrap(),
+            peer_pk.clone(),
+            Alg::ChaCha20Poly1305,
+            state,
+        );
+        (sid, ip)
+    }
+
+    fn sticky(grace: Duration) -> Sessions {
+        Sessions::new(&"10.0.0.0".parse().unwrap(), 24).with_sticky_leases(grace)
+    }
+
+    #[test]
+    fn test_sticky_lease_survives_reconnect() {
+        let sessions = sticky(Duration::from_secs(60));
+        let (alice, bob) = (peer(), peer());
+
+        let (sid, ip) = connect(&sessions, &alice);
+        sessions.release_by_sid(sid);
+        assert!(!sessions.is_holy_ip_allocated(&ip));
+        // Bob must not be handed Alice's reserved address meanwhile.
+        let (_, bob_ip) = connect(&sessions, &bob);
+        assert_ne!(bob_ip, ip);
+
+        assert_eq!(connect(&sessions, &alice).1, ip);
+    }
+
+    #[test]
+    fn test_second_device_gets_its_own_address() {
+        let sessions = sticky(Duration::from_secs(60));
+        let alice = peer();
+        let (_, first) = connect(&sessions, &alice);
+        let (_, second) = connect(&sessions, &alice);
+        assert_ne!(first, second);
+        assert_eq!(sessions.leases().len(), 1);
+    }
+
+    #[test]
+    fn test_expired_lease_returns_to_pool() {
+        let sessions = sticky(Duration::ZERO);
+        let alice = peer();
+        let (sid, ip) = connect(&sessions, &alice);
+        sessions.release_by_sid(sid);
+
+        sessions.expire_leases();
+        assert!(sessions.leases().is_empty());
+        assert!(sessions.holy_ip_gen.reserve(&ip));
+    }
+
+    #[test]
+    fn test_pinned_address_is_used_and_never_expires() {
+        let sessions = sticky(Duration::ZERO);
+        let (alice, bob) = (peer(), peer());
+        let pinned: HolyIp = "10.0.0.200".parse().unwrap();
+        assert!(sessions.pin(&alice, pinned));
+        assert!(!sessions.pin(&bob, pinned), "held by another key");
+        assert!(!sessions.pin(&bob, "192.168.0.1".parse().unwrap()));
+
+        let (sid, ip) = connect(&sessions, &alice);
+        assert_eq!(ip, pinned);
+        sessions.release_by_sid(sid);
+        sessions.expire_leases();
+        assert_eq!(sessions.pinned(&alice), Some(pinned));
+        assert!(sessions.leases().is_empty(), "pins are not sticky leases");
+
+        sessions.unpin(&alice);
+        assert!(sessions.pin(&bob, pinned));
+    }
+
+    #[test]
+    fn test_restore_lease_reserves_address() {
+        let sessions = sticky(Duration::from_secs(60));
+        let alice = peer();
+        let lease = Lease {
+            ip: "10.0.0.7".parse().unwrap(),
+            ip6: None,
+            expires: None,
+        };
+        assert!(sessions.restore_lease(alice.clone(), lease));
+        assert!(!sessions.restore_lease(peer(), lease));
+
+        let restored = sessions.leases();
+        assert_eq!(restored.len(), 1);
+        assert!(restored[0].1.expires.is_some(), "grace restarts on restore");
+        assert_eq!(connect(&sessions, &alice).1, lease.ip);
+    }
+}
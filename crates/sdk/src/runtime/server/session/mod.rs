@@ -1,9 +1,12 @@
+mod event;
 mod generator;
+mod lease;
+mod shaper;
 pub mod worker;
 
 use std::collections::BTreeMap;
 use std::sync::{
-    Mutex, Mutex as StdMutex,
+    Mutex as StdMutex,
     atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
 };
 use std::time::Duration;
@@ -15,14 +18,20 @@ use std::{
 
 use dashmap::DashMap;
 use snow::StatelessTransportState;
+use tokio::sync::{broadcast, mpsc};
 use tracing::debug;
 
-use crate::protocol::{Alg, SessionId};
-use crate::runtime::replay::ReplayWindow;
+use crate::crypto::PublicKey;
+use crate::protocol::{Alg, DisconnectReason, SessionId};
+use crate::runtime::keys::KeyRing;
 use crate::time::sec_since_start;
 
+pub use event::SessionEvent;
 pub use generator::HolyIp;
 use generator::{IpAddressGenerator, SessionIdGenerator, increment_ip};
+use lease::Held;
+pub use lease::Lease;
+pub use shaper::{RateLimit, Shaper, TokenBucket};
 
 pub struct Session {
     pub id: SessionId,
@@ -34,12 +43,40 @@ pub struct Session {
     pub last_seen: AtomicU64,
     pub created_at: Instant,
     pub holy_ip: HolyIp,
+    /// IPv6 tunnel address, set when the server runs dual-stack.
+    pub holy_ip6: Option<Ipv6Addr>,
     pub enc: Alg,
-    pub state: StatelessTransportState,
-    /// Monotonically increasing nonce for packets sent by the server to this client.
-    pub(crate) send_nonce: AtomicU64,
-    /// Anti-replay window for packets received from this client.
-    pub(crate) recv_window: Mutex<ReplayWindow>,
+    /// Static key of the client that owns this session.
+    pub peer_pk: PublicKey,
+    /// Transport keys per epoch, each with its own send nonce and replay window.
+    pub(crate) keys: KeyRing,
+    pub traffic: Traffic,
+    /// Rate limits, checked on the data path before forwarding.
+    pub shaper: Shaper,
+}
+
+/// Tunnelled IP traffic of one session, counted on the hot paths. `rx` is
+/// client → server, `tx` server → client.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    pub rx_bytes: AtomicU64,
+    pub rx_packets: AtomicU64,
+    pub tx_bytes: AtomicU64,
+    pub tx_packets: AtomicU64,
+}
+
+impl Traffic {
+    #[inline]
+    pub(crate) fn received(&self, bytes: usize) {
+        self.rx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.tx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 impl Session {
@@ -88,10 +125,17 @@ impl Session {
     }
 }
 
+/// Queue of closed sessions whose clients are still to be told why.
+pub(crate) type Disconnects = mpsc::UnboundedSender<(Arc<Session>, DisconnectReason)>;
+
+/// Events buffered per subscriber before the slowest one starts losing them.
+const EVENT_BUFFER: usize = 1024;
+
 #[derive(Clone)]
 pub struct Sessions {
     sid_gen: Arc<SessionIdGenerator>,
     holy_ip_gen: Arc<IpAddressGenerator>,
+    holy_ip6_gen: Option<Arc<IpAddressGenerator>>,
     map: Arc<DashMap<SessionId, Arc<Session>>>,
     holy_ip_map: Arc<DashMap<HolyIp, SessionId>>,
     /// TTL-ordered queue for O(k) cleanup.
@@ -101,6 +145,12 @@ pub struct Sessions {
     /// Updated only on `add()` and inside `cleanup_sessions()` (not on `touch()`),
     /// so the hot data path sees zero overhead.
     expiry_queue: Arc<StdMutex<BTreeMap<u64, Vec<SessionId>>>>,
+    /// Pinned and sticky addresses per client key.
+    leases: Arc<DashMap<PublicKey, Held>>,
+    /// How long a sticky lease outlives its session; `None` turns them off.
+    lease_grace: Option<Duration>,
+    /// Lifecycle events; sending without subscribers is a no-op.
+    events: broadcast::Sender<SessionEvent>,
 }
 
 impl Sessions {
@@ -108,12 +158,92 @@ impl Sessions {
         Sessions {
             sid_gen: Arc::new(SessionIdGenerator::new()),
             holy_ip_gen: Arc::new(IpAddressGenerator::new(increment_ip(*network), prefix)),
+            holy_ip6_gen: None,
             map: Arc::new(DashMap::new()),
             holy_ip_map: Arc::new(DashMap::new()),
             expiry_queue: Arc::new(StdMutex::new(BTreeMap::new())),
+            leases: Arc::new(DashMap::new()),
+            lease_grace: None,
+            events: broadcast::channel(EVENT_BUFFER).0,
         }
     }
 
+    /// Receive every [`SessionEvent`] from now on. A subscriber that falls
+    /// more than `EVENT_BUFFER` events behind skips the oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: SessionEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Record that `session` was closed with `reason`, after it has been
+    /// released.
+    pub(crate) fn closed(&self, session: &Session, reason: DisconnectReason) {
+        self.emit(SessionEvent::Closed {
+            sid: session.id,
+            peer: session.peer_pk.clone(),
+            reason,
+        });
+    }
+
+    /// Close `session` with `reason`: release it, publish
+    /// [`SessionEvent::Closed`] and queue the notice for its client. `false`,
+    /// and nothing happens, when the session has ended already, even if its
+    /// sid belongs to a newer session by now.
+    pub(crate) fn close(
+        &self,
+        session: Arc<Session>,
+        reason: DisconnectReason,
+        disconnects: &Disconnects,
+    ) -> bool {
+        if !self.release_exact(&session) {
+            return false;
+        }
+        self.closed(&session, reason);
+        // The queue goes away only once the server has stopped, and then
+        // there is nobody left to notify.
+        let _ = disconnects.send((session, reason));
+        true
+    }
+
+    /// Release `session` at its client's request.
+    pub fn leave(&self, session: &Session) {
+        if self.release_owned(session.id, &session.peer_pk).is_some() {
+            self.emit(SessionEvent::Left {
+                sid: session.id,
+                peer: session.peer_pk.clone(),
+            });
+        }
+    }
+
+    /// Move `session` to the client's new address `addr`, if it changed.
+    pub fn roam(&self, session: &Session, addr: SocketAddr) {
+        let from = session.sock_addr();
+        if from == addr {
+            return;
+        }
+        debug!("[{}] addr changed for sid {}", addr, session.id);
+        session.set_sock_addr(addr);
+        self.emit(SessionEvent::Roamed {
+            sid: session.id,
+            from,
+            to: addr,
+        });
+    }
+
+    /// Also hand every session an address from the IPv6 `network`.
+    pub fn with_ipv6(mut self, network: &Ipv6Addr, prefix: u8) -> Self {
+        let start = increment_ip(IpAddr::V6(*network));
+        self.holy_ip6_gen = Some(Arc::new(IpAddressGenerator::new(start, prefix)));
+        self
+    }
+
+    pub fn has_ipv6(&self) -> bool {
+        self.holy_ip6_gen.is_some()
+    }
+
     pub fn next_session_id(&self) -> Option<SessionId> {
         self.sid_gen.next()
     }
@@ -132,14 +262,50 @@ impl Sessions {
         self.holy_ip_gen.release(holy_ip);
     }
 
+    /// `None` when the IPv6 pool is exhausted or not configured.
+    pub fn next_holy_ip6(&self) -> Option<Ipv6Addr> {
+        match self.holy_ip6_gen.as_ref()?.next()? {
+            IpAddr::V6(v6) => Some(v6),
+            IpAddr::V4(_) => None,
+        }
+    }
+
+    /// Only call if the address was allocated via `next_holy_ip6` but never passed to `add`.
+    pub fn release_holy_ip6(&self, holy_ip6: &Ipv6Addr) {
+        if let Some(generator) = &self.holy_ip6_gen {
+            generator.release(&IpAddr::V6(*holy_ip6));
+        }
+    }
+
+    /// Unindex both tunnel addresses of a session already taken out of `map`
+    /// and free those its key does not keep as a lease.
+    fn release_addresses(&self, session: &Session) {
+        let kept = self.keep_lease(session);
+        if let Some((holy_ip, _)) = self.holy_ip_map.remove(&session.holy_ip)
+            && !kept.ip
+        {
+            self.holy_ip_gen.release(&holy_ip);
+        }
+        if let Some(holy_ip6) = session.holy_ip6
+            && self.holy_ip_map.remove(&IpAddr::V6(holy_ip6)).is_some()
+            && !kept.ip6
+        {
+            self.release_holy_ip6(&holy_ip6);
+        }
+    }
+
+    /// Register a new session and return it.
+    #[allow(clippy::too_many_arguments)]
     pub fn add(
         &self,
         sid: SessionId,
         ip: HolyIp,
+        ip6: Option<Ipv6Addr>,
         sock_addr: SocketAddr,
+        peer_pk: PublicKey,
         enc: Alg,
         state: StatelessTransportState,
-    ) {
+    ) -> Arc<Session> {
         let (ipv4_data, ipv6_data, is_ipv4) = match sock_addr {
             SocketAddr::V4(addr_v4) => {
                 let ip_u32 = u32::from_be_bytes(addr_v4.ip().octets());
@@ -170,20 +336,34 @@ impl Sessions {
             last_seen: AtomicU64::from(sec_since_start()),
             created_at: Instant::now(),
             holy_ip: ip,
+            holy_ip6: ip6,
             enc,
-            state,
-            send_nonce: AtomicU64::new(0),
-            recv_window: Mutex::new(ReplayWindow::new()),
+            peer_pk,
+            keys: KeyRing::new(state),
+            traffic: Traffic::default(),
+            shaper: Shaper::default(),
         });
 
-        self.map.insert(sid, session);
+        let created = SessionEvent::Created {
+            sid,
+            peer: session.peer_pk.clone(),
+            ip,
+            ip6,
+            endpoint: sock_addr,
+        };
+        self.map.insert(sid, session.clone());
         self.holy_ip_map.insert(ip, sid);
+        if let Some(ip6) = ip6 {
+            self.holy_ip_map.insert(IpAddr::V6(ip6), sid);
+        }
         self.expiry_queue
             .lock()
             .unwrap()
             .entry(sec_since_start())
             .or_default()
             .push(sid);
+        self.emit(created);
+        session
     }
 
     /// Remove expired sessions in O(k + m) time, where k = candidate sessions
@@ -219,11 +399,13 @@ impl Sessions {
                     // Truly expired.
                     drop(session);
                     if let Some((_, session)) = self.map.remove(&sid) {
-                        if let Some((holy_ip, _)) = self.holy_ip_map.remove(&session.holy_ip) {
-                            self.holy_ip_gen.release(&holy_ip);
-                        }
+                        self.release_addresses(&session);
                         self.sid_gen.release(&sid);
                         removed += 1;
+                        self.emit(SessionEvent::Expired {
+                            sid,
+                            peer: session.peer_pk.clone(),
+                        });
                     }
                 } else {
                     // Still alive — re-queue at its current last_seen so we
@@ -245,14 +427,58 @@ impl Sessions {
     }
 
     pub fn release_by_sid(&self, sid: SessionId) {
-        let holy_ip = self.map.remove(&sid).map(|(_, session)| {
-            self.holy_ip_map.remove(&session.holy_ip);
-            session.holy_ip
+        if let Some((_, session)) = self.map.remove(&sid) {
+            self.release_addresses(&session);
+            self.sid_gen.release(&sid);
+        }
+    }
+
+    /// Remove every session owned by `peer_pk` and return them, so the caller
+    /// can still notify the clients with their last keys. Sessions another
+    /// caller releases in the meantime are left out.
+    pub fn release_by_peer(&self, peer_pk: &PublicKey) -> Vec<Arc<Session>> {
+        self.by_peer(peer_pk)
+            .into_iter()
+            .filter(|session| self.release_exact(session))
+            .collect()
+    }
+
+    /// Release `session` itself. `false` when it has ended already, even if
+    /// its sid has been handed to a newer session since.
+    fn release_exact(&self, session: &Session) -> bool {
+        let removed = self.map.remove_if(&session.id, |_, live| {
+            std::ptr::eq(Arc::as_ptr(live), session)
         });
-        if let Some(holy_ip) = holy_ip {
-            self.holy_ip_gen.release(&holy_ip);
+        if removed.is_none() {
+            return false;
         }
+        self.release_addresses(session);
+        self.sid_gen.release(&session.id);
+        true
+    }
+
+    /// Release `sid` only if it is owned by `peer_pk`. A client names its
+    /// previous session when it reconnects, so a session left behind by a NAT
+    /// rebinding or a restart is freed right away instead of timing out.
+    pub fn release_owned(&self, sid: SessionId, peer_pk: &PublicKey) -> Option<Arc<Session>> {
+        let (_, session) = self
+            .map
+            .remove_if(&sid, |_, session| session.peer_pk == *peer_pk)?;
+        self.release_addresses(&session);
         self.sid_gen.release(&sid);
+        Some(session)
+    }
+
+    /// Live sessions owned by `peer_pk`, oldest first.
+    pub fn by_peer(&self, peer_pk: &PublicKey) -> Vec<Arc<Session>> {
+        let mut owned: Vec<Arc<Session>> = self
+            .map
+            .iter()
+            .filter(|entry| entry.peer_pk == *peer_pk)
+            .map(|entry| entry.value().clone())
+            .collect();
+        owned.sort_by_key(|session| session.created_at);
+        owned
     }
 
     pub fn is_sid_allocated(&self, sid: SessionId) -> bool {
@@ -263,6 +489,11 @@ impl Sessions {
         self.holy_ip_map.contains_key(ip)
     }
 
+    /// Snapshot of every live session.
+    pub fn all(&self) -> Vec<Arc<Session>> {
+        self.map.iter().map(|entry| entry.value().clone()).collect()
+    }
+
     pub fn get_by_sid(&self, sid: &SessionId) -> Option<Arc<Session>> {
         self.map.get(sid).map(|entry| entry.value().clone())
     }
@@ -295,8 +526,8 @@ impl Sessions {
     }
 
     pub fn update_sock_addr(&self, sid: SessionId, addr: SocketAddr) {
-        if let Some(entry) = self.map.get(&sid) {
-            entry.value().set_sock_addr(addr);
+        if let Some(session) = self.get_by_sid(&sid) {
+            self.roam(&session, addr);
         }
     }
 }
@@ -310,6 +541,7 @@ mod tests {
     use snow::StatelessTransportState;
 
     use super::*;
+    use crate::crypto::SecretKey;
     use crate::protocol::Alg;
     use crate::runtime::crypto::make_noise_pair_for_test;
     use crate::time::sec_since_start;
@@ -322,10 +554,20 @@ mod tests {
         sessions: &Sessions,
         addr: SocketAddr,
         state: StatelessTransportState,
+    ) -> (SessionId, HolyIp) {
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        add_for_peer(sessions, addr, peer_pk, state)
+    }
+
+    fn add_for_peer(
+        sessions: &Sessions,
+        addr: SocketAddr,
+        peer_pk: PublicKey,
+        state: StatelessTransportState,
     ) -> (SessionId, HolyIp) {
         let sid = sessions.next_session_id().unwrap();
         let ip = sessions.next_holy_ip().unwrap();
-        sessions.add(sid, ip, addr, Alg::ChaCha20Poly1305, state);
+        sessions.add(sid, ip, None, addr, peer_pk, Alg::ChaCha20Poly1305, state);
         (sid, ip)
     }
 
@@ -369,12 +611,124 @@ mod tests {
         assert!(sessions.is_sid_allocated(sid));
         assert!(sessions.is_holy_ip_allocated(&ip));
 
+        assert_eq!(sessions.len(), 1);
         sessions.release_by_sid(sid);
 
+        assert_eq!(sessions.len(), 0);
         assert!(!sessions.is_sid_allocated(sid));
         assert!(!sessions.is_holy_ip_allocated(&ip));
     }
 
+    #[test]
+    fn test_release_by_peer_removes_only_its_sessions() {
+        let sessions = make_sessions();
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let (s1, _) = make_noise_pair_for_test();
+        let (s2, _) = make_noise_pair_for_test();
+        let (s3, _) = make_noise_pair_for_test();
+        let (sid1, ip1) = add_for_peer(
+            &sessions,
+            "127.0.0.1:1111".parse().unwrap(),
+            peer_pk.clone(),
+            s1,
+        );
+        let (sid2, _) = add_for_peer(
+            &sessions,
+            "127.0.0.1:2222".parse().unwrap(),
+            peer_pk.clone(),
+            s2,
+        );
+        let (other, _) = add_one(&sessions, "127.0.0.1:3333".parse().unwrap(), s3);
+
+        let mut released: Vec<_> = sessions
+            .release_by_peer(&peer_pk)
+            .iter()
+            .map(|s| s.id)
+            .collect();
+        released.sort();
+        let mut expected = vec![sid1, sid2];
+        expected.sort();
+
+        assert_eq!(released, expected);
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.is_sid_allocated(other));
+        assert!(!sessions.is_holy_ip_allocated(&ip1));
+    }
+
+    #[test]
+    fn test_release_owned_ignores_foreign_sessions() {
+        let sessions = make_sessions();
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let (s1, _) = make_noise_pair_for_test();
+        let (s2, _) = make_noise_pair_for_test();
+        let (own, ip) = add_for_peer(
+            &sessions,
+            "127.0.0.1:1111".parse().unwrap(),
+            peer_pk.clone(),
+            s1,
+        );
+        let (foreign, _) = add_one(&sessions, "127.0.0.1:2222".parse().unwrap(), s2);
+
+        assert!(sessions.release_owned(foreign, &peer_pk).is_none());
+        assert!(sessions.is_sid_allocated(foreign));
+
+        assert_eq!(sessions.release_owned(own, &peer_pk).unwrap().id, own);
+        assert!(!sessions.is_sid_allocated(own));
+        assert!(!sessions.is_holy_ip_allocated(&ip));
+    }
+
+    #[test]
+    fn test_close_spares_a_reused_sid() {
+        let sessions = make_sessions();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let (s1, _) = make_noise_pair_for_test();
+        let (s2, _) = make_noise_pair_for_test();
+        let (sid, _) = add_for_peer(
+            &sessions,
+            "127.0.0.1:1111".parse().unwrap(),
+            peer_pk.clone(),
+            s1,
+        );
+        let stale = sessions.get_by_sid(&sid).unwrap();
+
+        // Someone else closes it and the sid goes to a new session of the
+        // same client.
+        assert!(sessions.close(stale.clone(), DisconnectReason::Kicked, &tx));
+        let ip = sessions.next_holy_ip().unwrap();
+        sessions.add(
+            sid,
+            ip,
+            None,
+            "127.0.0.1:2222".parse().unwrap(),
+            peer_pk,
+            Alg::ChaCha20Poly1305,
+            s2,
+        );
+
+        assert!(!sessions.close(stale, DisconnectReason::Replaced, &tx));
+        assert!(sessions.is_sid_allocated(sid));
+        assert_eq!(rx.try_recv().unwrap().1, DisconnectReason::Kicked);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_by_peer_is_oldest_first() {
+        let sessions = make_sessions();
+        let peer_pk = PublicKey::from_secret(&SecretKey::generate_x25519());
+        let mut sids = Vec::new();
+        for port in [1111, 2222, 3333] {
+            let (state, _) = make_noise_pair_for_test();
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            sids.push(add_for_peer(&sessions, addr, peer_pk.clone(), state).0);
+        }
+        let (other, _) = make_noise_pair_for_test();
+        add_one(&sessions, "127.0.0.1:4444".parse().unwrap(), other);
+
+        let owned: Vec<_> = sessions.by_peer(&peer_pk).iter().map(|s| s.id).collect();
+        assert_eq!(owned, sids);
+    }
+
     // ── cleanup_sessions ───────────────────────────────────────────────────────
 
     #[test]
@@ -436,6 +790,48 @@ mod tests {
         assert!(sessions.is_holy_ip_allocated(&ip));
     }
 
+    #[test]
+    fn test_lifecycle_events() {
+        let sessions = make_sessions();
+        let mut events = sessions.subscribe();
+        let (state, _) = make_noise_pair_for_test();
+        let first: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let (sid, ip) = add_one(&sessions, first, state);
+        match events.try_recv().unwrap() {
+            SessionEvent::Created {
+                sid: created,
+                ip: created_ip,
+                endpoint,
+                ..
+            } => assert_eq!((created, created_ip, endpoint), (sid, ip, first)),
+            _ => panic!("expected Created"),
+        }
+
+        // Packets from the known address are not a roam.
+        sessions.update_sock_addr(sid, first);
+        let second: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        sessions.update_sock_addr(sid, second);
+        match events.try_recv().unwrap() {
+            SessionEvent::Roamed { from, to, .. } => assert_eq!((from, to), (first, second)),
+            _ => panic!("expected Roamed"),
+        }
+
+        sessions
+            .get_by_sid(&sid)
+            .unwrap()
+            .last_seen
+            .store(0, Ordering::Relaxed);
+        while sec_since_start() == 0 {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        sessions.cleanup_sessions(Duration::ZERO);
+        match events.try_recv().unwrap() {
+            SessionEvent::Expired { sid: expired, .. } => assert_eq!(expired, sid),
+            _ => panic!("expected Expired"),
+        }
+        assert!(events.try_recv().is_err());
+    }
+
     // ── update_sock_addr ───────────────────────────────────────────────────────
 
     #[test]
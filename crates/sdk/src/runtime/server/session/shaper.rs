@@ -0,0 +1,170 @@
+//! Per-session rate limits.
+//!
+//! Each direction of a session has a [`TokenBucket`] checked on the data path
+//! before a packet is forwarded; packets over the rate are dropped, and TCP
+//! inside the tunnel backs off as it would on a slower link. The bucket is
+//! kept in its GCRA form: instead of a token count and a refill time it stores
+//! the moment it will be full again, so a packet costs one compare-and-swap
+//! and nothing is locked or allocated.
+
+use crate::runtime::server::ClientPolicy;
+use crate::time::nanos_since_start;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Traffic a bucket lets through at once after idling: this long at the
+/// configured rate, or one packet if that is larger.
+const BURST_NANOS: u64 = 100_000_000;
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Rates of a session in bytes per second; `0` is unlimited. `upload` is
+/// client → server, `download` server → client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    pub upload: u64,
+    pub download: u64,
+}
+
+impl RateLimit {
+    /// These rates with the overrides of `policy` applied.
+    pub fn for_client(self, policy: &ClientPolicy) -> Self {
+        Self {
+            upload: policy.upload_limit.unwrap_or(self.upload),
+            download: policy.download_limit.unwrap_or(self.download),
+        }
+    }
+}
+
+/// A lock-free token bucket.
+#[derive(Debug, Default)]
+pub struct TokenBucket {
+    /// Bytes per second, `0` for unlimited
+    rate: AtomicU64,
+    /// When the bucket is full again, in nanoseconds since start
+    full_at: AtomicU64,
+}
+
+impl TokenBucket {
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Change the rate; a changed bucket starts full.
+    pub fn set_rate(&self, rate: u64) {
+        if self.rate.swap(rate, Ordering::Relaxed) != rate {
+            self.full_at.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Take `bytes` out of the bucket. `false` when the packet must be
+    /// dropped.
+    #[inline]
+    pub fn take(&self, bytes: usize) -> bool {
+        let rate = self.rate.load(Ordering::Relaxed);
+        if rate == 0 {
+            return true;
+        }
+        self.take_at(bytes, rate, nanos_since_start())
+    }
+
+    fn take_at(&self, bytes: usize, rate: u64, now: u64) -> bool {
+        let cost = (bytes as u128 * NANOS_PER_SEC / rate as u128).min(u64::MAX as u128) as u64;
+        let burst = BURST_NANOS.max(cost);
+        let mut full_at = self.full_at.load(Ordering::Relaxed);
+        loop {
+            let next = full_at.max(now).saturating_add(cost);
+            if next - now > burst {
+                return false;
+            }
+            match self.full_at.compare_exchange_weak(
+                full_at,
+                next,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => full_at = current,
+            }
+        }
+    }
+}
+
+/// The rate limits of one session.
+#[derive(Debug, Default)]
+pub struct Shaper {
+    pub upload: TokenBucket,
+    pub download: TokenBucket,
+}
+
+impl Shaper {
+    pub fn set(&self, limit: RateLimit) {
+        self.upload.set_rate(limit.upload);
+        self.download.set_rate(limit.download);
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        RateLimit {
+            upload: self.upload.rate(),
+            download: self.download.rate(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn test_bucket_holds_rate_and_burst() {
+        let bucket = TokenBucket::default();
+        assert!(bucket.take(usize::MAX), "unlimited by default");
+
+        // 1 MB/s: a 100 ms burst is 100 kB, then 1 kB per millisecond.
+        let rate = 1_000_000;
+        let now = 1_000 * MS;
+        let burst = (0..200)
+            .take_while(|_| bucket.take_at(1_000, rate, now))
+            .count();
+        assert_eq!(burst, 100);
+        assert!(!bucket.take_at(1_000, rate, now + MS / 2));
+        assert!(bucket.take_at(1_000, rate, now + MS));
+        assert!(!bucket.take_at(1_000, rate, now + MS));
+
+        // Idle time refills the bucket, but never beyond the burst.
+        let later = now + 10_000 * MS;
+        let burst = (0..200)
+            .take_while(|_| bucket.take_at(1_000, rate, later))
+            .count();
+        assert_eq!(burst, 100);
+    }
+
+    #[test]
+    fn test_oversized_packet_passes_idle_bucket() {
+        let bucket = TokenBucket::default();
+        // 10 kB/s: a 64 KiB GRO packet costs more than the burst.
+        let rate = 10_000;
+        assert!(bucket.take_at(65_536, rate, 1_000 * MS));
+        assert!(!bucket.take_at(1, rate, 1_000 * MS));
+        assert!(bucket.take_at(65_536, rate, 8_000 * MS));
+    }
+
+    #[test]
+    fn test_policy_overrides_default() {
+        let default = RateLimit {
+            upload: 1_000,
+            download: 2_000,
+        };
+        let policy = ClientPolicy {
+            download_limit: Some(0),
+            ..ClientPolicy::default()
+        };
+        assert_eq!(
+            default.for_client(&policy),
+            RateLimit {
+                upload: 1_000,
+                download: 0
+            }
+        );
+    }
+}
@@ -12,7 +12,10 @@ pub async fn run(
     loop {
         tokio::select! {
             _ = stop.changed() => break,
-            _ = timer.tick() => sessions.cleanup_sessions(timeout),
+            _ = timer.tick() => {
+                sessions.cleanup_sessions(timeout);
+                sessions.expire_leases();
+            }
         }
     }
 }
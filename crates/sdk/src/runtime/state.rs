@@ -1,33 +1,67 @@
-use std::sync::atomic::AtomicU64;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
 use snow::StatelessTransportState;
+use tokio::sync::broadcast;
 
-use crate::protocol::HandshakeResponderPayload;
+use crate::protocol::{DisconnectReason, HandshakeResponderPayload};
+use crate::runtime::client::{ClientEvent, Counters};
 use crate::runtime::error::RuntimeError;
-use crate::runtime::replay::ReplayWindow;
+use crate::runtime::keys::KeyRing;
+use crate::time::micros_since_start;
 
-/// Per-session state shared by all client tasks (network, recv, keepalive).
+/// Per-session state shared by all client tasks (network, recv, keepalive, rekey).
 ///
-/// Wrapping nonce counters in `Arc` lets the three tasks share the same
-/// counters without cloning underlying state on every watch channel read.
+/// The key ring sits behind an `Arc` so every task sees a rekey as soon as it
+/// happens, without cloning underlying state on every watch channel read.
 #[derive(Clone, Debug)]
 pub struct ClientSession {
-    pub(crate) noise: Arc<StatelessTransportState>,
-    /// Monotonically increasing counter used as the Noise nonce for outgoing packets.
-    pub(crate) send_nonce: Arc<AtomicU64>,
-    /// Anti-replay sliding window for incoming packets from the server.
-    pub(crate) recv_window: Arc<Mutex<ReplayWindow>>,
+    /// Transport keys per epoch, each with its own send nonce and replay window.
+    pub(crate) keys: Arc<KeyRing>,
+    /// When the last authenticated packet from the server arrived, in
+    /// microseconds since process start.
+    last_recv: Arc<AtomicU64>,
+    /// Client-wide counters, shared by every session of the client.
+    pub(crate) stats: Arc<Counters>,
+    /// Client-wide event stream.
+    pub(crate) events: broadcast::Sender<ClientEvent>,
 }
 
 impl ClientSession {
-    pub(crate) fn new(noise: StatelessTransportState) -> Self {
+    pub(crate) fn new(
+        noise: StatelessTransportState,
+        stats: Arc<Counters>,
+        events: broadcast::Sender<ClientEvent>,
+    ) -> Self {
         Self {
-            noise: Arc::new(noise),
-            send_nonce: Arc::new(AtomicU64::new(0)),
-            recv_window: Arc::new(Mutex::new(ReplayWindow::new())),
+            keys: Arc::new(KeyRing::new(noise)),
+            last_recv: Arc::new(AtomicU64::new(micros_since_start() as u64)),
+            stats,
+            events,
         }
     }
+
+    /// Publish `event`; without subscribers it is dropped.
+    pub(crate) fn emit(&self, event: ClientEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Count one keepalive echo that took `micros` and report it.
+    pub(crate) fn keepalive_echo(&self, micros: u64) {
+        self.stats.rtt_sample(micros);
+        self.emit(ClientEvent::RttSample(Duration::from_micros(micros)));
+    }
+
+    /// Record that a packet from the server passed decryption.
+    pub(crate) fn touch(&self) {
+        self.last_recv
+            .store(micros_since_start() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn last_recv(&self) -> u64 {
+        self.last_recv.load(Ordering::Relaxed)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -36,4 +70,11 @@ pub enum RuntimeState {
     Connected((HandshakeResponderPayload, ClientSession)),
     Error(RuntimeError),
     Listening,
+    /// The server closed the session; the connector applies the
+    /// `ReconnectPolicy` for this reason next.
+    Disconnected(DisconnectReason),
+    /// Idle on request of
+    /// [`ClientHandle::pause`](crate::runtime::client::ClientHandle::pause)
+    /// until `resume` moves it back to `Connecting`.
+    Paused,
 }
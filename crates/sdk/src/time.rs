@@ -7,6 +7,10 @@ pub fn micros_since_start() -> u128 {
     APP_START_INSTANT.elapsed().as_micros()
 }
 
+pub fn nanos_since_start() -> u64 {
+    APP_START_INSTANT.elapsed().as_nanos() as u64
+}
+
 pub fn sec_since_start() -> u64 {
     APP_START_INSTANT.elapsed().as_secs()
 }